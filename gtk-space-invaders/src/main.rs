@@ -5,49 +5,61 @@
 extern crate gtk;
 extern crate gdk;
 extern crate cairo;
-extern crate intel_8080_emu;
 extern crate space_invaders_core;
 
 use gtk::prelude::*;
 use gtk::{DrawingArea, Window, WindowType};
 use cairo::ImageSurface;
-use intel_8080_emu::proc_state::Proc8080;
-use space_invaders_core::{ SpaceInvaderDataBus, SpaceInvaderMachine, INVADERS_ROM };
+use space_invaders_core::{ run_headless, Control, EmulatorThread, FrontendOptions, StatusMessages, USAGE };
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::time::{ Instant, Duration };
+use std::time::{ Duration, Instant };
 use gdk::EventType;
 use gdk::enums::key;
 
 fn main() {
-    run_space_invader()
+    let options = FrontendOptions::from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(1);
+    });
+    if options.headless {
+        if let Err(e) = run_headless(&options) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    run_space_invader(options)
 }
 
 const PIXEL_WIDTH: i32 = 224;
 const PIXEL_HEIGHT: i32 = 256;
+/// Time an unlocked achievement stays in the title of the window
+const ACHIEVEMENT_DURATION: Duration = Duration::from_secs(5);
 
-fn run_space_invader() {
+fn run_space_invader(options: FrontendOptions) {
 
-    let machine = Rc::new(RefCell::new(SpaceInvaderMachine::new()));
-    let data_bus = SpaceInvaderDataBus::new(machine.clone());
-
-    let mut memory = Box::new([0x00; 0xffff]);
-    memory[0..INVADERS_ROM.len()].copy_from_slice(INVADERS_ROM);
-
-    let proc8080 = Rc::new(RefCell::new(Proc8080::new(memory, data_bus)));
+    let (driver, rom) = options.load_game().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let emulator = Rc::new(RefCell::new(EmulatorThread::spawn(&options, driver, &rom)));
 
     gtk::init().unwrap();
 
     let window = Window::new(WindowType::Toplevel);
-    window.set_title("Space invader");
+    window.set_title(driver.title());
 
-    let drawing_area = Rc::new(set_up_drawing_area(proc8080.clone()));
+    let drawing_area = Rc::new(set_up_drawing_area(emulator.clone()));
     drawing_area.set_size_request(PIXEL_WIDTH, PIXEL_HEIGHT);
 
-    set_proc_timeout(proc8080.clone(), drawing_area.clone());
+    set_frame_timeout(emulator.clone(), drawing_area.clone());
+    set_achievement_timeout(emulator.clone(), window.clone(), driver.title());
     window.add(drawing_area.as_ref());
 
-    window.connect_delete_event(|_, _| {
+    let closed_emulator = emulator.clone();
+    window.connect_delete_event(move |_, _| {
+        closed_emulator.borrow_mut().stop();
         gtk::main_quit();
         Inhibit(false)
     });
@@ -56,11 +68,11 @@ fn run_space_invader() {
         match event.get_event_type() {
             EventType::KeyPress => {
                 let event_key = event.clone().downcast::<gdk::EventKey>().unwrap();
-                handle_key(&machine, event_key, true);
+                handle_key(&emulator.borrow(), event_key, true);
             },
             EventType::KeyRelease => {
                 let event_key = event.clone().downcast::<gdk::EventKey>().unwrap();
-                handle_key(&machine, event_key, false);
+                handle_key(&emulator.borrow(), event_key, false);
             }, 
             _ => (),
         }
@@ -71,52 +83,51 @@ fn run_space_invader() {
     gtk::main();
 }
 
-fn handle_key(machine: &Rc<RefCell<SpaceInvaderMachine>>, event: gdk::EventKey, pressed: bool) {
-    match event.get_keyval() {
-        key::Return => machine.borrow_mut().insert_coin(pressed),
-        key::s => machine.borrow_mut().p1_start_button(pressed),
-        key::space => machine.borrow_mut().p1_fire_button(pressed),
-        key::Left => machine.borrow_mut().p1_left_button(pressed),
-        key::Right => machine.borrow_mut().p1_right_button(pressed),
-        _ => (),
-    }
+fn handle_key(emulator: &EmulatorThread, event: gdk::EventKey, pressed: bool) {
+    let control = match event.get_keyval() {
+        key::Return => Control::Coin,
+        key::s => Control::P1Start,
+        key::space => Control::Fire,
+        key::Left => Control::Left,
+        key::Right => Control::Right,
+        key::m => Control::ToggleMute,
+        key::F5 => Control::SaveState,
+        key::F9 => Control::LoadState,
+        key::F10 => Control::ToggleRecording,
+        key::F12 => Control::Screenshot,
+        digit @ key::_1..=key::_9 => Control::ToggleCheat((digit - key::_1) as usize),
+        _ => return,
+    };
+    emulator.control(control, pressed);
 }
 
-
-fn set_up_drawing_area(proc8080: Rc<RefCell<Proc8080<SpaceInvaderDataBus>>>) -> DrawingArea {
+fn set_up_drawing_area(emulator: Rc<RefCell<EmulatorThread>>) -> DrawingArea {
     
 
     let drawing_area = DrawingArea::new();
     drawing_area.connect_draw(move |canvas, cr| {
         
-        let mut surface =ImageSurface::create(cairo::Format::A8, PIXEL_WIDTH, PIXEL_HEIGHT)
+        let emulator = emulator.borrow();
+        let (width, height) = (emulator.width() as i32, emulator.height() as i32);
+        let mut surface =ImageSurface::create(cairo::Format::Rgb24, width, height)
             .expect("Could not create image surface");
+        let stride = surface.get_stride() as usize;
 
         surface.get_data().as_mut().map(|data| {
-            
-            let borrowed = proc8080.borrow();
-            let pixels: &[u8] = &borrowed.memory()[0x2400..0x4000];
-
-            // transform the pixels (reverse + rotation)
-            for (i, px_byte) in pixels.iter().enumerate() {
-                let (x_source, y_source) = memory_buffer_index_to_coordinates(i);
+            let rows = emulator.pixels().chunks(width as usize * 4);
 
-                let x_target = y_source;
-                
-                for bit in 0..8 {
-                    let y_target = PIXEL_HEIGHT - 1 - x_source - bit;
-
-                    let px = px_byte & (1 << bit) == 0 ;
-                    let index = (y_target * PIXEL_WIDTH) + x_target;
-
-                    data[index as usize] = if px { 0xff } else { 0 } ;
+            // RGBA to the native endian 0x00RRGGBB words of cairo
+            for (target, source) in data.chunks_mut(stride).zip(rows) {
+                for (target, px) in target.chunks_mut(4).zip(source.chunks(4)) {
+                    let word = (px[0] as u32) << 16 | (px[1] as u32) << 8 | px[2] as u32;
+                    target.copy_from_slice(&word.to_ne_bytes());
                 }
             }
         }).unwrap();
         let drawing_width = canvas.get_allocated_width();
         let drawing_height = canvas.get_allocated_height();
         cr.set_antialias(cairo::Antialias::None);
-        cr.scale(drawing_width as f64 / PIXEL_WIDTH as f64, drawing_height as f64 / PIXEL_HEIGHT as f64);
+        cr.scale(drawing_width as f64 / width as f64, drawing_height as f64 / height as f64);
         cr.set_source_surface(&surface, 0.0, 0.0);
         cr.paint();
         Inhibit(false)
@@ -124,39 +135,29 @@ fn set_up_drawing_area(proc8080: Rc<RefCell<Proc8080<SpaceInvaderDataBus>>>) ->
     drawing_area
 }
 
-fn memory_buffer_index_to_coordinates(index: usize) -> (i32, i32) {
-    let x = (index as i32 * 8) % PIXEL_HEIGHT;
-    let y = (index as i32 * 8) / PIXEL_HEIGHT;
-    (x, y)
-}
-
-fn set_proc_timeout(proc8080: Rc<RefCell<Proc8080<SpaceInvaderDataBus>>>, drawing_area: Rc<DrawingArea>) {
-
-    let frame_duration = Duration::from_nanos(16666667);
-    let mut last_run = Instant::now();
-    let mut last_vbl_interrupt = Instant::now();
-    let mut last_half_interrupt = Instant::now() - (frame_duration / 2);
-
+/// Redraws the screen when the emulation thread has finished a frame
+fn set_frame_timeout(emulator: Rc<RefCell<EmulatorThread>>, drawing_area: Rc<DrawingArea>) {
     timeout_add(4, move || {
-        let now = Instant::now();
-
-        if now.duration_since(last_vbl_interrupt) > frame_duration {
-            proc8080.borrow_mut().interrupt(1);
-            last_vbl_interrupt = now;
+        if emulator.borrow_mut().update_frame() {
             drawing_area.queue_draw();
-        } else if now.duration_since(last_half_interrupt) > frame_duration {
-            proc8080.borrow_mut().interrupt(2);
-            last_half_interrupt = now;
         }
+        gtk::Continue(true)
+    });
+}
 
-        let cycles = now.duration_since(last_run).subsec_nanos() as u64 / 500 ;
-        let current_cycles = proc8080.borrow().cycles();
-        while proc8080.borrow().cycles() < (current_cycles + cycles) {
-            proc8080.borrow_mut().emulate();
+/// Shows the achievements unlocked in the title of the window, one after another for a few seconds
+fn set_achievement_timeout(emulator: Rc<RefCell<EmulatorThread>>, window: Window, title: &'static str) {
+    let mut status = StatusMessages::new(ACHIEVEMENT_DURATION);
+    timeout_add(100, move || {
+        for achievement in emulator.borrow().unlocked_achievements() {
+            status.push(format!("achievement unlocked: {}", achievement.name));
+        }
+        if status.update(Instant::now()) {
+            match status.shown() {
+                Some(message) => window.set_title(&format!("{} - {}", title, message)),
+                None => window.set_title(title),
+            }
         }
-
-        last_run = now;
-
         gtk::Continue(true)
     });
-}
\ No newline at end of file
+}
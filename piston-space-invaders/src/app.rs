@@ -2,58 +2,46 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::time::{ Instant, Duration };
-
 use piston::input::*;
 use piston_window::texture::Filter;
 use opengl_graphics::{ GlGraphics, OpenGL };
 use opengl_graphics::Texture;
 use piston_window::texture::TextureSettings;
+use std::time::{ Duration, Instant };
 
-use space_invaders_core::{ SpaceInvaderDataBus, SpaceInvaderMachine, INVADERS_ROM };
-use intel_8080_emu::proc_state::Proc8080;
+use space_invaders_core::{ BoardDriver, Control, EmulatorThread, FrontendOptions, StatusMessages };
 
-use super::{ PIXEL_WIDTH, PIXEL_HEIGHT };
+/// Time an unlocked achievement stays in the title of the window
+const ACHIEVEMENT_DURATION: Duration = Duration::from_secs(5);
 
 pub struct App {
     gl: GlGraphics,
     screen: Texture,
-    cpu: Rc<RefCell<Proc8080<SpaceInvaderDataBus>>>,
-    machine: Rc<RefCell<SpaceInvaderMachine>>,
-    last_vbl_interrupt: Instant,
-    last_half_interrupt: Instant,
-    last_cpu_run: Instant,
+    emulator: EmulatorThread,
+    title: &'static str,
+    /// Achievements unlocked, shown in the title one after another
+    status: StatusMessages,
 }
 
 impl App {
 
-    pub fn new(opengl: OpenGL) -> App {
-
-        let machine = Rc::new(RefCell::new(SpaceInvaderMachine::new()));
-        let data_bus = SpaceInvaderDataBus::new(machine.clone());
-        
-        let mut memory = Box::new([0x00; 0xffff]);
-        memory[0..INVADERS_ROM.len()].copy_from_slice(INVADERS_ROM);
-        
-        let proc8080 = Rc::new(RefCell::new(Proc8080::new(memory, data_bus)));
+    /// Builds the app running `driver` with its ROM image `rom` (see `FrontendOptions::load_game`).
+    pub fn new(opengl: OpenGL, options: &FrontendOptions, driver: &'static dyn BoardDriver, rom: &[u8]) -> App {
 
-        let now = Instant::now();
+        let emulator = EmulatorThread::spawn(options, driver, rom);
+        let (width, height) = (emulator.width() as u32, emulator.height() as u32);
         let texture_settings = TextureSettings::new().filter(Filter::Nearest);
         let screen = Texture::from_memory_alpha(
-            &[128u8; (PIXEL_WIDTH * PIXEL_HEIGHT) as usize],
-            PIXEL_WIDTH, 
-            PIXEL_HEIGHT, 
+            &vec![128u8; (width * height) as usize],
+            width,
+            height,
             &texture_settings).unwrap();
         App {
             gl: GlGraphics::new(opengl),
             screen,
-            cpu: proc8080,
-            machine,
-            last_vbl_interrupt: now,
-            last_half_interrupt: now,
-            last_cpu_run: now,
+            emulator,
+            title: driver.title(),
+            status: StatusMessages::new(ACHIEVEMENT_DURATION),
         }
     }
 
@@ -61,12 +49,13 @@ impl App {
         use graphics::*;
 
         let screen = &self.screen;
+        let (width, height) = screen.get_size();
         self.gl.draw(args.viewport(), |c, gl| {
 
-            let scale_width = args.draw_width as f64 / PIXEL_WIDTH as f64;
-            let scale_height = args.draw_height as f64 / PIXEL_HEIGHT as f64;
+            let scale_width = args.draw_width as f64 / width as f64;
+            let scale_height = args.draw_height as f64 / height as f64;
             let actual_scale = scale_width.min(scale_height);
-            let actual_width = PIXEL_WIDTH as f64 * actual_scale;
+            let actual_width = width as f64 * actual_scale;
             let transform = c.transform
                 .trans((args.draw_width as f64 - actual_width) / 2.0, 0.0)
                 .scale(actual_scale, actual_scale);
@@ -74,91 +63,59 @@ impl App {
         });
     }
 
-    const FRAME_DURATION: Duration = Duration::from_nanos(16666667);
-
-    pub fn run_processor(&mut self) {
-        let now = Instant::now();
-
-        if now.duration_since(self.last_vbl_interrupt) > Self::FRAME_DURATION {
-            self.cpu.borrow_mut().interrupt(1);
-            self.last_vbl_interrupt = now;
-        } else if now.duration_since(self.last_half_interrupt) > Self::FRAME_DURATION {
-            self.cpu.borrow_mut().interrupt(2);
-            self.last_half_interrupt = now;
+    /// New title of the window: the achievements unlocked one after another, then the title of
+    /// the game. `None` if it does not change.
+    pub fn update_title(&mut self) -> Option<String> {
+        for achievement in self.emulator.unlocked_achievements() {
+            self.status.push(format!("achievement unlocked: {}", achievement.name));
         }
-
-        let cycles = now.duration_since(self.last_cpu_run).subsec_nanos() as u64 / 500 ;
-        let current_cycles = self.cpu.borrow().cycles();
-        while self.cpu.borrow().cycles() < (current_cycles + cycles) {
-            self.cpu.borrow_mut().emulate();
+        if !self.status.update(Instant::now()) {
+            return None;
+        }
+        match self.status.shown() {
+            Some(message) => Some(format!("{} - {}", self.title, message)),
+            None => Some(self.title.to_string()),
         }
+    }
 
-        self.last_cpu_run = now;
+    /// Copies the last frame of the emulation thread in the texture, if there is a new one.
+    pub fn update_screen(&mut self) {
+        use image::{ ImageBuffer, Rgba };
+
+        if !self.emulator.update_frame() {
+            return;
+        }
+        let buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_raw(
+            self.emulator.width() as u32, self.emulator.height() as u32, self.emulator.pixels().to_vec()).unwrap();
 
-        self.copy_screen();
+        self.screen.update(&buffer)
     }
 
     pub fn handle_input(&mut self, args: ButtonArgs) {
         use piston::input::Button::Keyboard;
 
-        let pressed = args.state == ButtonState::Press;
-        match args.button {
-            Keyboard(Key::Return) => self.machine.borrow_mut().insert_coin(pressed),
-            Keyboard(Key::LCtrl) => self.machine.borrow_mut().p1_start_button(pressed),
-            Keyboard(Key::RCtrl) => self.machine.borrow_mut().p2_start_button(pressed),
-            Keyboard(Key::Space) => {
-                self.machine.borrow_mut().p1_fire_button(pressed);
-                self.machine.borrow_mut().p2_fire_button(pressed);
-            },
-            Keyboard(Key::Left) => {
-                self.machine.borrow_mut().p1_left_button(pressed);
-                self.machine.borrow_mut().p2_left_button(pressed);
-            },
-            Keyboard(Key::Right) => {
-                self.machine.borrow_mut().p1_right_button(pressed);
-                self.machine.borrow_mut().p2_right_button(pressed);
+        let control = match args.button {
+            Keyboard(Key::Return) => Control::Coin,
+            Keyboard(Key::LCtrl) => Control::P1Start,
+            Keyboard(Key::RCtrl) => Control::P2Start,
+            Keyboard(Key::Space) => Control::Fire,
+            Keyboard(Key::Left) => Control::Left,
+            Keyboard(Key::Right) => Control::Right,
+            Keyboard(Key::M) => Control::ToggleMute,
+            Keyboard(Key::F5) => Control::SaveState,
+            Keyboard(Key::F9) => Control::LoadState,
+            Keyboard(Key::F10) => Control::ToggleRecording,
+            Keyboard(Key::F12) => Control::Screenshot,
+            Keyboard(digit @ (Key::D1 | Key::D2 | Key::D3 | Key::D4 | Key::D5 | Key::D6 | Key::D7 | Key::D8 | Key::D9)) => {
+                Control::ToggleCheat(digit as usize - Key::D1 as usize)
             },
-            _ => (),
-        }
+            _ => return,
+        };
+        self.emulator.control(control, args.state == ButtonState::Press);
     }
 
-    fn copy_screen(&mut self) {
-        use image::{ ImageBuffer, Rgba };
-
-        let borrowed = self.cpu.borrow();
-        let pixels: &[u8] = &borrowed.memory()[0x2400..0x4000];
-
-        let mut buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(PIXEL_WIDTH, PIXEL_HEIGHT);
-
-        // transform the pixels (reverse + rotation)
-        for (i, px_byte) in pixels.iter().enumerate() {
-            let (x_source, y_source) = memory_buffer_index_to_coordinates(i);
-
-            let x_target = y_source;
-            
-            for bit in 0..8 {
-                let y_target = PIXEL_HEIGHT - 1 - x_source - bit;
-
-                let px = px_byte & (1 << bit) == 0 ;
-                
-                const WHITE: [u8; 4] = [ 255, 255, 255 , 255];
-                const BLACK: [u8; 4] = [ 0, 0, 0 , 255];
-
-                buffer[(x_target, y_target)] = 
-                if px { 
-                    Rgba { data: BLACK }
-                } else { 
-                    Rgba { data: WHITE } 
-                } ;
-            }
-        }
-
-        self.screen.update(&buffer)
+    /// Stops the emulation and finishes the recording, if any
+    pub fn close(&mut self) {
+        self.emulator.stop();
     }
 }
-
-fn memory_buffer_index_to_coordinates(index: usize) -> (u32, u32) {
-    let x = (index as u32 * 8) % PIXEL_HEIGHT;
-    let y = (index as u32 * 8) / PIXEL_HEIGHT;
-    (x, y)
-}
@@ -9,7 +9,6 @@ extern crate glutin_window;
 extern crate opengl_graphics;
 extern crate image;
 
-extern crate intel_8080_emu;
 extern crate space_invaders_core;
 
 mod app;
@@ -20,6 +19,7 @@ use piston::event_loop::*;
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{ OpenGL };
 use piston::input::{ Event, Loop, Input };
+use space_invaders_core::{ run_headless, FrontendOptions, USAGE };
 
 use app::App;
 
@@ -27,12 +27,29 @@ const PIXEL_WIDTH: u32 = 224;
 const PIXEL_HEIGHT: u32 = 256;
 
 fn main() {
+    let options = FrontendOptions::from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(1);
+    });
+    if options.headless {
+        if let Err(e) = run_headless(&options) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (driver, rom) = options.load_game().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
     // Change this to OpenGL::V2_1 if not working.
     let opengl = OpenGL::V3_2;
 
     // Create an Glutin window.
     let mut window: Window = WindowSettings::new(
-            "space-invaders",
+            driver.title(),
             [PIXEL_WIDTH*2, PIXEL_HEIGHT*2]
         )
         .opengl(opengl)
@@ -45,15 +62,21 @@ fn main() {
     window.set_capture_cursor(true);
 
     // Create a new game and run it.
-    let mut app = App::new(opengl);
+    let mut app = App::new(opengl, &options, driver, &rom);
 
     let mut events = Events::new(EventSettings::new());
     while let Some(e) = events.next(&mut window) {
         match e {
             Event::Loop(Loop::Render(args)) => app.render(&args),
-            Event::Loop(Loop::Idle(_)) => app.run_processor(),
+            Event::Loop(Loop::Idle(_)) => {
+                app.update_screen();
+                if let Some(title) = app.update_title() {
+                    window.set_title(title);
+                }
+            },
             Event::Input(Input::Button(args)) => app.handle_input(args),
             _ => (),
         }
     }
+    app.close();
 }
@@ -0,0 +1,86 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Emulated frames per second without display, `cargo bench`.
+//!
+//! `shared bus` is the data bus before it owned the machine: the machine behind a `RefCell`,
+//! borrowed on each IN and OUT and on each button. `owned bus` is the current one, with the buttons
+//! pressed through the `InputLatch`.
+
+extern crate intel_8080_emu;
+extern crate space_invaders_core;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+use intel_8080_emu::proc_state::DataBus;
+use space_invaders_core::{
+    Cpu8080, Input, MemoryMap, SpaceInvaderDataBus, SpaceInvaderMachine, INVADERS, INVADERS_ROM,
+};
+
+const FRAMES: u32 = 10_000;
+/// The best of the rounds is kept, the rounds of both buses alternate
+const ROUNDS: u32 = 5;
+const HALF_FRAME_CYCLES: u64 = 2_000_000 / 120;
+
+struct SharedBus(Rc<RefCell<SpaceInvaderDataBus>>);
+
+impl DataBus for SharedBus {
+    fn read_port(&self, port: u8) -> u8 {
+        self.0.borrow_mut().read_port(port)
+    }
+
+    fn write_port(&mut self, port: u8, value: u8) {
+        self.0.borrow_mut().write_port(port, value)
+    }
+}
+
+/// Runs `FRAMES` frames, inserting a coin from time to time so that the game is played, and
+/// returns the number of frames per second.
+fn frames_per_second<B: DataBus, F: FnMut(Input, bool)>(mut cpu: Cpu8080<B>, mut set_input: F) -> f64 {
+    let start = Instant::now();
+    let mut next_interrupt = HALF_FRAME_CYCLES;
+    for frame in 0..FRAMES {
+        for &interrupt in &[1, 2] {
+            while cpu.cycles() < next_interrupt {
+                cpu.emulate();
+            }
+            cpu.interrupt(interrupt);
+            next_interrupt += HALF_FRAME_CYCLES;
+        }
+        set_input(Input::Coin, frame % 600 < 5);
+        set_input(Input::P1Start, frame % 600 == 60);
+        set_input(Input::P1Fire, frame % 30 < 15);
+        set_input(Input::P1Left, frame % 120 < 60);
+    }
+    FRAMES as f64 / start.elapsed().as_secs_f64()
+}
+
+fn memory() -> MemoryMap {
+    MemoryMap::new(&INVADERS, INVADERS_ROM)
+}
+
+fn shared_bus() -> f64 {
+    let bus = Rc::new(RefCell::new(SpaceInvaderDataBus::new(SpaceInvaderMachine::new())));
+    let cpu = Cpu8080::new(memory(), SharedBus(bus.clone()));
+    frames_per_second(cpu, |input, pressed| bus.borrow_mut().machine_mut().set_input(input, pressed))
+}
+
+fn owned_bus() -> f64 {
+    let machine = SpaceInvaderMachine::new();
+    let inputs = machine.input_latch();
+    let cpu = Cpu8080::new(memory(), SpaceInvaderDataBus::new(machine));
+    frames_per_second(cpu, |input, pressed| inputs.set_input(input, pressed))
+}
+
+fn main() {
+    let (mut shared, mut owned) = (0f64, 0f64);
+    for _ in 0..ROUNDS {
+        shared = shared.max(shared_bus());
+        owned = owned.max(owned_bus());
+    }
+    println!("shared bus: {:>8.0} frames/s", shared);
+    println!("owned bus:  {:>8.0} frames/s ({:+.1}%)", owned, (owned / shared - 1.0) * 100.0);
+}
@@ -0,0 +1,558 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{ self, OpenOptions };
+use std::io::{ self, Write };
+use std::iter::Peekable;
+use std::path::{ Path, PathBuf };
+use std::str::SplitWhitespace;
+
+use cheat::{ parse_comparison, parse_number, Comparison };
+use memory::MemoryMap;
+use record::timestamp;
+
+/*
+ Achievement files are text files, one condition per line, grouped in named achievements. `#`
+ starts a comment, the numbers are decimal or hexadecimal (`0x`):
+
+    achievement UFO hunter
+    description Shoot the UFO 5 times in one game
+    require byte 0x2085 > delta byte 0x2085 hits 5
+    require byte 0x20ef == 1
+    reset byte 0x20ef == 0
+
+ `require <operand> <comparison> <operand> [hits <count>]` must hold at the frame of the unlock,
+ or, with `hits`, must have held at `count` frames (not necessarily in a row) since the start.
+ `reset <operand> <comparison> <operand>` clears the hits of the achievement while it holds.
+ The comparisons are `==`, `!=`, `<`, `>`, `<=` and `>=`, the operands are numbers or values read
+ in memory:
+
+    byte <address>                   the byte at the address
+    word <address>                   the 16 bits little-endian word at the address
+    bcd <address>                    the same word, read as 4 BCD digits (like the scores)
+    bits <address>                   the number of bits set in the byte at the address
+    <size> <first>..<last> [step <n>]  the sum of the values at first, first + n... up to last
+    delta <size> <address>...        the value read at the previous frame
+
+ The conditions are checked once per frame, an achievement unlocks when all its `require` hold.
+ So that loading a state does not unlock anything, it must not hold at the first frame checked:
+ an achievement whose conditions already hold then unlocks only once they have stopped holding,
+ and hold again.
+*/
+
+/// Achievements of space invaders: the UFO hunter, the shield keeper and 5000 points
+pub const INVADERS_ACHIEVEMENTS: &str = include_str!("../resources/invaders.achievements");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueSize {
+    Byte,
+    /// Little-endian 16 bits word
+    Word,
+    /// Little-endian word of 4 BCD digits
+    Bcd,
+    /// Number of bits set in a byte
+    Bits,
+}
+
+/// Value read in memory: the one at `address`, or the sum of those at `address`,
+/// `address + step`... up to `last`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryValue {
+    pub size: ValueSize,
+    pub address: u16,
+    pub last: u16,
+    pub step: u16,
+}
+
+impl MemoryValue {
+    /// Value of `size` at `address`
+    pub fn new(size: ValueSize, address: u16) -> MemoryValue {
+        MemoryValue { size, address, last: address, step: 1 }
+    }
+
+    /// Addresses of the summed values
+    fn addresses(&self) -> impl Iterator<Item = u16> {
+        (self.address..=self.last).step_by(self.step as usize)
+    }
+
+    /// Addresses of all the bytes read
+    fn bytes(&self) -> impl Iterator<Item = u16> {
+        let wide = matches!(self.size, ValueSize::Word | ValueSize::Bcd);
+        self.addresses().flat_map(move |address| {
+            let next = if wide { Some(address.wrapping_add(1)) } else { None };
+            Some(address).into_iter().chain(next)
+        })
+    }
+
+    fn read(&self, byte: &dyn Fn(u16) -> u8) -> u32 {
+        self.addresses().fold(0u32, |sum, address| {
+            let word = || u32::from(byte(address)) | u32::from(byte(address.wrapping_add(1))) << 8;
+            let value = match self.size {
+                ValueSize::Byte => u32::from(byte(address)),
+                ValueSize::Word => word(),
+                ValueSize::Bcd => {
+                    let word = word();
+                    (0..4).rev().fold(0, |value, digit| value * 10 + ((word >> (digit * 4)) & 0xf))
+                },
+                ValueSize::Bits => byte(address).count_ones(),
+            };
+            sum.saturating_add(value)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Constant(u32),
+    Memory(MemoryValue),
+    /// Value at the previous frame
+    Delta(MemoryValue),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConditionKind {
+    /// Must hold for the unlock, at `hits` frames if there are some
+    Require { hits: Option<u32> },
+    /// Clears the hits while it holds
+    Reset,
+}
+
+/// Condition of an achievement: compares `left` to `right`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AchievementCondition {
+    pub kind: ConditionKind,
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Achievement {
+    pub name: String,
+    pub description: String,
+    pub conditions: Vec<AchievementCondition>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AchievementError {
+    /// A line of an achievement file is not understood (the lines are counted from 1)
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for AchievementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AchievementError::Syntax { line, ref message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for AchievementError {}
+
+/// Progress of an achievement
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Progress {
+    /// Frames at which each condition held, since the start or the last reset
+    hits: Vec<u32>,
+    /// Whether the conditions did not all hold at some frame, see the format above
+    armed: bool,
+    unlocked: bool,
+}
+
+/// Achievements of a game, whose conditions are checked at every frame.
+///
+/// The progress (the hits and the values of the previous frame) starts again with `restart`, when
+/// a state is loaded. The unlocked achievements stay unlocked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AchievementEngine {
+    achievements: Vec<Achievement>,
+    progress: Vec<Progress>,
+    /// Bytes read by the `delta` operands, with their value at the previous frame, by address
+    previous: Vec<(u16, u8)>,
+    /// Whether `previous` was read, the deltas are the current values at the first frame
+    started: bool,
+}
+
+impl AchievementEngine {
+    /// Engine with all the `achievements` locked.
+    pub fn new(achievements: Vec<Achievement>) -> AchievementEngine {
+        let mut previous: Vec<(u16, u8)> = achievements.iter()
+            .flat_map(|achievement| &achievement.conditions)
+            .flat_map(|condition| vec![condition.left, condition.right])
+            .filter_map(|operand| match operand {
+                Operand::Delta(value) => Some(value),
+                _ => None,
+            })
+            .flat_map(|value| value.bytes().collect::<Vec<u16>>())
+            .map(|address| (address, 0))
+            .collect();
+        previous.sort_unstable();
+        previous.dedup();
+        let progress = achievements.iter().map(|achievement| Progress {
+            hits: vec![0; achievement.conditions.len()],
+            armed: false,
+            unlocked: false,
+        }).collect();
+        AchievementEngine { achievements, progress, previous, started: false }
+    }
+
+    pub fn achievements(&self) -> &[Achievement] {
+        &self.achievements
+    }
+
+    pub fn is_unlocked(&self, index: usize) -> bool {
+        self.progress.get(index).is_some_and(|progress| progress.unlocked)
+    }
+
+    /// Unlocks the achievement `name` without checking it (e.g. one unlocked in an earlier
+    /// session). Returns false if there is no such achievement.
+    pub fn mark_unlocked(&mut self, name: &str) -> bool {
+        match self.achievements.iter().position(|achievement| achievement.name == name) {
+            Some(index) => {
+                self.progress[index].unlocked = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Forgets the progress of the locked achievements.
+    pub fn restart(&mut self) {
+        for progress in &mut self.progress {
+            progress.hits.iter_mut().for_each(|hits| *hits = 0);
+            progress.armed = false;
+        }
+        self.started = false;
+    }
+
+    /// Checks the conditions on `memory`, to be called once per frame. Returns the indices of the
+    /// achievements unlocked at this frame.
+    pub fn update(&mut self, memory: &MemoryMap) -> Vec<usize> {
+        let AchievementEngine { ref achievements, ref mut progress, ref mut previous, ref mut started } = *self;
+        let current = |address: u16| memory.peek(address);
+        let delta = |address: u16| match previous.binary_search_by_key(&address, |&(byte, _)| byte) {
+            Ok(index) if *started => previous[index].1,
+            _ => memory.peek(address),
+        };
+        let holds = |condition: &AchievementCondition| {
+            let value = |operand: Operand| match operand {
+                Operand::Constant(value) => value,
+                Operand::Memory(value) => value.read(&current),
+                Operand::Delta(value) => value.read(&delta),
+            };
+            condition.comparison.holds(value(condition.left), value(condition.right))
+        };
+
+        let mut unlocked = Vec::new();
+        for (index, (achievement, progress)) in achievements.iter().zip(progress.iter_mut()).enumerate() {
+            if progress.unlocked {
+                continue;
+            }
+            if achievement.conditions.iter().any(|condition| condition.kind == ConditionKind::Reset && holds(condition)) {
+                progress.hits.iter_mut().for_each(|hits| *hits = 0);
+                progress.armed = true;
+                continue;
+            }
+            let mut satisfied = true;
+            for (condition, hits) in achievement.conditions.iter().zip(progress.hits.iter_mut()) {
+                match condition.kind {
+                    ConditionKind::Require { hits: Some(target) } => {
+                        if *hits < target && holds(condition) {
+                            *hits += 1;
+                        }
+                        satisfied &= *hits >= target;
+                    },
+                    ConditionKind::Require { hits: None } => satisfied &= holds(condition),
+                    ConditionKind::Reset => (),
+                }
+            }
+            if !satisfied {
+                progress.armed = true;
+            } else if progress.armed {
+                progress.unlocked = true;
+                unlocked.push(index);
+            }
+        }
+
+        for &mut (address, ref mut value) in previous.iter_mut() {
+            *value = memory.peek(address);
+        }
+        *started = true;
+        unlocked
+    }
+}
+
+/// Reads an achievement file (see the format above).
+pub fn parse_achievements(text: &str) -> Result<Vec<Achievement>, AchievementError> {
+    let mut achievements: Vec<Achievement> = Vec::new();
+    // line of each achievement, for its errors
+    let mut lines = Vec::new();
+    for (number, line) in (1..).zip(text.lines()) {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let syntax_error = |message: &str| AchievementError::Syntax { line: number, message: message.to_string() };
+        let mut words = line.split_whitespace().peekable();
+        let keyword = match words.next() {
+            None => continue,
+            Some("achievement") => {
+                let name = line["achievement".len()..].trim();
+                if name.is_empty() {
+                    return Err(syntax_error("the achievement has no name"));
+                }
+                achievements.push(Achievement { name: name.to_string(), description: String::new(), conditions: Vec::new() });
+                lines.push(number);
+                continue;
+            },
+            Some(keyword) => keyword,
+        };
+        let achievement = achievements.last_mut().ok_or_else(|| syntax_error("condition outside of an achievement"))?;
+        let kind = match keyword {
+            "description" => {
+                achievement.description = line["description".len()..].trim().to_string();
+                continue;
+            },
+            "require" => ConditionKind::Require { hits: None },
+            "reset" => ConditionKind::Reset,
+            _ => return Err(syntax_error(&format!("unknown condition {}", keyword))),
+        };
+        let left = parse_operand(&mut words).ok_or_else(|| syntax_error("invalid operand"))?;
+        let comparison = words.next().and_then(parse_comparison).ok_or_else(|| syntax_error("invalid comparison"))?;
+        let right = parse_operand(&mut words).ok_or_else(|| syntax_error("invalid operand"))?;
+        let kind = match (kind, words.next(), words.next()) {
+            (kind, None, _) => kind,
+            (ConditionKind::Require { .. }, Some("hits"), Some(hits)) => {
+                let hits = parse_number(hits).filter(|&hits| hits > 0).ok_or_else(|| syntax_error("invalid hit count"))?;
+                ConditionKind::Require { hits: Some(hits) }
+            },
+            (ConditionKind::Require { .. }, _, _) => {
+                return Err(syntax_error("expected require <operand> <comparison> <operand> [hits <count>]"));
+            },
+            (ConditionKind::Reset, _, _) => return Err(syntax_error("expected reset <operand> <comparison> <operand>")),
+        };
+        if words.next().is_some() {
+            return Err(syntax_error("unexpected text after the condition"));
+        }
+        achievement.conditions.push(AchievementCondition { kind, left, comparison, right });
+    }
+    for (achievement, &line) in achievements.iter().zip(&lines) {
+        if !achievement.conditions.iter().any(|condition| condition.kind != ConditionKind::Reset) {
+            return Err(AchievementError::Syntax { line, message: format!("{} has no require condition", achievement.name) });
+        }
+    }
+    Ok(achievements)
+}
+
+fn parse_operand(words: &mut Peekable<SplitWhitespace>) -> Option<Operand> {
+    let word = words.next()?;
+    let (delta, word) = match word {
+        "delta" => (true, words.next()?),
+        _ => (false, word),
+    };
+    let size = match word {
+        "byte" => ValueSize::Byte,
+        "word" => ValueSize::Word,
+        "bcd" => ValueSize::Bcd,
+        "bits" => ValueSize::Bits,
+        _ if !delta => return parse_number(word).map(Operand::Constant),
+        _ => return None,
+    };
+    let addresses = words.next()?;
+    let mut value = match addresses.split_once("..") {
+        Some((first, last)) => MemoryValue { size, address: parse_number(first)?, last: parse_number(last)?, step: 1 },
+        None => MemoryValue::new(size, parse_number(addresses)?),
+    };
+    if words.peek() == Some(&"step") {
+        words.next();
+        value.step = words.next().and_then(parse_number).filter(|&step| step > 0)?;
+    }
+    if value.last < value.address {
+        return None;
+    }
+    Some(if delta { Operand::Delta(value) } else { Operand::Memory(value) })
+}
+
+/// Text file where the unlocks are appended, one `<timestamp> <game> <name>` line per achievement
+/// (see `record::timestamp`), so that they stay unlocked from one session to the next.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AchievementLog {
+    path: PathBuf,
+    /// Short name of the game (see `BoardDriver::name`)
+    game: String,
+}
+
+impl AchievementLog {
+    pub fn new(path: &Path, game: &str) -> AchievementLog {
+        AchievementLog { path: path.to_path_buf(), game: game.to_string() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Names of the achievements of the game unlocked so far, none if the file does not exist yet.
+    pub fn read(&self) -> io::Result<Vec<String>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(text.lines().filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let (_, game, name) = (fields.next()?, fields.next()?, fields.next()?);
+            if game == self.game { Some(name.to_string()) } else { None }
+        }).collect())
+    }
+
+    /// Adds the unlock of the achievement `name`, now.
+    pub fn append(&self, name: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{} {} {}", timestamp(), self.game, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use achievement::*;
+    use board::INVADERS;
+    use cheat::Comparison;
+    use memory::MemoryMap;
+
+    fn parse(text: &str) -> AchievementEngine {
+        AchievementEngine::new(parse_achievements(text).unwrap())
+    }
+
+    #[test]
+    fn parse_achievement_file() {
+        let achievements = parse_achievements("\
+            # comment\n\
+            achievement Score\n\
+            description Reach 5000 points\n\
+            require bcd 0x20f8 >= 5000  # player 1\n\
+            require delta byte 0x2085 < byte 0x2085 hits 0x05\n\
+            reset bits 0x2406..0x2446 step 32 != 3\n").unwrap();
+        assert_eq!(vec![Achievement {
+            name: "Score".to_string(),
+            description: "Reach 5000 points".to_string(),
+            conditions: vec![
+                AchievementCondition {
+                    kind: ConditionKind::Require { hits: None },
+                    left: Operand::Memory(MemoryValue::new(ValueSize::Bcd, 0x20f8)),
+                    comparison: Comparison::GreaterOrEqual,
+                    right: Operand::Constant(5000),
+                },
+                AchievementCondition {
+                    kind: ConditionKind::Require { hits: Some(5) },
+                    left: Operand::Delta(MemoryValue::new(ValueSize::Byte, 0x2085)),
+                    comparison: Comparison::Less,
+                    right: Operand::Memory(MemoryValue::new(ValueSize::Byte, 0x2085)),
+                },
+                AchievementCondition {
+                    kind: ConditionKind::Reset,
+                    left: Operand::Memory(MemoryValue { size: ValueSize::Bits, address: 0x2406, last: 0x2446, step: 32 }),
+                    comparison: Comparison::NotEqual,
+                    right: Operand::Constant(3),
+                },
+            ],
+        }], achievements);
+        assert_eq!(3, parse_achievements(INVADERS_ACHIEVEMENTS).unwrap().len());
+    }
+
+    #[test]
+    fn reject_invalid_achievement_files() {
+        let error = |text: &str| parse_achievements(text).unwrap_err().to_string();
+        assert_eq!("line 1: condition outside of an achievement", error("require byte 0x2000 == 1"));
+        assert_eq!("line 2: invalid operand", error("achievement a\nrequire word 0x10000 == 1"));
+        assert_eq!("line 2: invalid operand", error("achievement a\nrequire delta 1 == 1"));
+        assert_eq!("line 2: invalid comparison", error("achievement a\nrequire byte 0x2000 = 1"));
+        assert_eq!("line 2: invalid hit count", error("achievement a\nrequire byte 0x2000 == 1 hits 0"));
+        assert_eq!("line 2: unknown condition pause", error("achievement a\npause byte 0x2000 == 1"));
+        assert_eq!("line 1: a has no require condition", error("achievement a\nreset byte 0x2000 == 1"));
+        assert!(parse_achievements("achievement a\nreset byte 0x2000 == 1 hits 2").is_err());
+        assert!(parse_achievements("achievement a\nrequire bits 0x2010..0x2000 == 1").is_err());
+        assert!(parse_achievements("achievement").is_err());
+    }
+
+    #[test]
+    fn memory_values_are_summed() {
+        let mut memory = MemoryMap::new(&INVADERS, &[]);
+        memory.write(0x2000, 0x34);
+        memory.write(0x2001, 0x12);
+        memory.write(0x2020, 0xff);
+        let read = |size, address, last, step| MemoryValue { size, address, last, step }.read(&|address| memory.peek(address));
+        assert_eq!(0x34, read(ValueSize::Byte, 0x2000, 0x2000, 1));
+        assert_eq!(0x1234, read(ValueSize::Word, 0x2000, 0x2000, 1));
+        assert_eq!(1234, read(ValueSize::Bcd, 0x2000, 0x2000, 1));
+        assert_eq!(3 + 2 + 8, read(ValueSize::Bits, 0x2000, 0x2020, 1));
+        assert_eq!(3 + 8, read(ValueSize::Bits, 0x2000, 0x2020, 0x20));
+    }
+
+    #[test]
+    fn hits_are_counted_until_a_reset() {
+        let mut engine = parse("\
+            achievement Hunter\n\
+            require byte 0x2000 > delta byte 0x2000 hits 2\n\
+            reset byte 0x2001 == 0\n");
+        let mut memory = MemoryMap::new(&INVADERS, &[]);
+        memory.write(0x2001, 1);
+        let hit = |memory: &mut MemoryMap, engine: &mut AchievementEngine| {
+            let value = memory.peek(0x2000);
+            memory.write(0x2000, value + 1);
+            let unlocked = engine.update(memory);
+            memory.write(0x2000, value);
+            assert!(engine.update(memory).is_empty());
+            unlocked
+        };
+        assert!(engine.update(&memory).is_empty());
+        assert!(hit(&mut memory, &mut engine).is_empty());
+        // the hits are lost
+        memory.write(0x2001, 0);
+        assert!(engine.update(&memory).is_empty());
+        memory.write(0x2001, 1);
+        assert!(hit(&mut memory, &mut engine).is_empty());
+        assert_eq!(vec![0], hit(&mut memory, &mut engine));
+        assert!(engine.is_unlocked(0));
+        // only once
+        assert!(hit(&mut memory, &mut engine).is_empty());
+    }
+
+    #[test]
+    fn achievements_holding_at_the_first_frame_are_not_unlocked() {
+        let mut engine = parse("achievement Score\nrequire bcd 0x20f8 >= 5000");
+        let mut memory = MemoryMap::new(&INVADERS, &[]);
+        memory.write(0x20f9, 0x50);
+        assert!(engine.update(&memory).is_empty());
+        assert!(engine.update(&memory).is_empty());
+        memory.write(0x20f9, 0x49);
+        assert!(engine.update(&memory).is_empty());
+        memory.write(0x20f9, 0x50);
+        assert_eq!(vec![0], engine.update(&memory));
+
+        let mut engine = parse("achievement Score\nrequire bcd 0x20f8 >= 5000");
+        memory.write(0x20f9, 0x49);
+        assert!(engine.update(&memory).is_empty());
+        // as if a state was loaded
+        engine.restart();
+        memory.write(0x20f9, 0x50);
+        assert!(engine.update(&memory).is_empty());
+        assert!(engine.mark_unlocked("Score"));
+        assert!(engine.is_unlocked(0));
+        assert!(!engine.mark_unlocked("Missing"));
+    }
+
+    #[test]
+    fn unlocks_are_logged_by_game() {
+        let path = env::temp_dir().join(format!("space-invaders-achievements-{}.log", process::id()));
+        let log = AchievementLog::new(&path, "invaders");
+        assert!(log.read().unwrap().is_empty());
+        log.append("UFO hunter").unwrap();
+        AchievementLog::new(&path, "lrescue").append("Rescue").unwrap();
+        log.append("5000 points").unwrap();
+        assert_eq!(vec!["UFO hunter".to_string(), "5000 points".to_string()], log.read().unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fs::File;
+use std::io::{ self, BufWriter, Seek, SeekFrom, Write };
+use std::path::Path;
+use std::time::Duration;
+
+use host::AudioSink;
+use machine::CPU_FREQUENCY;
+use sound::{ SoundLatches, Synth };
+
+/// Destination of the samples produced by the emulator.
+pub trait AudioBackend {
+
+    /// Sample rate expected by the backend
+    fn sample_rate(&self) -> u32;
+
+    /// Queues mono samples in `[-1, 1]` for playback.
+    fn queue(&mut self, samples: &[f32]);
+
+    /// Number of samples queued but not played yet.
+    ///
+    /// `None` for backends which are not driven by a real clock (files, tests...). The samples
+    /// are then produced at exactly the emulated rate.
+    fn queued(&self) -> Option<usize>;
+}
+
+/// Backend which drops every sample.
+pub struct NullBackend {
+    sample_rate: u32,
+}
+
+impl NullBackend {
+    pub fn new(sample_rate: u32) -> NullBackend {
+        NullBackend { sample_rate }
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queue(&mut self, _samples: &[f32]) {}
+
+    fn queued(&self) -> Option<usize> {
+        None
+    }
+}
+
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// Backend writing the samples in a 16 bits mono WAV file.
+///
+/// The sizes in the header are only correct once `finish` is called (or the backend is dropped).
+pub struct WavBackend<W: Write + Seek> {
+    // only `None` once finished
+    writer: Option<W>,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl WavBackend<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<WavBackend<BufWriter<File>>> {
+        WavBackend::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavBackend<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavBackend<W>> {
+        write_wav_header(&mut writer, sample_rate, 0)?;
+        Ok(WavBackend { writer: Some(writer), sample_rate, data_size: 0 })
+    }
+
+    /// Writes the final header and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut writer = self.writer.take().unwrap();
+        finish_wav(&mut writer, self.sample_rate, self.data_size)?;
+        Ok(writer)
+    }
+}
+
+fn finish_wav<W: Write + Seek>(writer: &mut W, sample_rate: u32, data_size: u32) -> io::Result<()> {
+    writer.seek(SeekFrom::Start(0))?;
+    write_wav_header(writer, sample_rate, data_size)?;
+    writer.seek(SeekFrom::End(0))?;
+    writer.flush()
+}
+
+impl<W: Write + Seek> AudioBackend for WavBackend<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queue(&mut self, samples: &[f32]) {
+        if let Some(ref mut writer) = self.writer {
+            for sample in samples {
+                let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                if writer.write_all(&value.to_le_bytes()).is_err() {
+                    return;
+                }
+                self.data_size += 2;
+            }
+        }
+    }
+
+    fn queued(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<W: Write + Seek> Drop for WavBackend<W> {
+    fn drop(&mut self) {
+        if let Some(ref mut writer) = self.writer {
+            let _ = finish_wav(writer, self.sample_rate, self.data_size);
+        }
+    }
+}
+
+/// Writes the header of a 16 bits mono PCM WAV file containing `data_size` bytes of samples.
+pub fn write_wav_header<W: Write>(writer: &mut W, sample_rate: u32, data_size: u32) -> io::Result<()> {
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // mono
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // bytes per frame
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())
+}
+
+/// Linear interpolation resampler, which keeps its state between two chunks of samples.
+pub struct Resampler {
+    position: f64,
+    last: f32,
+}
+
+impl Resampler {
+    pub fn new() -> Resampler {
+        Resampler { position: 0.0, last: 0.0 }
+    }
+
+    /// Resamples `input` with `ratio` (output rate / input rate) and appends the result to
+    /// `output`.
+    pub fn process(&mut self, input: &[f32], ratio: f64, output: &mut Vec<f32>) {
+        if input.is_empty() {
+            return;
+        }
+        let step = 1.0 / ratio;
+        let last_index = (input.len() - 1) as f64;
+        while self.position < last_index {
+            let index = self.position.floor();
+            let fraction = (self.position - index) as f32;
+            let index = index as isize;
+            let x0 = if index < 0 { self.last } else { input[index as usize] };
+            let x1 = input[(index + 1) as usize];
+            output.push(x0 + (x1 - x0) * fraction);
+            self.position += step;
+        }
+        self.position -= input.len() as f64;
+        self.last = input[input.len() - 1];
+    }
+}
+
+impl Default for Resampler {
+    fn default() -> Resampler {
+        Resampler::new()
+    }
+}
+
+/// Settings of the audio output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings {
+    /// Number of frames requested by the device on every callback, `None` for its default
+    pub buffer_size: Option<u32>,
+    /// Amount of audio which should be queued in the device
+    pub latency: Duration,
+}
+
+impl Default for AudioSettings {
+    fn default() -> AudioSettings {
+        AudioSettings {
+            buffer_size: None,
+            latency: Duration::from_millis(60),
+        }
+    }
+}
+
+// Maximum deviation of the playback speed used to keep the device queue at the target latency.
+// 0.5% is not audible.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// Produces the sound of the emulated machine on an `AudioBackend`.
+///
+/// The emulation and the audio device are driven by two different clocks. To avoid drifting, the
+/// samples are slightly resampled so that the device queue stays around the configured latency.
+pub struct AudioOutput {
+    backend: Box<dyn AudioBackend>,
+    synth: Synth,
+    resampler: Resampler,
+    target_queued: usize,
+    cycles_remainder: u64,
+    synthesized: Vec<f32>,
+    resampled: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn new(backend: Box<dyn AudioBackend>, settings: AudioSettings) -> AudioOutput {
+        let sample_rate = backend.sample_rate();
+        let latency = settings.latency.as_secs_f64();
+        AudioOutput {
+            backend,
+            synth: Synth::new(sample_rate),
+            resampler: Resampler::new(),
+            target_queued: (latency * sample_rate as f64) as usize,
+            cycles_remainder: 0,
+            synthesized: Vec::new(),
+            resampled: Vec::new(),
+        }
+    }
+
+    pub fn synth(&self) -> &Synth {
+        &self.synth
+    }
+
+    /// Gives access to the volume and mute settings
+    pub fn synth_mut(&mut self) -> &mut Synth {
+        &mut self.synth
+    }
+
+    /// Produces the samples for `cycles` cycles of emulation with the current sound `latches`.
+    pub fn update(&mut self, latches: SoundLatches, cycles: u64) {
+        let total = cycles * self.synth.sample_rate() as u64 + self.cycles_remainder;
+        let sample_count = (total / CPU_FREQUENCY) as usize;
+        self.cycles_remainder = total % CPU_FREQUENCY;
+
+        self.synth.set_latches(latches);
+        self.synthesized.resize(sample_count, 0.0);
+        self.synth.render(&mut self.synthesized);
+
+        match self.backend.queued() {
+            None => self.backend.queue(&self.synthesized),
+            Some(queued) => {
+                let ratio = self.rate_ratio(queued);
+                self.resampled.clear();
+                self.resampler.process(&self.synthesized, ratio, &mut self.resampled);
+                self.backend.queue(&self.resampled);
+            },
+        }
+    }
+
+    fn rate_ratio(&self, queued: usize) -> f64 {
+        if self.target_queued == 0 {
+            return 1.0;
+        }
+        let delta = (self.target_queued as f64 - queued as f64) / self.target_queued as f64;
+        1.0 + MAX_RATE_ADJUSTMENT * delta.clamp(-1.0, 1.0)
+    }
+}
+
+impl AudioSink for AudioOutput {
+    fn update(&mut self, latches: SoundLatches, cycles: u64) {
+        AudioOutput::update(self, latches, cycles);
+    }
+
+    fn is_muted(&self) -> bool {
+        self.synth.is_muted()
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.synth.set_muted(muted);
+    }
+}
+
+#[cfg(feature = "cpal")]
+pub use self::device::CpalBackend;
+
+#[cfg(feature = "cpal")]
+mod device {
+    extern crate cpal;
+
+    use std::collections::VecDeque;
+    use std::sync::{ Arc, Mutex };
+
+    use self::cpal::traits::{ DeviceTrait, HostTrait, StreamTrait };
+    use self::cpal::{ FromSample, SizedSample };
+
+    use super::{ AudioBackend, AudioSettings };
+
+    /// Frames per callback assumed when the device chooses them
+    const DEFAULT_BUFFER_SIZE: u32 = 1024;
+
+    /// Backend playing the samples on the default output device of the system (ALSA or
+    /// PulseAudio on linux, CoreAudio on osx, WASAPI on windows).
+    pub struct CpalBackend {
+        _stream: cpal::Stream,
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        sample_rate: u32,
+        max_queued: usize,
+    }
+
+    impl CpalBackend {
+        pub fn open(settings: AudioSettings) -> Result<CpalBackend, String> {
+            let host = cpal::default_host();
+            let device = host.default_output_device()
+                .ok_or_else(|| "no audio output device".to_string())?;
+            let supported = device.default_output_config().map_err(|e| e.to_string())?;
+            let sample_rate = supported.sample_rate().0;
+            // many devices reject a fixed size they do not support
+            let buffer_size = match (settings.buffer_size, supported.buffer_size()) {
+                (Some(size), &cpal::SupportedBufferSize::Range { min, max }) => cpal::BufferSize::Fixed(size.clamp(min, max)),
+                (Some(size), &cpal::SupportedBufferSize::Unknown) => cpal::BufferSize::Fixed(size),
+                (None, _) => cpal::BufferSize::Default,
+            };
+            let config = cpal::StreamConfig {
+                channels: supported.channels(),
+                sample_rate: supported.sample_rate(),
+                buffer_size,
+            };
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+            let stream = match supported.sample_format() {
+                cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+                cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+                cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+                format => Err(format!("unsupported sample format {}", format)),
+            }?;
+            stream.play().map_err(|e| e.to_string())?;
+
+            let latency_samples = settings.latency.as_secs_f64() * sample_rate as f64;
+            Ok(CpalBackend {
+                _stream: stream,
+                queue,
+                sample_rate,
+                // past that, the emulation is too far ahead (e.g. after a pause), samples are dropped
+                max_queued: (latency_samples * 4.0) as usize + settings.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE) as usize,
+            })
+        }
+    }
+
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        queue: Arc<Mutex<VecDeque<f32>>>
+    ) -> Result<cpal::Stream, String>
+        where T: SizedSample + FromSample<f32> {
+
+        let channels = config.channels as usize;
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut queue = queue.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let sample = queue.pop_front().unwrap_or(0.0);
+                    for channel in frame.iter_mut() {
+                        *channel = T::from_sample(sample);
+                    }
+                }
+            },
+            |err| eprintln!("audio stream error: {}", err),
+            None,
+        ).map_err(|e| e.to_string())
+    }
+
+    impl AudioBackend for CpalBackend {
+        fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+
+        fn queue(&mut self, samples: &[f32]) {
+            let mut queue = self.queue.lock().unwrap();
+            queue.extend(samples);
+            if queue.len() > self.max_queued {
+                let overflow = queue.len() - self.max_queued;
+                queue.drain(0..overflow);
+            }
+        }
+
+        fn queued(&self) -> Option<usize> {
+            Some(self.queue.lock().unwrap().len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use audio::*;
+    use sound::SoundLatches;
+    use machine::CPU_FREQUENCY;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    struct TestBackend {
+        samples: Rc<RefCell<Vec<f32>>>,
+        queued: Option<usize>,
+    }
+
+    impl AudioBackend for TestBackend {
+        fn sample_rate(&self) -> u32 { 48000 }
+
+        fn queue(&mut self, samples: &[f32]) {
+            self.samples.borrow_mut().extend_from_slice(samples)
+        }
+
+        fn queued(&self) -> Option<usize> { self.queued }
+    }
+
+    #[test]
+    fn resampler_with_ratio_1_keeps_samples() {
+        let mut resampler = Resampler::new();
+        let mut output = Vec::new();
+        // the last sample of a chunk is only emitted with the next one
+        resampler.process(&[1.0, 2.0, 3.0, 4.0], 1.0, &mut output);
+        assert_eq!(vec![1.0, 2.0, 3.0], output);
+        resampler.process(&[5.0, 6.0], 1.0, &mut output);
+        assert_eq!(vec![1.0, 2.0, 3.0, 4.0, 5.0], output);
+    }
+
+    #[test]
+    fn resampler_interpolates() {
+        let mut resampler = Resampler::new();
+        let mut output = Vec::new();
+        resampler.process(&[0.0, 1.0, 2.0, 3.0, 4.0], 2.0, &mut output);
+        assert_eq!(vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5], output);
+    }
+
+    #[test]
+    fn produces_samples_at_emulated_rate_without_clock() {
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let backend = TestBackend { samples: samples.clone(), queued: None };
+        let mut output = AudioOutput::new(Box::new(backend), AudioSettings::default());
+        for _ in 0..100 {
+            output.update(SoundLatches::default(), CPU_FREQUENCY / 100);
+        }
+        assert_eq!(48000, samples.borrow().len());
+    }
+
+    #[test]
+    fn speeds_up_when_device_queue_is_empty() {
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let backend = TestBackend { samples: samples.clone(), queued: Some(0) };
+        let mut output = AudioOutput::new(Box::new(backend), AudioSettings::default());
+        output.update(SoundLatches::default(), CPU_FREQUENCY);
+        let len = samples.borrow().len();
+        assert!(len > 48000 && len <= 48000 + 48000 / 200, "{}", len);
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut backend = WavBackend::new(Cursor::new(Vec::new()), 44100).unwrap();
+        backend.queue(&[0.0, 1.0, -1.0]);
+        let bytes = backend.finish().unwrap().into_inner();
+
+        assert_eq!(44 + 6, bytes.len());
+        assert_eq!(b"RIFF", &bytes[0..4]);
+        assert_eq!(&(36u32 + 6).to_le_bytes(), &bytes[4..8]);
+        assert_eq!(&44100u32.to_le_bytes(), &bytes[24..28]);
+        assert_eq!(&6u32.to_le_bytes(), &bytes[40..44]);
+        assert_eq!(&[0, 0, 0xff, 0x7f, 0x01, 0x80], &bytes[44..50]);
+    }
+}
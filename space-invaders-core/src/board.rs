@@ -0,0 +1,482 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::str::FromStr;
+
+use rom::RomFile;
+
+/*
+ Space invaders runs on a board (designed by Midway, also built by Taito) with an 8080, 8 KiB of
+ RAM (the video RAM is its upper 7 KiB), a MB14241 barrel shifter and discrete sound circuits.
+ Many games were released on the same board. They differ by the ROM (which may be mapped in a
+ second area from 0x4000), the wiring of the inputs and DIP switches on the ports, and the
+ sounds. A `BoardDriver` describes one of these games, the `SpaceInvaderMachine` emulates the
+ board.
+
+ The Taito boards of Space Invaders Part II, Lunar Rescue and Balloon Bomber add a colour RAM and
+ a colour PROM (see the `frame` module).
+
+ The ROM files are the ones of the MAME sets. Only space invaders is supported: the drivers of the
+ other games describe their ROM areas and colours, but they still use the ports, the DIP switches
+ and the sounds of space invaders, and their files have no CRC32 to check the dumps. They are not
+ in `DRIVERS` until they have them.
+*/
+
+/// Inputs of the cabinets of the family (not every game uses all of them)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Coin,
+    P1Start,
+    P2Start,
+    P1Fire,
+    P1Left,
+    P1Right,
+    P2Fire,
+    P2Left,
+    P2Right,
+    Tilt,
+}
+
+impl FromStr for Input {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Input, String> {
+        match name {
+            "coin" => Ok(Input::Coin),
+            "p1-start" => Ok(Input::P1Start),
+            "p2-start" => Ok(Input::P2Start),
+            "p1-fire" => Ok(Input::P1Fire),
+            "p1-left" => Ok(Input::P1Left),
+            "p1-right" => Ok(Input::P1Right),
+            "p2-fire" => Ok(Input::P2Fire),
+            "p2-left" => Ok(Input::P2Left),
+            "p2-right" => Ok(Input::P2Right),
+            "tilt" => Ok(Input::Tilt),
+            _ => Err(format!("unknown input {}", name)),
+        }
+    }
+}
+
+/// Bit of an input port which changes while an input is active
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputBit {
+    pub port: u8,
+    pub bit: u8,
+    /// The bit is cleared rather than set when the input is active
+    pub active_low: bool,
+}
+
+/// Wiring of the ports of a game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortMap {
+    /// Value of the input ports 0 to 2 when nothing is pressed (the bits tied to 1)
+    pub idle: [u8; 3],
+    pub inputs: &'static [(Input, InputBit)],
+    /// Out port setting the offset of the shifter, out port pushing a byte in it and in port
+    /// reading its result
+    pub shift_offset: u8,
+    pub shift_data: u8,
+    pub shift_result: u8,
+    /// Out ports of the two sound latches (see `SoundLatches`)
+    pub sound: [u8; 2],
+    /// Out port of the watchdog, which resets the board if the game stops writing in it
+    pub watchdog: u8,
+}
+
+/// Settings of the cabinet, read by the game on the input ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DipSwitches {
+    /// Ships at the start of a game, from 3 to 6
+    pub lives: u8,
+    /// Extra ship at 1000 points instead of 1500
+    pub extra_life_at_1000: bool,
+    /// Display the coin information in the demo screen
+    pub coin_info: bool,
+}
+
+impl Default for DipSwitches {
+    fn default() -> DipSwitches {
+        DipSwitches { lives: 3, extra_life_at_1000: false, coin_info: true }
+    }
+}
+
+/// One game of the family.
+///
+/// The provided methods describe space invaders, which most of the other games keep.
+pub trait BoardDriver: Sync {
+    /// Short name, as in MAME (e.g. `invaders`)
+    fn name(&self) -> &'static str;
+
+    fn title(&self) -> &'static str;
+
+    /// Files of the ROM set and where they are mapped
+    fn rom_files(&self) -> &'static [RomFile];
+
+    /// CRC32 of the ROM when it is dumped as a single image, if known
+    fn image_crc(&self) -> Option<u32> {
+        None
+    }
+
+    fn ports(&self) -> &'static PortMap {
+        &MIDWAY_PORTS
+    }
+
+    /// Writes the DIP switches in the input ports, `lives` is clamped between 3 and 6.
+    fn encode_dip_switches(&self, dip_switches: DipSwitches, ports: &mut [u8; 3]) {
+        let lives = dip_switches.lives.clamp(3, 6) - 3;
+        let port_2 = lives
+            | ((dip_switches.extra_life_at_1000 as u8) << EXTRA_LIFE_OFFSET)
+            | ((!dip_switches.coin_info as u8) << COIN_INFO_OFFSET);
+        ports[2] = (ports[2] & !PORT_2_DIP_MASK) | port_2;
+    }
+
+    fn decode_dip_switches(&self, ports: &[u8; 3]) -> DipSwitches {
+        DipSwitches {
+            lives: (ports[2] & LIVES_MASK) + 3,
+            extra_life_at_1000: ports[2] & (1 << EXTRA_LIFE_OFFSET) != 0,
+            coin_info: ports[2] & (1 << COIN_INFO_OFFSET) == 0,
+        }
+    }
+
+    /// Whether the board has the colour RAM (see `Frame::update_color_ram`)
+    fn has_color_ram(&self) -> bool {
+        false
+    }
+
+    /// Colour PROM of the set, which gives the colours of the codes of the colour RAM (see
+    /// `color_palette`)
+    fn color_prom(&self) -> Option<RomFile> {
+        None
+    }
+
+    /// Address of the high score in RAM (4 BCD digits, least significant byte first), for the
+    /// games where it is known
+    fn high_score_address(&self) -> Option<usize> {
+        None
+    }
+}
+
+/*
+ From : http://computerarcheology.com/Arcade/SpaceInvaders/Hardware.html
+    Port 0
+    bit 0 DIP4 (Seems to be self-test-request read at power up)
+    bit 1 Always 1
+    bit 2 Always 1
+    bit 3 Always 1
+    bit 4 Fire
+    bit 5 Left
+    bit 6 Right
+    bit 7 ? tied to demux port 7 ?
+
+    Port 1
+    bit 0 = CREDIT (1 if deposit)
+    bit 1 = 2P start (1 if pressed)
+    bit 2 = 1P start (1 if pressed)
+    bit 3 = Always 1
+    bit 4 = 1P shot (1 if pressed)
+    bit 5 = 1P left (1 if pressed)
+    bit 6 = 1P right (1 if pressed)
+    bit 7 = Not connected
+
+    Port 2
+    bit 0 = DIP3 00 = 3 ships  10 = 5 ships
+    bit 1 = DIP5 01 = 4 ships  11 = 6 ships
+    bit 2 = Tilt
+    bit 3 = DIP6 0 = extra ship at 1500, 1 = extra ship at 1000
+    bit 4 = P2 shot (1 if pressed)
+    bit 5 = P2 left (1 if pressed)
+    bit 6 = P2 right (1 if pressed)
+    bit 7 = DIP7 Coin info displayed in demo screen 0=ON
+
+    Port 3
+    bit 0-7 Shift register data
+
+ Output ports 3 and 5 drive the sounds, see the `sound` module.
+*/
+
+const LIVES_MASK: u8 = 0b0000_0011;
+const EXTRA_LIFE_OFFSET: u8 = 3;
+const COIN_INFO_OFFSET: u8 = 7;
+const PORT_2_DIP_MASK: u8 = LIVES_MASK | (1 << EXTRA_LIFE_OFFSET) | (1 << COIN_INFO_OFFSET);
+
+const fn bit(port: u8, bit: u8) -> InputBit {
+    InputBit { port, bit, active_low: false }
+}
+
+/// Ports of space invaders
+pub const MIDWAY_PORTS: PortMap = PortMap {
+    idle: [0b0000_1111, 0b0000_1000, 0b0000_0000],
+    inputs: &[
+        (Input::Coin, bit(1, 0)),
+        (Input::P2Start, bit(1, 1)),
+        (Input::P1Start, bit(1, 2)),
+        (Input::P1Fire, bit(1, 4)),
+        (Input::P1Left, bit(1, 5)),
+        (Input::P1Right, bit(1, 6)),
+        (Input::Tilt, bit(2, 2)),
+        (Input::P2Fire, bit(2, 4)),
+        (Input::P2Left, bit(2, 5)),
+        (Input::P2Right, bit(2, 6)),
+    ],
+    shift_offset: 2,
+    shift_data: 4,
+    shift_result: 3,
+    sound: [3, 5],
+    watchdog: 6,
+};
+
+/// The Taito boards read the coin inverted (0 when a coin is inserted)
+const TAITO_PORTS: PortMap = PortMap {
+    idle: [0b0000_1111, 0b0000_1001, 0b0000_0000],
+    inputs: &[
+        (Input::Coin, InputBit { port: 1, bit: 0, active_low: true }),
+        (Input::P2Start, bit(1, 1)),
+        (Input::P1Start, bit(1, 2)),
+        (Input::P1Fire, bit(1, 4)),
+        (Input::P1Left, bit(1, 5)),
+        (Input::P1Right, bit(1, 6)),
+        (Input::Tilt, bit(2, 2)),
+        (Input::P2Fire, bit(2, 4)),
+        (Input::P2Left, bit(2, 5)),
+        (Input::P2Right, bit(2, 6)),
+    ],
+    ..MIDWAY_PORTS
+};
+
+const fn rom_file(name: &'static str, offset: usize, size: usize, crc: Option<u32>) -> RomFile {
+    RomFile { name, offset, size, crc }
+}
+
+/// Space Invaders (Midway, 1978)
+pub struct Invaders;
+
+impl BoardDriver for Invaders {
+    fn name(&self) -> &'static str {
+        "invaders"
+    }
+
+    fn title(&self) -> &'static str {
+        "Space Invaders"
+    }
+
+    fn rom_files(&self) -> &'static [RomFile] {
+        const FILES: &[RomFile] = &[
+            rom_file("invaders.h", 0x0000, 0x800, Some(0x734f_5ad8)),
+            rom_file("invaders.g", 0x0800, 0x800, Some(0x6bfa_ca4a)),
+            rom_file("invaders.f", 0x1000, 0x800, Some(0x0cce_ad96)),
+            rom_file("invaders.e", 0x1800, 0x800, Some(0x14e5_38b0)),
+        ];
+        FILES
+    }
+
+    fn image_crc(&self) -> Option<u32> {
+        Some(0xb64c_a815)
+    }
+
+    fn high_score_address(&self) -> Option<usize> {
+        Some(0x20f4)
+    }
+}
+
+/// Space Invaders Part II (Taito, 1979)
+pub struct InvadersPart2;
+
+impl BoardDriver for InvadersPart2 {
+    fn name(&self) -> &'static str {
+        "invadpt2"
+    }
+
+    fn title(&self) -> &'static str {
+        "Space Invaders Part II"
+    }
+
+    fn rom_files(&self) -> &'static [RomFile] {
+        const FILES: &[RomFile] = &[
+            rom_file("pv01", 0x0000, 0x800, None),
+            rom_file("pv02", 0x0800, 0x800, None),
+            rom_file("pv03", 0x1000, 0x800, None),
+            rom_file("pv04", 0x1800, 0x800, None),
+            rom_file("pv05", 0x4000, 0x800, None),
+        ];
+        FILES
+    }
+
+    fn ports(&self) -> &'static PortMap {
+        &TAITO_PORTS
+    }
+
+    fn has_color_ram(&self) -> bool {
+        true
+    }
+
+    fn color_prom(&self) -> Option<RomFile> {
+        Some(rom_file("pv06.1", 0, 0x400, None))
+    }
+}
+
+/// Space Invaders Deluxe (Midway, 1980), Part II with the Midway cabinet
+pub struct InvadersDeluxe;
+
+impl BoardDriver for InvadersDeluxe {
+    fn name(&self) -> &'static str {
+        "invaddlx"
+    }
+
+    fn title(&self) -> &'static str {
+        "Space Invaders Deluxe"
+    }
+
+    fn rom_files(&self) -> &'static [RomFile] {
+        const FILES: &[RomFile] = &[
+            rom_file("invdelux.h", 0x0000, 0x800, None),
+            rom_file("invdelux.g", 0x0800, 0x800, None),
+            rom_file("invdelux.f", 0x1000, 0x800, None),
+            rom_file("invdelux.e", 0x1800, 0x800, None),
+            rom_file("invdelux.d", 0x4000, 0x800, None),
+        ];
+        FILES
+    }
+}
+
+/// Lunar Rescue (Taito, 1979)
+pub struct LunarRescue;
+
+impl BoardDriver for LunarRescue {
+    fn name(&self) -> &'static str {
+        "lrescue"
+    }
+
+    fn title(&self) -> &'static str {
+        "Lunar Rescue"
+    }
+
+    fn rom_files(&self) -> &'static [RomFile] {
+        const FILES: &[RomFile] = &[
+            rom_file("lrescue.1", 0x0000, 0x800, None),
+            rom_file("lrescue.2", 0x0800, 0x800, None),
+            rom_file("lrescue.3", 0x1000, 0x800, None),
+            rom_file("lrescue.4", 0x1800, 0x800, None),
+            rom_file("lrescue.5", 0x4000, 0x800, None),
+            rom_file("lrescue.6", 0x4800, 0x800, None),
+        ];
+        FILES
+    }
+
+    fn ports(&self) -> &'static PortMap {
+        &TAITO_PORTS
+    }
+
+    fn has_color_ram(&self) -> bool {
+        true
+    }
+
+    fn color_prom(&self) -> Option<RomFile> {
+        Some(rom_file("7643-1.cpu", 0, 0x400, None))
+    }
+}
+
+/// Balloon Bomber (Taito, 1980)
+pub struct BalloonBomber;
+
+impl BoardDriver for BalloonBomber {
+    fn name(&self) -> &'static str {
+        "ballbomb"
+    }
+
+    fn title(&self) -> &'static str {
+        "Balloon Bomber"
+    }
+
+    fn rom_files(&self) -> &'static [RomFile] {
+        const FILES: &[RomFile] = &[
+            rom_file("tn01", 0x0000, 0x800, None),
+            rom_file("tn02", 0x0800, 0x800, None),
+            rom_file("tn03", 0x1000, 0x800, None),
+            rom_file("tn04", 0x1800, 0x800, None),
+            rom_file("tn05-1", 0x4000, 0x800, None),
+        ];
+        FILES
+    }
+
+    fn ports(&self) -> &'static PortMap {
+        &TAITO_PORTS
+    }
+
+    fn has_color_ram(&self) -> bool {
+        true
+    }
+
+    fn color_prom(&self) -> Option<RomFile> {
+        Some(rom_file("tn06", 0, 0x400, None))
+    }
+}
+
+/// Galaxy Wars (Universal, 1979)
+pub struct GalaxyWars;
+
+impl BoardDriver for GalaxyWars {
+    fn name(&self) -> &'static str {
+        "galxwars"
+    }
+
+    fn title(&self) -> &'static str {
+        "Galaxy Wars"
+    }
+
+    fn rom_files(&self) -> &'static [RomFile] {
+        const FILES: &[RomFile] = &[
+            rom_file("univgw3.0", 0x0000, 0x400, None),
+            rom_file("univgw4.1", 0x0400, 0x400, None),
+            rom_file("univgw5.2", 0x0800, 0x400, None),
+            rom_file("univgw6.3", 0x0c00, 0x400, None),
+            rom_file("univgw1.4", 0x4000, 0x400, None),
+            rom_file("univgw2.5", 0x4400, 0x400, None),
+        ];
+        FILES
+    }
+}
+
+pub static INVADERS: Invaders = Invaders;
+
+/// Every supported game, space invaders first
+pub static DRIVERS: [&dyn BoardDriver; 1] = [
+    &Invaders,
+];
+
+/// Finds a game by its short name.
+pub fn find_driver(name: &str) -> Option<&'static dyn BoardDriver> {
+    DRIVERS.iter().cloned().find(|driver| driver.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use board::*;
+
+    #[test]
+    fn find_drivers_by_name() {
+        assert_eq!("Space Invaders", find_driver("invaders").unwrap().title());
+        // not supported yet
+        assert!(find_driver("lrescue").is_none());
+        assert!(find_driver("pacman").is_none());
+    }
+
+    #[test]
+    fn parse_inputs() {
+        assert_eq!(Ok(Input::Coin), "coin".parse());
+        assert_eq!(Ok(Input::P2Left), "p2-left".parse());
+        assert!("p3-fire".parse::<Input>().is_err());
+    }
+
+    #[test]
+    fn rom_files_do_not_overlap_the_ram() {
+        let others: [&dyn BoardDriver; 5] = [&InvadersPart2, &InvadersDeluxe, &LunarRescue, &BalloonBomber, &GalaxyWars];
+        for driver in DRIVERS.iter().chain(others.iter()) {
+            for file in driver.rom_files() {
+                let end = file.offset + file.size;
+                assert!(end <= 0x2000 || file.offset >= 0x4000, "{} {}", driver.name(), file.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,625 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use memory::MemoryMap;
+
+/*
+ Cheat files are text files, one action per line, grouped in named cheats. `#` starts a comment,
+ the numbers are decimal or hexadecimal (`0x`):
+
+    cheat Infinite lives
+    write 0x21ff 0x03 if 0x20ef == 0x01
+    cheat Invincibility
+    patch 0x060f 0x00 0x00 0x00
+
+ `write <address> <value> [if <address> <==|!=|<|>|<=|>=> <value>]` writes a byte at every frame, if the
+ byte at the address of the condition compares to its value. `patch <address> <bytes>...` replaces
+ bytes (usually of the ROM) while the cheat is enabled, and puts the original ones back after.
+
+ MAME cheat files (cheat.xml) can be imported too, see `import_mame_cheats`.
+*/
+
+/// Cheats of space invaders: infinite lives, invincibility and slow fleet
+pub const INVADERS_CHEATS: &str = include_str!("../resources/invaders.cheats");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub(crate) fn holds<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::Greater => left > right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+/// Condition of a write: compares the byte at `address` to `value`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub address: u16,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatAction {
+    /// Writes `value` at `address` at every frame, if there is no `condition` or if it holds
+    Write { address: u16, value: u8, condition: Option<Condition> },
+    /// Replaces the byte at `address` (ROM or RAM) while the cheat is enabled
+    Patch { address: u16, value: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub actions: Vec<CheatAction>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheatError {
+    /// A line of a cheat file is not understood (the lines are counted from 1)
+    Syntax { line: usize, message: String },
+    /// A MAME cheat file is not well-formed
+    Xml(String),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheatError::Syntax { line, ref message } => write!(f, "line {}: {}", line, message),
+            CheatError::Xml(ref message) => write!(f, "invalid cheat.xml: {}", message),
+        }
+    }
+}
+
+impl Error for CheatError {}
+
+/// Cheats of a game, which the frontends enable and disable while it runs.
+///
+/// The patches are made when their cheat is enabled, the writes at every `apply`, once per frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheatEngine {
+    cheats: Vec<Cheat>,
+    /// Bytes replaced by the patches of each cheat, `None` while it is disabled
+    originals: Vec<Option<Vec<u8>>>,
+}
+
+impl CheatEngine {
+    /// Engine with all the `cheats` disabled.
+    pub fn new(cheats: Vec<Cheat>) -> CheatEngine {
+        let originals = vec![None; cheats.len()];
+        CheatEngine { cheats, originals }
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_enabled(&self, index: usize) -> bool {
+        self.originals.get(index).is_some_and(|originals| originals.is_some())
+    }
+
+    pub fn any_enabled(&self) -> bool {
+        self.originals.iter().any(|originals| originals.is_some())
+    }
+
+    /// Enables or disables the cheat `index`, and makes or reverts its patches in `memory`.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool, memory: &mut MemoryMap) {
+        if enabled == self.is_enabled(index) {
+            return;
+        }
+        let patches = self.cheats[index].actions.iter().filter_map(|action| match *action {
+            CheatAction::Patch { address, value } => Some((address, value)),
+            CheatAction::Write { .. } => None,
+        });
+        if enabled {
+            let originals = patches.map(|(address, value)| {
+                let original = memory.peek(address);
+                memory.poke(address, value);
+                original
+            }).collect();
+            self.originals[index] = Some(originals);
+        } else if let Some(originals) = self.originals[index].take() {
+            // in the reverse order, for the patches of the same address
+            let patches: Vec<(u16, u8)> = patches.collect();
+            for (&(address, _), original) in patches.iter().zip(originals).rev() {
+                memory.poke(address, original);
+            }
+        }
+    }
+
+    /// Enables the cheat `index` if it is disabled, and the other way round. Returns whether it is
+    /// now enabled, or `None` if there is no such cheat.
+    pub fn toggle(&mut self, index: usize, memory: &mut MemoryMap) -> Option<bool> {
+        if index >= self.cheats.len() {
+            return None;
+        }
+        let enabled = !self.is_enabled(index);
+        self.set_enabled(index, enabled, memory);
+        Some(enabled)
+    }
+
+    /// Makes the writes of the enabled cheats, to be called once per frame.
+    pub fn apply(&self, memory: &mut MemoryMap) {
+        for (cheat, _) in self.cheats.iter().zip(&self.originals).filter(|(_, originals)| originals.is_some()) {
+            for action in &cheat.actions {
+                if let CheatAction::Write { address, value, condition } = *action {
+                    if condition.is_none_or(|c| c.comparison.holds(memory.peek(c.address), c.value)) {
+                        memory.poke(address, value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads a cheat file (see the format above).
+pub fn parse_cheats(text: &str) -> Result<Vec<Cheat>, CheatError> {
+    let mut cheats: Vec<Cheat> = Vec::new();
+    for (number, line) in (1..).zip(text.lines()) {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let syntax_error = |message: &str| CheatError::Syntax { line: number, message: message.to_string() };
+        let mut words = line.split_whitespace();
+        let action = match words.next() {
+            None => continue,
+            Some("cheat") => {
+                let name = line["cheat".len()..].trim();
+                if name.is_empty() {
+                    return Err(syntax_error("the cheat has no name"));
+                }
+                cheats.push(Cheat { name: name.to_string(), actions: Vec::new() });
+                continue;
+            },
+            Some(action) => action,
+        };
+        let cheat = cheats.last_mut().ok_or_else(|| syntax_error("action outside of a cheat"))?;
+        let words: Vec<&str> = words.collect();
+        match (action, words.as_slice()) {
+            ("write", [address, value]) => cheat.actions.push(CheatAction::Write {
+                address: parse_number(address).ok_or_else(|| syntax_error("invalid address"))?,
+                value: parse_number(value).ok_or_else(|| syntax_error("invalid value"))?,
+                condition: None,
+            }),
+            ("write", [address, value, "if", condition_address, comparison, condition_value]) => {
+                let condition = Condition {
+                    address: parse_number(condition_address).ok_or_else(|| syntax_error("invalid address"))?,
+                    comparison: parse_comparison(comparison).ok_or_else(|| syntax_error("invalid comparison"))?,
+                    value: parse_number(condition_value).ok_or_else(|| syntax_error("invalid value"))?,
+                };
+                cheat.actions.push(CheatAction::Write {
+                    address: parse_number(address).ok_or_else(|| syntax_error("invalid address"))?,
+                    value: parse_number(value).ok_or_else(|| syntax_error("invalid value"))?,
+                    condition: Some(condition),
+                });
+            },
+            ("patch", [address, values @ ..]) if !values.is_empty() => {
+                let address: u16 = parse_number(address).ok_or_else(|| syntax_error("invalid address"))?;
+                for (offset, value) in (0..).zip(values) {
+                    cheat.actions.push(CheatAction::Patch {
+                        address: address.wrapping_add(offset),
+                        value: parse_number(value).ok_or_else(|| syntax_error("invalid value"))?,
+                    });
+                }
+            },
+            ("write", _) => return Err(syntax_error("expected write <address> <value> [if <address> <comparison> <value>]")),
+            ("patch", _) => return Err(syntax_error("expected patch <address> <bytes>...")),
+            _ => return Err(syntax_error(&format!("unknown action {}", action))),
+        }
+    }
+    Ok(cheats)
+}
+
+pub(crate) fn parse_number<T: TryFrom<u32>>(text: &str) -> Option<T> {
+    let number = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    T::try_from(number).ok()
+}
+
+pub(crate) fn parse_comparison(text: &str) -> Option<Comparison> {
+    match text {
+        "==" => Some(Comparison::Equal),
+        "!=" => Some(Comparison::NotEqual),
+        "<" => Some(Comparison::Less),
+        ">" => Some(Comparison::Greater),
+        "<=" => Some(Comparison::LessOrEqual),
+        ">=" => Some(Comparison::GreaterOrEqual),
+        _ => None,
+    }
+}
+
+/*
+ MAME cheat files have one `cheat` element per cheat, with Lua-like scripts run when the cheat is
+ enabled (`on`), at every frame (`run`), when it is changed and when it is disabled (`off`):
+
+    <mamecheat version="1">
+      <cheat desc="Infinite Lives">
+        <script state="run">
+          <action condition="maincpu.pb@20EF==01">maincpu.pb@21FF=03</action>
+        </script>
+      </cheat>
+    </mamecheat>
+
+ Only the assignments of constant bytes of the main cpu are imported: those of `run` become
+ writes, those of `on` to the ROM (`rb`) become patches. The `off` scripts, which restore the
+ bytes saved in `temp` variables, are not needed. Cheats with parameters, expressions or other
+ sizes cannot be imported.
+*/
+
+const MAME_COMPARISONS: [(&str, Comparison); 4] =
+    [("==", Comparison::Equal), ("!=", Comparison::NotEqual), ("<", Comparison::Less), (">", Comparison::Greater)];
+
+/// Cheats read from a MAME cheat file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MameCheats {
+    pub cheats: Vec<Cheat>,
+    /// Descriptions of the cheats which could not be imported
+    pub skipped: Vec<String>,
+}
+
+/// Imports the cheats of a MAME cheat file (e.g. `invaders.xml` in the cheat.7z of the MAME cheat
+/// collection).
+pub fn import_mame_cheats(xml: &str) -> Result<MameCheats, CheatError> {
+    let mut imported = MameCheats { cheats: Vec::new(), skipped: Vec::new() };
+    // the cheat being read, and whether it can be imported so far
+    let mut current: Option<(Cheat, bool)> = None;
+    let mut script: Option<String> = None;
+    let mut action: Option<(Option<String>, String)> = None;
+    for event in XmlReader::new(xml) {
+        match event? {
+            XmlEvent::Start { name: "cheat", attributes } => {
+                let desc = attribute(&attributes, "desc").unwrap_or_default();
+                current = Some((Cheat { name: desc, actions: Vec::new() }, true));
+            },
+            XmlEvent::Start { name: "parameter", .. } => {
+                if let Some((_, ref mut supported)) = current {
+                    *supported = false;
+                }
+            },
+            XmlEvent::Start { name: "script", attributes } => script = attribute(&attributes, "state"),
+            XmlEvent::Start { name: "action", attributes } => {
+                action = Some((attribute(&attributes, "condition"), String::new()));
+            },
+            XmlEvent::Text(text) => {
+                if let Some((_, ref mut expression)) = action {
+                    expression.push_str(&text);
+                }
+            },
+            XmlEvent::End("action") => {
+                if let (Some((cheat, supported)), Some((condition, expression))) = (current.as_mut(), action.take()) {
+                    let script = script.as_deref().unwrap_or("run");
+                    match import_mame_action(script, condition.as_deref(), &expression) {
+                        Some(Some(action)) => cheat.actions.push(action),
+                        Some(None) => (),
+                        None => *supported = false,
+                    }
+                }
+            },
+            XmlEvent::End("script") => script = None,
+            XmlEvent::End("cheat") => {
+                if let Some((cheat, supported)) = current.take() {
+                    if supported && !cheat.actions.is_empty() {
+                        imported.cheats.push(cheat);
+                    } else if !cheat.name.is_empty() {
+                        // the others are separators or comments of the list
+                        imported.skipped.push(cheat.name);
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+    Ok(imported)
+}
+
+/// Action of a MAME script, `None` if it cannot be imported and `Some(None)` if it is not needed.
+fn import_mame_action(script: &str, condition: Option<&str>, expression: &str) -> Option<Option<CheatAction>> {
+    let expression: String = expression.chars().filter(|c| !c.is_whitespace()).collect();
+    if script == "off" || expression.starts_with("temp") {
+        return Some(None);
+    }
+    let (target, value) = expression.split_once('=')?;
+    let value = u8::from_str_radix(value, 16).ok()?;
+    let (space, address) = parse_mame_address(target)?;
+    match (script, condition) {
+        ("run", None) => Some(Some(CheatAction::Write { address, value, condition: None })),
+        ("run", Some(condition)) => {
+            let condition: String = condition.chars().filter(|c| !c.is_whitespace()).collect();
+            let &(operator, comparison) = MAME_COMPARISONS.iter().find(|(operator, _)| condition.contains(operator))?;
+            let (left, right) = condition.split_once(operator)?;
+            let (_, condition_address) = parse_mame_address(left)?;
+            let condition = Condition { address: condition_address, comparison, value: u8::from_str_radix(right, 16).ok()? };
+            Some(Some(CheatAction::Write { address, value, condition: Some(condition) }))
+        },
+        ("on", None) if space == 'r' => Some(Some(CheatAction::Patch { address, value })),
+        _ => None,
+    }
+}
+
+/// Space (`p`rogram, `m`emory or `r`om) and address of a byte of the main cpu, as in
+/// `maincpu.pb@21FF`.
+fn parse_mame_address(text: &str) -> Option<(char, u16)> {
+    let rest = text.strip_prefix("maincpu.")?;
+    let mut chars = rest.chars();
+    let space = chars.next().filter(|space| "pmr".contains(*space))?;
+    let address = chars.as_str().strip_prefix("b@")?;
+    Some((space, u16::from_str_radix(address, 16).ok()?))
+}
+
+fn attribute(attributes: &[(&str, String)], name: &str) -> Option<String> {
+    attributes.iter().find(|(key, _)| *key == name).map(|(_, value)| value.clone())
+}
+
+/// Piece of an XML document
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum XmlEvent<'a> {
+    Start { name: &'a str, attributes: Vec<(&'a str, String)> },
+    End(&'a str),
+    /// Text between the tags, with the entities decoded
+    Text(String),
+}
+
+/// Markup skipped by the `XmlReader`: declaration, comments, CDATA and doctypes
+const SKIPPED: [(&str, &str); 4] = [("<?", "?>"), ("<!--", "-->"), ("<![CDATA[", "]]>"), ("<!", ">")];
+
+/// Reads just enough XML for the cheat files: elements, attributes, text and the predefined
+/// entities.
+struct XmlReader<'a> {
+    rest: &'a str,
+    /// End tag of an empty element (`<a/>`), returned after its start tag
+    pending_end: Option<&'a str>,
+}
+
+impl<'a> XmlReader<'a> {
+    fn new(xml: &'a str) -> XmlReader<'a> {
+        XmlReader { rest: xml, pending_end: None }
+    }
+
+    fn take_until(&mut self, end: &str) -> Result<&'a str, CheatError> {
+        let index = self.rest.find(end).ok_or_else(|| CheatError::Xml(format!("missing {}", end)))?;
+        let taken = &self.rest[..index];
+        self.rest = &self.rest[(index + end.len())..];
+        Ok(taken)
+    }
+
+    fn next_event(&mut self) -> Result<Option<XmlEvent<'a>>, CheatError> {
+        if let Some(name) = self.pending_end.take() {
+            return Ok(Some(XmlEvent::End(name)));
+        }
+        loop {
+            if self.rest.is_empty() {
+                return Ok(None);
+            }
+            if !self.rest.starts_with('<') {
+                let end = self.rest.find('<').unwrap_or(self.rest.len());
+                let text = &self.rest[..end];
+                self.rest = &self.rest[end..];
+                return Ok(Some(XmlEvent::Text(decode_entities(text)?)));
+            }
+            if let Some(&(start, end)) = SKIPPED.iter().find(|(start, _)| self.rest.starts_with(start)) {
+                self.rest = &self.rest[start.len()..];
+                self.take_until(end)?;
+            } else if let Some(rest) = self.rest.strip_prefix("</") {
+                self.rest = rest;
+                return Ok(Some(XmlEvent::End(self.take_until(">")?.trim())));
+            } else {
+                self.rest = &self.rest[1..];
+                return self.start_tag().map(Some);
+            }
+        }
+    }
+
+    fn start_tag(&mut self) -> Result<XmlEvent<'a>, CheatError> {
+        let mut tag = self.take_until(">")?;
+        let empty = tag.ends_with('/');
+        if empty {
+            tag = &tag[..(tag.len() - 1)];
+        }
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let name = &tag[..name_end];
+        let mut attributes = Vec::new();
+        let mut rest = tag[name_end..].trim_start();
+        while !rest.is_empty() {
+            let invalid = || CheatError::Xml(format!("invalid attributes in <{}>", name));
+            let (key, after) = rest.split_once('=').ok_or_else(invalid)?;
+            let after = after.trim_start();
+            let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or_else(invalid)?;
+            let (value, after) = after[1..].split_once(quote).ok_or_else(invalid)?;
+            attributes.push((key.trim(), decode_entities(value)?));
+            rest = after.trim_start();
+        }
+        if empty {
+            self.pending_end = Some(name);
+        }
+        Ok(XmlEvent::Start { name, attributes })
+    }
+}
+
+impl<'a> Iterator for XmlReader<'a> {
+    type Item = Result<XmlEvent<'a>, CheatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+fn decode_entities(text: &str) -> Result<String, CheatError> {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let end = rest[start..].find(';').ok_or_else(|| CheatError::Xml("unterminated entity".to_string()))?;
+        let entity = &rest[(start + 1)..(start + end)];
+        decoded.push(match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|decimal| decimal.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+                    .ok_or_else(|| CheatError::Xml(format!("unknown entity &{};", entity)))?
+            },
+        });
+        rest = &rest[(start + end + 1)..];
+    }
+    decoded.push_str(rest);
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use board::INVADERS;
+    use cheat::*;
+    use memory::MemoryMap;
+
+    #[test]
+    fn parse_cheat_file() {
+        let cheats = parse_cheats("\
+            # comment\n\
+            cheat Infinite lives\n\
+            write 0x21ff 3 if 0x20ef == 0x01  # while playing\n\
+            \n\
+            cheat Patch\n\
+            patch 0x060f 0x00 0x01\n").unwrap();
+        assert_eq!(vec![
+            Cheat {
+                name: "Infinite lives".to_string(),
+                actions: vec![CheatAction::Write {
+                    address: 0x21ff,
+                    value: 3,
+                    condition: Some(Condition { address: 0x20ef, comparison: Comparison::Equal, value: 1 }),
+                }],
+            },
+            Cheat {
+                name: "Patch".to_string(),
+                actions: vec![
+                    CheatAction::Patch { address: 0x060f, value: 0 },
+                    CheatAction::Patch { address: 0x0610, value: 1 },
+                ],
+            },
+        ], cheats);
+        assert_eq!(3, parse_cheats(INVADERS_CHEATS).unwrap().len());
+    }
+
+    #[test]
+    fn reject_invalid_cheat_files() {
+        let error = |text: &str| parse_cheats(text).unwrap_err().to_string();
+        assert_eq!("line 1: action outside of a cheat", error("write 0x2000 1"));
+        assert_eq!("line 2: invalid value", error("cheat a\nwrite 0x2000 256"));
+        assert_eq!("line 2: invalid comparison", error("cheat a\nwrite 0x2000 1 if 0x2001 = 1"));
+        assert_eq!("line 2: unknown action poke", error("cheat a\npoke 0x2000 1"));
+        assert!(parse_cheats("cheat a\npatch 0x2000").is_err());
+        assert!(parse_cheats("cheat").is_err());
+    }
+
+    #[test]
+    fn writes_are_made_at_every_frame_when_the_condition_holds() {
+        let cheats = parse_cheats("cheat a\nwrite 0x6000 5 if 0x2001 > 1\ncheat b\nwrite 0x2002 7").unwrap();
+        let mut engine = CheatEngine::new(cheats);
+        let mut memory = MemoryMap::new(&INVADERS, &[]);
+        assert!(!engine.any_enabled());
+        assert_eq!(Some(true), engine.toggle(0, &mut memory));
+        assert!(engine.any_enabled());
+        engine.apply(&mut memory);
+        assert_eq!(0, memory.peek(0x2000));
+        memory.write(0x2001, 2);
+        engine.apply(&mut memory);
+        assert_eq!(5, memory.peek(0x2000));
+        // the disabled cheat does nothing
+        assert_eq!(0, memory.peek(0x2002));
+
+        memory.write(0x2000, 0);
+        assert_eq!(Some(false), engine.toggle(0, &mut memory));
+        engine.apply(&mut memory);
+        assert_eq!(0, memory.peek(0x2000));
+        assert_eq!(None, engine.toggle(2, &mut memory));
+    }
+
+    #[test]
+    fn patches_are_reverted_when_disabled() {
+        let mut engine = CheatEngine::new(parse_cheats("cheat a\npatch 0x0001 0xaa 0xbb").unwrap());
+        let mut memory = MemoryMap::new(&INVADERS, &[1, 2, 3]);
+        engine.set_enabled(0, true, &mut memory);
+        assert!(engine.is_enabled(0));
+        assert_eq!(&[1, 0xaa, 0xbb], &memory.bytes()[0..3]);
+        // the ROM is still write protected for the cpu
+        memory.write(0x0001, 0);
+        assert_eq!(0xaa, memory.peek(0x0001));
+        engine.set_enabled(0, true, &mut memory);
+        engine.set_enabled(0, false, &mut memory);
+        assert_eq!(&[1, 2, 3], &memory.bytes()[0..3]);
+    }
+
+    #[test]
+    fn import_mame_cheat_file() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- Space Invaders -->
+<mamecheat version="1">
+  <cheat desc="Infinite Lives">
+    <script state="run">
+      <action condition="maincpu.pb@20EF == 01">maincpu.pb@21FF=03</action>
+    </script>
+  </cheat>
+  <cheat desc="Invincibility">
+    <comment>&lt;patched&gt; &amp; restored</comment>
+    <script state="on">
+      <action>temp0=maincpu.rb@060F</action>
+      <action>maincpu.rb@060F=00</action>
+    </script>
+    <script state="off">
+      <action>maincpu.rb@060F=temp0</action>
+    </script>
+  </cheat>
+  <cheat desc="Select Level">
+    <parameter min="1" max="9" step="1"/>
+    <script state="run">
+      <action>maincpu.pb@2067=param</action>
+    </script>
+  </cheat>
+  <cheat/>
+</mamecheat>"#;
+        let imported = import_mame_cheats(xml).unwrap();
+        assert_eq!(vec![
+            Cheat {
+                name: "Infinite Lives".to_string(),
+                actions: vec![CheatAction::Write {
+                    address: 0x21ff,
+                    value: 3,
+                    condition: Some(Condition { address: 0x20ef, comparison: Comparison::Equal, value: 1 }),
+                }],
+            },
+            Cheat {
+                name: "Invincibility".to_string(),
+                actions: vec![CheatAction::Patch { address: 0x060f, value: 0 }],
+            },
+        ], imported.cheats);
+        assert_eq!(vec!["Select Level".to_string()], imported.skipped);
+
+        assert!(import_mame_cheats("<cheat desc=\"a\">").is_ok());
+        assert!(import_mame_cheats("<cheat desc=\"a").is_err());
+        assert!(import_mame_cheats("<cheat desc=\"&bad;\">").is_err());
+    }
+}
@@ -0,0 +1,738 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use intel_8080_emu::opcode::{ self, OpCode, Register, Reg16 };
+use intel_8080_emu::opcode::OpCode::*;
+use intel_8080_emu::proc_state::{ DataBus, Flags, Registers };
+
+use memory::MemoryMap;
+use state::{ StateError, StateReader, StateWriter };
+
+/// Clock cycles (states) of every opcode, from the Intel 8080 programmer's manual. Conditional calls
+/// and returns take `BRANCH_TAKEN_CYCLES` more when the condition is met.
+const CYCLES: [u8; 256] = [
+    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4,
+    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4,
+    4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4,
+    4, 10, 13, 5, 10, 10, 10, 4, 4, 10, 13, 5, 5, 5, 7, 4,
+    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5,
+    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5,
+    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5,
+    7, 7, 7, 7, 7, 7, 7, 7, 5, 5, 5, 5, 5, 5, 7, 5,
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+    5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11,
+    5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11,
+    5, 10, 10, 18, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17, 7, 11,
+    5, 10, 10, 4, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17, 7, 11,
+];
+const BRANCH_TAKEN_CYCLES: u64 = 6;
+const INTERRUPT_CYCLES: u64 = 11;
+/// Cycles spent by a halted processor on each call to `emulate`, waiting for an interrupt.
+const HALTED_CYCLES: u64 = 4;
+
+/// Intel 8080 processor.
+///
+/// It follows the implementation of `intel_8080_emu::proc_state::Proc8080` (same opcodes and
+/// flags) but counts clock cycles rather than machine cycles, goes through the `MemoryMap` of the
+/// board for every access, and gives write access to the memory and to the processor state, which is
+/// needed to save and restore the emulation.
+pub struct Cpu8080<Bus: DataBus> {
+    flags: Flags,
+    registers: Registers,
+    memory: MemoryMap,
+    cycles: u64,
+    interrupt_enabled: bool,
+    stopped: bool,
+    data_bus: Bus,
+}
+
+impl<Bus: DataBus> Cpu8080<Bus> {
+
+    /// Builds a processor with the memory `mem` (ROM and RAM) and the `DataBus` used by the `IN`
+    /// and `OUT` instructions. The program counter starts at 0.
+    pub fn new(mem: MemoryMap, data_bus: Bus) -> Cpu8080<Bus> {
+        Cpu8080 {
+            flags: Flags::default(),
+            registers: Registers::default(),
+            memory: mem,
+            cycles: 0,
+            interrupt_enabled: false,
+            stopped: false,
+            data_bus,
+        }
+    }
+
+    pub fn flags(&self) -> &Flags {
+        &self.flags
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// The whole memory, see `MemoryMap::bytes`
+    pub fn memory(&self) -> &[u8] {
+        self.memory.bytes()
+    }
+
+    /// The whole memory, without write protection, see `MemoryMap::bytes_mut`
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.memory.bytes_mut()
+    }
+
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.memory
+    }
+
+    pub fn memory_map_mut(&mut self) -> &mut MemoryMap {
+        &mut self.memory
+    }
+
+    pub fn data_bus(&self) -> &Bus {
+        &self.data_bus
+    }
+
+    pub fn data_bus_mut(&mut self) -> &mut Bus {
+        &mut self.data_bus
+    }
+
+    /// Cycles elapsed since the cpu was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn interrupt_enabled(&self) -> bool {
+        self.interrupt_enabled
+    }
+
+    /// Whether the processor executed a `HLT` and waits for an interrupt
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Reads the next opcode and applies it.
+    pub fn emulate(&mut self) {
+        if self.stopped {
+            self.cycles += HALTED_CYCLES;
+            return;
+        }
+        let pc = self.registers.pc;
+        // the opcodes are not reported to the hooks of the memory
+        let memory = &self.memory;
+        let opcode = opcode::read_opcode(memory.unmirrored(pc))
+            .unwrap_or_else(|_| {
+                let bytes = [memory.peek(pc), memory.peek(pc.wrapping_add(1)), memory.peek(pc.wrapping_add(2))];
+                opcode::read_opcode_safe(&bytes)
+            });
+        self.registers.pc = pc.wrapping_add(opcode.size());
+        self.cycles += CYCLES[memory.peek(pc) as usize] as u64;
+        self.apply_op(opcode);
+    }
+
+    /// Lets the time pass like a halted processor, without executing anything, for a machine
+    /// which is stopped.
+    pub fn idle(&mut self) {
+        self.cycles += HALTED_CYCLES;
+    }
+
+    /// Runs a `RST rst_value` instruction if the interrupts are enabled, and wakes the processor
+    /// up if it was halted.
+    ///
+    /// # Panics
+    /// If `rst_value` is greater than 7.
+    pub fn interrupt(&mut self, rst_value: u8) {
+        assert!(rst_value <= 7, "RST value are only from 0 to 7");
+        self.stopped = false;
+        if self.interrupt_enabled {
+            self.interrupt_enabled = false;
+            self.cycles += INTERRUPT_CYCLES;
+            self.apply_op(Rst(rst_value));
+        }
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        let r = &self.registers;
+        for value in &[r.a, r.b, r.c, r.d, r.e, r.h, r.l] {
+            writer.u8(*value);
+        }
+        writer.u16(r.sp);
+        writer.u16(r.pc);
+        writer.u8(self.flags.to_processor_status_word());
+        writer.bool(self.interrupt_enabled);
+        writer.bool(self.stopped);
+        writer.u64(self.cycles);
+    }
+
+    pub(crate) fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let registers = Registers {
+            a: reader.u8()?,
+            b: reader.u8()?,
+            c: reader.u8()?,
+            d: reader.u8()?,
+            e: reader.u8()?,
+            h: reader.u8()?,
+            l: reader.u8()?,
+            sp: reader.u16()?,
+            pc: reader.u16()?,
+        };
+        let flags = Flags::from_processor_status_word(reader.u8()?);
+        let interrupt_enabled = reader.bool()?;
+        let stopped = reader.bool()?;
+        let cycles = reader.u64()?;
+
+        self.registers = registers;
+        self.flags = flags;
+        self.interrupt_enabled = interrupt_enabled;
+        self.stopped = stopped;
+        self.cycles = cycles;
+        Ok(())
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.memory.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.memory.write(addr, value);
+    }
+
+    fn hl(&self) -> u16 {
+        self.registers.reg_16_val(Reg16::H)
+    }
+
+    fn apply_op(&mut self, op: OpCode) {
+        match op {
+            Nop => (),
+
+            // Data transfer
+            Mov(reg1, reg2) => self.registers.apply_mov(reg1, reg2),
+            MovFromM(reg) => {
+                let value = self.read(self.hl());
+                self.registers.set_reg_val(reg, value);
+            },
+            MovToM(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.write(self.hl(), value);
+            },
+            Mvi(reg, value) => self.registers.set_reg_val(reg, value),
+            MviM(value) => self.write(self.hl(), value),
+            Lxi(reg, value) => self.registers.set_reg_16_val(reg, value),
+            Lda(addr) => {
+                let value = self.read(addr);
+                self.registers.set_reg_val(Register::A, value);
+            },
+            Sta(addr) => {
+                let value = self.registers.reg_val(Register::A);
+                self.write(addr, value);
+            },
+            Lhld(addr) => {
+                let value = self.read16(addr);
+                self.registers.set_reg_16_val(Reg16::H, value);
+            },
+            Shld(addr) => {
+                let value = self.hl();
+                self.write16(addr, value);
+            },
+            LdaxB => self.ldax(Reg16::B),
+            LdaxD => self.ldax(Reg16::D),
+            StaxB => self.stax(Reg16::B),
+            StaxD => self.stax(Reg16::D),
+            Xchg => {
+                let d = self.registers.reg_16_val(Reg16::D);
+                let h = self.hl();
+                self.registers.set_reg_16_val(Reg16::D, h);
+                self.registers.set_reg_16_val(Reg16::H, d);
+            },
+
+            // Arithmetic
+            Add(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.add_to_accumulator(value as i16);
+            },
+            AddM => {
+                let value = self.read(self.hl());
+                self.add_to_accumulator(value as i16);
+            },
+            Adi(value) => self.add_to_accumulator(value as i16),
+            Adc(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.add_with_carry(value);
+            },
+            AdcM => {
+                let value = self.read(self.hl());
+                self.add_with_carry(value);
+            },
+            Aci(value) => self.add_with_carry(value),
+            Sub(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.add_to_accumulator(-(value as i16));
+            },
+            SubM => {
+                let value = self.read(self.hl());
+                self.add_to_accumulator(-(value as i16));
+            },
+            Sui(value) => self.add_to_accumulator(-(value as i16)),
+            Sbb(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.sub_with_borrow(value);
+            },
+            SbbM => {
+                let value = self.read(self.hl());
+                self.sub_with_borrow(value);
+            },
+            Sbi(value) => self.sub_with_borrow(value),
+            Inr(reg) => {
+                let value = self.registers.reg_val(reg);
+                let result = (value as i16) + 1;
+                self.registers.set_reg_val(reg, result as u8);
+                self.flags.set_flags_except_carry_for(value, result);
+            },
+            InrM => {
+                let addr = self.hl();
+                let result = self.read(addr).wrapping_add(1);
+                self.write(addr, result);
+                self.flags.set_flags_except_carry_for(result, result as i16);
+            },
+            Dcr(reg) => {
+                let value = self.registers.reg_val(reg);
+                let result = (value as i16).wrapping_sub(1);
+                self.registers.set_reg_val(reg, result as u8);
+                self.flags.set_flags_except_carry_for(value, result);
+            },
+            DcrM => {
+                let addr = self.hl();
+                let result = self.read(addr).wrapping_sub(1);
+                self.write(addr, result);
+                self.flags.set_flags_except_carry_for(result, result as i16);
+            },
+            Inx(reg) => {
+                let value = self.registers.reg_16_val(reg).wrapping_add(1);
+                self.registers.set_reg_16_val(reg, value);
+            },
+            Dcx(reg) => {
+                let value = self.registers.reg_16_val(reg).wrapping_sub(1);
+                self.registers.set_reg_16_val(reg, value);
+            },
+            Dad(reg) => {
+                let result = self.hl() as u32 + self.registers.reg_16_val(reg) as u32;
+                self.registers.set_reg_16_val(Reg16::H, result as u16);
+                self.flags.cy = result > 0xffff;
+            },
+            Daa => self.decimal_adjust_accumulator(),
+
+            // Logical
+            Ana(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.apply_and(value);
+            },
+            AnaM => {
+                let value = self.read(self.hl());
+                self.apply_and(value);
+            },
+            Ani(value) => {
+                self.apply_and(value);
+                self.flags.ac = false;
+            },
+            Xra(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.apply_xor(value);
+            },
+            XraM => {
+                let value = self.read(self.hl());
+                self.apply_xor(value);
+            },
+            Xri(value) => self.apply_xor(value),
+            Ora(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.apply_or(value);
+            },
+            OraM => {
+                let value = self.read(self.hl());
+                self.apply_or(value);
+            },
+            Ori(value) => self.apply_or(value),
+            Cmp(reg) => {
+                let value = self.registers.reg_val(reg);
+                self.compare(value);
+            },
+            CmpM => {
+                let value = self.read(self.hl());
+                self.compare(value);
+            },
+            Cpi(value) => self.compare(value),
+            Rlc => {
+                let a = self.registers.a;
+                self.registers.a = a.rotate_left(1);
+                self.flags.cy = (a & 0x80) != 0;
+            },
+            Rrc => {
+                let a = self.registers.a;
+                self.registers.a = a.rotate_right(1);
+                self.flags.cy = (a & 0x01) != 0;
+            },
+            Ral => {
+                let a = self.registers.a;
+                self.registers.a = (a << 1) | self.flags.cy as u8;
+                self.flags.cy = (a & 0x80) != 0;
+            },
+            Rar => {
+                let a = self.registers.a;
+                self.registers.a = (a >> 1) | ((self.flags.cy as u8) << 7);
+                self.flags.cy = (a & 0x01) != 0;
+            },
+            Cma => self.registers.a = !self.registers.a,
+            Cmc => self.flags.cy = !self.flags.cy,
+            Stc => self.flags.cy = true,
+
+            // Branch
+            Jmp(addr) => self.registers.pc = addr,
+            Jnz(addr) => if !self.flags.z { self.registers.pc = addr },
+            Jz(addr) => if self.flags.z { self.registers.pc = addr },
+            Jnc(addr) => if !self.flags.cy { self.registers.pc = addr },
+            Jc(addr) => if self.flags.cy { self.registers.pc = addr },
+            Jpo(addr) => if !self.flags.p { self.registers.pc = addr },
+            Jpe(addr) => if self.flags.p { self.registers.pc = addr },
+            Jp(addr) => if !self.flags.s { self.registers.pc = addr },
+            Jm(addr) => if self.flags.s { self.registers.pc = addr },
+            Call(addr) => self.apply_call(addr),
+            Cnz(addr) => self.conditional_call(!self.flags.z, addr),
+            Cz(addr) => self.conditional_call(self.flags.z, addr),
+            Cnc(addr) => self.conditional_call(!self.flags.cy, addr),
+            Cc(addr) => self.conditional_call(self.flags.cy, addr),
+            Cpo(addr) => self.conditional_call(!self.flags.p, addr),
+            Cpe(addr) => self.conditional_call(self.flags.p, addr),
+            Cp(addr) => self.conditional_call(!self.flags.s, addr),
+            Cm(addr) => self.conditional_call(self.flags.s, addr),
+            Ret => self.apply_return(),
+            Rnz => self.conditional_return(!self.flags.z),
+            Rz => self.conditional_return(self.flags.z),
+            Rnc => self.conditional_return(!self.flags.cy),
+            Rc => self.conditional_return(self.flags.cy),
+            Rpo => self.conditional_return(!self.flags.p),
+            Rpe => self.conditional_return(self.flags.p),
+            Rp => self.conditional_return(!self.flags.s),
+            Rm => self.conditional_return(self.flags.s),
+            Rst(value) => self.apply_call((value * 8) as u16),
+            Pchl => self.registers.pc = self.hl(),
+
+            // Stack, I/O, and Machine Control
+            Push(reg) => {
+                let value = self.registers.reg_16_val(reg);
+                self.push_stack(value);
+            },
+            PushPSW => {
+                let value = ((self.registers.a as u16) << 8) | self.flags.to_processor_status_word() as u16;
+                self.push_stack(value);
+            },
+            Pop(reg) => {
+                let value = self.pop_stack();
+                self.registers.set_reg_16_val(reg, value);
+            },
+            PopPSW => {
+                let value = self.pop_stack();
+                self.registers.a = (value >> 8) as u8;
+                self.flags = Flags::from_processor_status_word(value as u8);
+            },
+            Xthl => {
+                let sp = self.registers.sp;
+                let h = self.hl();
+                let value = self.read16(sp);
+                self.write16(sp, h);
+                self.registers.set_reg_16_val(Reg16::H, value);
+            },
+            Sphl => self.registers.sp = self.hl(),
+            In(port) => self.registers.a = self.data_bus.read_port(port),
+            Out(port) => self.data_bus.write_port(port, self.registers.a),
+            Ei => self.interrupt_enabled = true,
+            Di => self.interrupt_enabled = false,
+            Hlt => self.stopped = true,
+        }
+    }
+
+    fn read16(&mut self, addr: u16) -> u16 {
+        self.read(addr) as u16 | ((self.read(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn write16(&mut self, addr: u16, value: u16) {
+        self.write(addr, value as u8);
+        self.write(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn ldax(&mut self, reg: Reg16) {
+        let value = self.read(self.registers.reg_16_val(reg));
+        self.registers.a = value;
+    }
+
+    fn stax(&mut self, reg: Reg16) {
+        let addr = self.registers.reg_16_val(reg);
+        let value = self.registers.a;
+        self.write(addr, value);
+    }
+
+    fn add_to_accumulator(&mut self, value: i16) {
+        let a = self.registers.a;
+        let result = (a as i16).wrapping_add(value);
+        self.registers.a = result as u8;
+        self.flags.set_flags_for(a, result);
+    }
+
+    fn add_with_carry(&mut self, value: u8) {
+        let carry = self.flags.cy as u8;
+        self.add_to_accumulator(value.wrapping_add(carry) as i16);
+    }
+
+    fn sub_with_borrow(&mut self, value: u8) {
+        let carry = self.flags.cy as u8;
+        self.add_to_accumulator(-(value.wrapping_add(carry) as i16));
+    }
+
+    fn decimal_adjust_accumulator(&mut self) {
+        let a = self.registers.a as i16;
+        let low_correction = if self.flags.ac || a & 0x0f > 9 { 0x06 } else { 0x00 };
+        let high_correction = if self.flags.cy || a + low_correction > 0x90 { 0x60 } else { 0x00 };
+        self.add_to_accumulator(low_correction + high_correction)
+    }
+
+    fn apply_and(&mut self, value: u8) {
+        let a = self.registers.a;
+        let result = a & value;
+        self.registers.a = result;
+        self.flags.set_flags_for(a, result as i16);
+    }
+
+    fn apply_xor(&mut self, value: u8) {
+        let a = self.registers.a;
+        let result = a ^ value;
+        self.registers.a = result;
+        self.flags.set_flags_for(a, result as i16);
+        self.flags.ac = false;
+    }
+
+    fn apply_or(&mut self, value: u8) {
+        let a = self.registers.a;
+        let result = a | value;
+        self.registers.a = result;
+        self.flags.set_flags_for(a, result as i16);
+        self.flags.ac = false;
+    }
+
+    fn compare(&mut self, value: u8) {
+        let a = self.registers.a;
+        self.flags.set_flags_for(a, a as i16 - value as i16);
+    }
+
+    fn apply_call(&mut self, addr: u16) {
+        let pc = self.registers.pc;
+        self.push_stack(pc);
+        self.registers.pc = addr;
+    }
+
+    fn apply_return(&mut self) {
+        self.registers.pc = self.pop_stack();
+    }
+
+    fn conditional_call(&mut self, condition: bool, addr: u16) {
+        if condition {
+            self.cycles += BRANCH_TAKEN_CYCLES;
+            self.apply_call(addr);
+        }
+    }
+
+    fn conditional_return(&mut self, condition: bool) {
+        if condition {
+            self.cycles += BRANCH_TAKEN_CYCLES;
+            self.apply_return();
+        }
+    }
+
+    fn push_stack(&mut self, value: u16) {
+        let sp = self.registers.sp.wrapping_sub(2);
+        self.write16(sp, value);
+        self.registers.sp = sp;
+    }
+
+    fn pop_stack(&mut self) -> u16 {
+        let sp = self.registers.sp;
+        self.registers.sp = sp.wrapping_add(2);
+        self.read16(sp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use intel_8080_emu::opcode;
+    use intel_8080_emu::proc_state::Proc8080;
+    use cpu::*;
+
+    struct DummyBus;
+
+    impl DataBus for DummyBus {
+        fn read_port(&self, port: u8) -> u8 {
+            port
+        }
+
+        fn write_port(&mut self, _port: u8, _value: u8) {}
+    }
+
+    fn cpu_with_program(program: &[u8]) -> Cpu8080<DummyBus> {
+        Cpu8080::new(MemoryMap::flat(program), DummyBus)
+    }
+
+    #[test]
+    fn call_and_return() {
+        // LXI SP,0x0080 ; CALL 0x0010 ; ... ; 0x0010: MVI A,0x42 ; RET
+        let mut cpu = cpu_with_program(&[0x31, 0x80, 0x00, 0xcd, 0x10, 0x00]);
+        cpu.memory_mut()[0x10..0x13].copy_from_slice(&[0x3e, 0x42, 0xc9]);
+        for _ in 0..4 {
+            cpu.emulate();
+        }
+        assert_eq!(0x42, cpu.registers().a);
+        assert_eq!(0x0006, cpu.registers().pc);
+        assert_eq!(0x0080, cpu.registers().sp);
+        assert_eq!(10 + 17 + 7 + 10, cpu.cycles());
+    }
+
+    #[test]
+    fn halt_waits_for_interrupt() {
+        // LXI SP,0x0080 ; EI ; HLT
+        let mut cpu = cpu_with_program(&[0x31, 0x80, 0x00, 0xfb, 0x76]);
+        for _ in 0..3 {
+            cpu.emulate();
+        }
+        assert!(cpu.is_stopped());
+        let cycles = cpu.cycles();
+        cpu.emulate();
+        assert_eq!(0x0005, cpu.registers().pc);
+        assert!(cpu.cycles() > cycles);
+
+        cpu.interrupt(1);
+        assert!(!cpu.is_stopped());
+        assert_eq!(0x0008, cpu.registers().pc);
+    }
+
+    #[test]
+    fn conditional_branches_cost_more_when_taken() {
+        // XRA A (sets Z) ; RNZ ; RZ
+        let mut cpu = cpu_with_program(&[0xaf, 0xc0, 0xc8]);
+        cpu.registers.sp = 0x80;
+        cpu.emulate();
+        cpu.emulate();
+        assert_eq!(4 + 5, cpu.cycles());
+        cpu.emulate();
+        assert_eq!(4 + 5 + 11, cpu.cycles());
+    }
+
+    #[test]
+    fn in_reads_the_data_bus() {
+        // IN 0x03
+        let mut cpu = cpu_with_program(&[0xdb, 0x03]);
+        cpu.emulate();
+        assert_eq!(0x03, cpu.registers().a);
+    }
+
+    /// `Proc8080`, the implementation `Cpu8080` was forked from, and `Cpu8080` run side by side.
+    struct Lockstep<Bus: DataBus> {
+        original: Proc8080<Bus>,
+        cpu: Cpu8080<Bus>,
+    }
+
+    impl<Bus: DataBus> Lockstep<Bus> {
+        /// Emulates the next instruction on both (or the interrupt `rst`), and checks that they end
+        /// in the same state. `Proc8080` counts machine cycles, which last 3 to 5 clock cycles.
+        fn step(&mut self, rst: Option<u8>) {
+            let (machine_cycles, cycles) = (self.original.cycles(), self.cpu.cycles());
+            let pc = self.cpu.registers().pc;
+            match rst {
+                Some(rst) => {
+                    self.original.interrupt(rst);
+                    self.cpu.interrupt(rst);
+                },
+                None => {
+                    self.original.emulate();
+                    self.cpu.emulate();
+                },
+            }
+            let opcode = self.cpu.memory()[pc as usize];
+            assert_eq!(
+                format!("{:?} {:?}", self.original.registers(), self.original.flags()),
+                format!("{:?} {:?}", self.cpu.registers(), self.cpu.flags()),
+                "opcode {:02x} at {:04x}, interrupt {:?}", opcode, pc, rst);
+            let (machine_cycles, cycles) = (self.original.cycles() - machine_cycles, self.cpu.cycles() - cycles);
+            assert!(
+                3 * machine_cycles <= cycles && cycles <= 5 * machine_cycles,
+                "opcode {:02x} at {:04x}: {} cycles for {} machine cycles", opcode, pc, cycles, machine_cycles);
+        }
+    }
+
+    /// Opcodes which do not branch, nor stop the processor
+    fn is_linear(opcode: u8) -> bool {
+        let branch = opcode >= 0xc0 && matches!(opcode & 0x07, 0 | 2 | 4 | 7)
+            || matches!(opcode, 0xc3 | 0xc9 | 0xcb | 0xcd | 0xd9 | 0xdd | 0xe9 | 0xed | 0xfd);
+        !branch && opcode != 0x76
+    }
+
+    #[test]
+    fn opcodes_match_the_original_implementation() {
+        // every value of the accumulator and of the carries, with the other registers and the
+        // operands pointing after the program
+        for opcode in (0..=255u8).filter(|&opcode| is_linear(opcode)) {
+            for carries in 0..4u8 {
+                let mut program = Vec::new();
+                for a in 0..=255u8 {
+                    let i = a.wrapping_mul(37);
+                    let psw = ((a as u16) << 8) | (carries & 1) as u16 | (((carries >> 1) & 1) << 4) as u16 | 0x02;
+                    program.extend_from_slice(&[0x31, 0x00, 0xf0]);                           // LXI SP,0xf000
+                    program.extend_from_slice(&[0x21, psw as u8, (psw >> 8) as u8, 0xe5, 0xf1]); // PUSH psw ; POP PSW
+                    program.extend_from_slice(&[0x01, i.wrapping_mul(3), 0x80 | i]);           // LXI B
+                    program.extend_from_slice(&[0x11, i.wrapping_mul(5), 0x80 | a]);           // LXI D
+                    program.extend_from_slice(&[0x21, i.wrapping_mul(7), 0x80 | i.wrapping_add(a)]); // LXI H
+                    // only the operands of the opcode, the next bytes would be run as opcodes
+                    let bytes = [opcode, i, 0x80 | a];
+                    let size = opcode::read_opcode(&bytes).unwrap().size() as usize;
+                    program.extend_from_slice(&bytes[..size]);
+                }
+                let original = Proc8080::new(MemoryMap::flat(&program).bytes().to_vec().into_boxed_slice(), DummyBus);
+                let mut lockstep = Lockstep { original, cpu: cpu_with_program(&program) };
+                while (lockstep.cpu.registers().pc as usize) < program.len() {
+                    lockstep.step(None);
+                }
+                assert!(lockstep.original.memory() == lockstep.cpu.memory(), "opcode {:02x}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn space_invaders_runs_as_on_the_original_implementation() {
+        use board::INVADERS;
+        use frame::{ MID_SCREEN_INTERRUPT, VBLANK_INTERRUPT };
+        use machine::{ SpaceInvaderDataBus, SpaceInvaderMachine, CPU_FREQUENCY };
+        use rom::is_known_rom;
+        use INVADERS_ROM;
+
+        if !is_known_rom(INVADERS_ROM) {
+            // built without the ROM
+            return;
+        }
+        let mut memory = vec![0; 0x10000];
+        memory[0..INVADERS_ROM.len()].copy_from_slice(INVADERS_ROM);
+        let bus = || SpaceInvaderDataBus::new(SpaceInvaderMachine::with_driver(&INVADERS));
+        let mut lockstep = Lockstep {
+            original: Proc8080::new(memory.into_boxed_slice(), bus()),
+            cpu: Cpu8080::new(MemoryMap::new(&INVADERS, INVADERS_ROM), bus()),
+        };
+        // the boot, the attract mode and the first demo game
+        let half_frame = CPU_FREQUENCY / 120;
+        for interrupt in 1..=(2 * 120) {
+            while lockstep.cpu.cycles() < interrupt * half_frame {
+                lockstep.step(None);
+            }
+            lockstep.step(Some(if interrupt % 2 == 1 { MID_SCREEN_INTERRUPT } else { VBLANK_INTERRUPT }));
+            assert!(lockstep.original.memory()[0x2000..0x4000] == lockstep.cpu.memory()[0x2000..0x4000]);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+/*
+ The game was designed for a CRT: the phosphor stays lit a few frames after the beam passes, which
+ hides the flicker of the sprites drawn every other frame, and the image is blurred and curved by
+ the tube. The filter reproduces this on the CPU, after the frame is decoded:
+
+    decoded frame -> phosphor persistence -> scanlines -> glow -> curvature -> output
+
+ The monitor is mounted rotated in the cabinet, so the scanlines are vertical in the final image.
+*/
+
+/// Settings of the `CrtFilter`, every effect is disabled at 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrtSettings {
+    /// Part of the brightness of a pixel kept from one frame to the next (0 to 1)
+    pub phosphor_decay: f32,
+    /// Darkening of every other scanline (0 to 1)
+    pub scanlines: f32,
+    /// Strength of the light bleeding around lit pixels (0 to 1)
+    pub glow: f32,
+    /// Barrel distortion of the tube (0 to 1, 0.1 already looks curved)
+    pub curvature: f32,
+}
+
+impl CrtSettings {
+    /// No post-processing at all
+    pub fn disabled() -> CrtSettings {
+        CrtSettings { phosphor_decay: 0.0, scanlines: 0.0, glow: 0.0, curvature: 0.0 }
+    }
+
+    /// Settings looking roughly like the cabinet monitor
+    pub fn arcade() -> CrtSettings {
+        CrtSettings { phosphor_decay: 0.6, scanlines: 0.3, glow: 0.4, curvature: 0.08 }
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        *self == CrtSettings::disabled()
+    }
+
+    fn clamped(self) -> CrtSettings {
+        CrtSettings {
+            phosphor_decay: self.phosphor_decay.clamp(0.0, 1.0),
+            scanlines: self.scanlines.clamp(0.0, 1.0),
+            glow: self.glow.clamp(0.0, 1.0),
+            curvature: self.curvature.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Default for CrtSettings {
+    fn default() -> CrtSettings {
+        CrtSettings::disabled()
+    }
+}
+
+/// Weights of the blur used for the glow (binomial kernel, sum is 16)
+const GLOW_KERNEL: [f32; 5] = [1.0, 4.0, 6.0, 4.0, 1.0];
+
+/// CRT post-processing of RGBA frames. `apply` must be called once per emulated frame, the
+/// phosphor decay is expressed per frame.
+pub struct CrtFilter {
+    width: usize,
+    height: usize,
+    settings: CrtSettings,
+    /// Brightness of the phosphor, RGB in [0, 1]
+    phosphor: Vec<f32>,
+    work: Vec<f32>,
+    blurred: Vec<f32>,
+    /// For every output pixel, the index of the source pixel (`None` outside of the tube)
+    curvature_map: Vec<Option<usize>>,
+    output: Vec<u8>,
+}
+
+impl CrtFilter {
+    pub fn new(width: usize, height: usize, settings: CrtSettings) -> CrtFilter {
+        let mut filter = CrtFilter {
+            width,
+            height,
+            settings: CrtSettings::disabled(),
+            phosphor: vec![0.0; width * height * 3],
+            work: vec![0.0; width * height * 3],
+            blurred: vec![0.0; width * height * 3],
+            curvature_map: Vec::new(),
+            output: vec![255; width * height * 4],
+        };
+        filter.set_settings(settings);
+        filter
+    }
+
+    pub fn settings(&self) -> CrtSettings {
+        self.settings
+    }
+
+    /// Changes the settings, each value is clamped between 0 and 1.
+    pub fn set_settings(&mut self, settings: CrtSettings) {
+        self.settings = settings.clamped();
+        self.curvature_map = curvature_map(self.width, self.height, self.settings.curvature);
+    }
+
+    /// Processes `frame` (RGBA, the size given to `new`) and returns the output, in RGBA as well.
+    pub fn apply(&mut self, frame: &[u8]) -> &[u8] {
+        let decay = self.settings.phosphor_decay;
+        for (phosphor, px) in self.phosphor.chunks_mut(3).zip(frame.chunks(4)) {
+            for channel in 0..3 {
+                let lit = px[channel] as f32 / 255.0;
+                phosphor[channel] = lit.max(phosphor[channel] * decay);
+            }
+        }
+
+        let scanline = 1.0 - self.settings.scanlines;
+        for (i, (work, phosphor)) in self.work.chunks_mut(3).zip(self.phosphor.chunks(3)).enumerate() {
+            let factor = if (i % self.width) % 2 == 1 { scanline } else { 1.0 };
+            for channel in 0..3 {
+                work[channel] = phosphor[channel] * factor;
+            }
+        }
+
+        if self.settings.glow > 0.0 {
+            self.add_glow();
+        }
+
+        for (out, source) in self.output.chunks_mut(4).zip(self.curvature_map.iter()) {
+            match *source {
+                Some(index) => {
+                    for (out, value) in out.iter_mut().zip(&self.work[(index * 3)..(index * 3 + 3)]) {
+                        *out = (value.min(1.0) * 255.0) as u8;
+                    }
+                },
+                None => out[0..3].copy_from_slice(&[0, 0, 0]),
+            }
+        }
+        &self.output
+    }
+
+    /// Last output of `apply`
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    fn add_glow(&mut self) {
+        let (width, height) = (self.width, self.height);
+        // horizontal pass in `blurred`, vertical pass added to `work`
+        for y in 0..height {
+            for x in 0..width {
+                for channel in 0..3 {
+                    let mut sum = 0.0;
+                    for (k, weight) in GLOW_KERNEL.iter().enumerate() {
+                        let sx = (x + k).saturating_sub(2).min(width - 1);
+                        sum += weight * self.work[(y * width + sx) * 3 + channel];
+                    }
+                    self.blurred[(y * width + x) * 3 + channel] = sum / 16.0;
+                }
+            }
+        }
+        let glow = self.settings.glow;
+        for y in 0..height {
+            for x in 0..width {
+                for channel in 0..3 {
+                    let mut sum = 0.0;
+                    for (k, weight) in GLOW_KERNEL.iter().enumerate() {
+                        let sy = (y + k).saturating_sub(2).min(height - 1);
+                        sum += weight * self.blurred[(sy * width + x) * 3 + channel];
+                    }
+                    self.work[(y * width + x) * 3 + channel] += glow * sum / 16.0;
+                }
+            }
+        }
+    }
+}
+
+fn curvature_map(width: usize, height: usize, curvature: f32) -> Vec<Option<usize>> {
+    let mut map = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            // coordinates from -1 to 1 from the center of the screen
+            let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+            let distortion = 1.0 + curvature * (u * u + v * v);
+            let sx = ((u * distortion + 1.0) / 2.0 * width as f32).floor();
+            let sy = ((v * distortion + 1.0) / 2.0 * height as f32).floor();
+            let inside = sx >= 0.0 && sy >= 0.0 && (sx as usize) < width && (sy as usize) < height;
+            map.push(if inside { Some(sy as usize * width + sx as usize) } else { None });
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crt::*;
+
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+    fn frame(width: usize, height: usize, lit: &[(usize, usize)]) -> Vec<u8> {
+        let mut pixels: Vec<u8> = BLACK.iter().cloned().cycle().take(width * height * 4).collect();
+        for &(x, y) in lit {
+            let offset = (y * width + x) * 4;
+            pixels[offset..(offset + 4)].copy_from_slice(&WHITE);
+        }
+        pixels
+    }
+
+    fn pixel(output: &[u8], width: usize, x: usize, y: usize) -> &[u8] {
+        let offset = (y * width + x) * 4;
+        &output[offset..(offset + 4)]
+    }
+
+    #[test]
+    fn disabled_filter_copies_the_frame() {
+        let mut filter = CrtFilter::new(8, 8, CrtSettings::disabled());
+        let input = frame(8, 8, &[(1, 2), (5, 5)]);
+        assert_eq!(&input[..], filter.apply(&input));
+    }
+
+    #[test]
+    fn phosphor_decays_over_frames() {
+        let settings = CrtSettings { phosphor_decay: 0.5, ..CrtSettings::disabled() };
+        let mut filter = CrtFilter::new(4, 4, settings);
+        filter.apply(&frame(4, 4, &[(1, 1)]));
+
+        let empty = frame(4, 4, &[]);
+        assert_eq!(&[127, 127, 127, 255], pixel(filter.apply(&empty), 4, 1, 1));
+        assert_eq!(&[63, 63, 63, 255], pixel(filter.apply(&empty), 4, 1, 1));
+    }
+
+    #[test]
+    fn scanlines_darken_every_other_column() {
+        let settings = CrtSettings { scanlines: 0.5, ..CrtSettings::disabled() };
+        let mut filter = CrtFilter::new(4, 1, settings);
+        let output = filter.apply(&frame(4, 1, &[(0, 0), (1, 0)]));
+        assert_eq!(&[255, 255, 255, 255], pixel(output, 4, 0, 0));
+        assert_eq!(&[127, 127, 127, 255], pixel(output, 4, 1, 0));
+    }
+
+    #[test]
+    fn glow_lights_the_neighbours() {
+        let settings = CrtSettings { glow: 1.0, ..CrtSettings::disabled() };
+        let mut filter = CrtFilter::new(5, 5, settings);
+        let output = filter.apply(&frame(5, 5, &[(2, 2)]));
+        assert_eq!(&[255, 255, 255, 255], pixel(output, 5, 2, 2));
+        assert!(pixel(output, 5, 1, 2)[0] > 0);
+        assert!(pixel(output, 5, 2, 1)[0] > 0);
+        assert_eq!(0, pixel(output, 5, 0, 0)[0]);
+    }
+
+    #[test]
+    fn curvature_blackens_the_corners() {
+        let settings = CrtSettings { curvature: 0.3, ..CrtSettings::disabled() };
+        let mut filter = CrtFilter::new(16, 16, settings);
+        let all: Vec<(usize, usize)> = (0..16).flat_map(|y| (0..16).map(move |x| (x, y))).collect();
+        let output = filter.apply(&frame(16, 16, &all));
+        assert_eq!(&[0, 0, 0, 255], pixel(output, 16, 0, 0));
+        assert_eq!(&[255, 255, 255, 255], pixel(output, 16, 8, 8));
+    }
+}
@@ -0,0 +1,566 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{ self, Receiver, Sender };
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+use achievement::{ Achievement, AchievementEngine, AchievementLog };
+use board::{ BoardDriver, Input };
+use cheat::CheatEngine;
+use cpu::Cpu8080;
+use fault::print_faults;
+use frame::VBLANK_INTERRUPT;
+use host::{ AudioSink, Control, HostEvent, HostLoop, SystemClock, VideoSink, FRAME_DURATION };
+use machine::{ InputLatch, SpaceInvaderDataBus, SpaceInvaderMachine, CPU_FREQUENCY };
+use memory::MemoryMap;
+use options::FrontendOptions;
+use record::{ record_frame, stop_recording, toggle_recording, Recorder, VideoFormat };
+use remote::RemoteServer;
+use scheduler::Scheduler;
+use screen::Screen;
+use state::{ load_state, save_state_file, StateError };
+use triple_buffer::{ triple_buffer, TripleBufferReader, TripleBufferWriter };
+
+/// Requests of a frontend to the emulation
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    ToggleMute,
+    SaveState(PathBuf),
+    LoadState(PathBuf),
+    /// Starts a timestamped recording in this format, or finishes the current one
+    ToggleRecording(VideoFormat),
+    /// Saves a screenshot in the directory, with the pixels enlarged by the factor
+    Screenshot(PathBuf, usize),
+    /// Enables or disables the cheat with this index (see `FrontendOptions::load_cheats`)
+    ToggleCheat(usize),
+}
+
+/// Game emulated on its own thread, at the speed of the arcade board.
+///
+/// The frontend presses the buttons through the `InputLatch` of the machine, sends the other
+/// commands through a channel, and takes the frames
+/// (the pixels of the `Screen`) from a triple buffer when it draws. A slow redraw only skips
+/// frames on the display, the game, the sound and the recordings are not slowed down. Errors of
+/// the commands and the faults of the machine (see `FaultPolicy`) are printed. With `--remote`,
+/// the `RemoteServer` drives the emulation too. The frontend shows the achievements unlocked,
+/// which it takes with `unlocked_achievements`.
+pub struct EmulatorThread {
+    options: FrontendOptions,
+    // only `None` once stopped
+    events: Option<Sender<HostEvent>>,
+    remote: Option<RemoteServer>,
+    unlocks: Receiver<Achievement>,
+    inputs: InputLatch,
+    // controls pressed, whose repeated presses send no command
+    held: RefCell<Vec<Control>>,
+    frames: TripleBufferReader<Vec<u8>>,
+    width: usize,
+    height: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EmulatorThread {
+    /// Starts emulating `driver` with its ROM image `rom` (see `FrontendOptions::load_game`), with
+    /// the screen, the sound and the recording set up from `options`.
+    pub fn spawn(options: &FrontendOptions, driver: &'static dyn BoardDriver, rom: &[u8]) -> EmulatorThread {
+        let mut emulator = Emulator::with_options(options, driver, rom);
+        let (unlock_sender, unlocks) = mpsc::channel();
+        emulator.send_unlocks(unlock_sender);
+        let screen = emulator.screen();
+        let (width, height) = (screen.width(), screen.height());
+        let (writer, reader) = triple_buffer(screen.pixels().to_vec());
+        let (sender, receiver) = mpsc::channel();
+        let inputs = emulator.input_latch();
+        let remote = options.remote.as_ref().and_then(|path| {
+            RemoteServer::start(path, options, sender.clone())
+                .map_err(|e| eprintln!("could not start the remote control on {}: {}", path.display(), e))
+                .ok()
+        });
+
+        let thread_options = options.clone();
+        let thread = thread::spawn(move || {
+            // the audio device stays on this thread
+            let audio = thread_options.open_audio();
+            HostLoop::new(emulator, writer, audio, receiver, SystemClock::new()).run()
+        });
+        EmulatorThread {
+            options: options.clone(),
+            events: Some(sender),
+            remote,
+            unlocks,
+            inputs,
+            held: RefCell::new(Vec::new()),
+            frames: reader,
+            width,
+            height,
+            thread: Some(thread),
+        }
+    }
+
+    /// Sends a command, which is ignored once the emulation is stopped.
+    pub fn send(&self, command: Command) {
+        if let Some(ref events) = self.events {
+            let _ = events.send(HostEvent::Command(command));
+        }
+    }
+
+    /// Presses or releases an input (see `SpaceInvaderMachine::set_input`).
+    pub fn set_input(&self, input: Input, pressed: bool) {
+        self.inputs.set_input(input, pressed);
+    }
+
+    /// Presses or releases the inputs of `control`, or sends its command when it is pressed. The
+    /// command is sent once until the control is released, whatever the auto-repeat of the key.
+    pub fn control(&self, control: Control, pressed: bool) {
+        for &input in control.inputs() {
+            self.set_input(input, pressed);
+        }
+        let mut held = self.held.borrow_mut();
+        let was_held = held.contains(&control);
+        if !pressed {
+            held.retain(|&other| other != control);
+        } else if !was_held {
+            held.push(control);
+            if let Some(command) = control.command(&self.options) {
+                self.send(command);
+            }
+        }
+    }
+
+    /// Takes the last emulated frame, returns false if there is no new frame since the last call.
+    pub fn update_frame(&mut self) -> bool {
+        self.frames.update()
+    }
+
+    /// RGBA pixels of the frame taken by `update_frame`
+    pub fn pixels(&self) -> &[u8] {
+        self.frames.buffer()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Achievements unlocked since the last call
+    pub fn unlocked_achievements(&self) -> Vec<Achievement> {
+        self.unlocks.try_iter().collect()
+    }
+
+    /// Stops the emulation and finishes the recording, if any.
+    pub fn stop(&mut self) {
+        // the connections of the remote control keep the channel open
+        self.remote = None;
+        if let Some(events) = self.events.take() {
+            let _ = events.send(HostEvent::Quit);
+        }
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("the emulation stopped with an error");
+            }
+        }
+    }
+}
+
+impl Drop for EmulatorThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The frames of the emulation thread go to the frontend through the triple buffer.
+impl VideoSink for TripleBufferWriter<Vec<u8>> {
+    fn present(&mut self, screen: &Screen) {
+        self.buffer_mut().copy_from_slice(screen.pixels());
+        self.publish();
+    }
+}
+
+/// The machine as the frontends run it: the cpu with the interrupts of the board, the screen, the
+/// cheats and the recording. A `HostLoop` (or the frontend itself) tells it how long to run, and
+/// gives it the `AudioSink` where the sound goes.
+///
+/// At each vertical blank, the cheats are applied, the screen is updated, the frame is recorded
+/// and the achievements are checked. The achievements unlocked are printed, added to the log and
+/// sent to the frontend, they are not checked while a cheat is enabled (their progress is lost).
+/// The faults of the machine are printed (see `FaultPolicy`), like the errors of the commands.
+/// While it is paused, the time given to run it is skipped, and only `step` emulates.
+pub struct Emulator {
+    cpu: Cpu8080<SpaceInvaderDataBus>,
+    inputs: InputLatch,
+    scheduler: Scheduler,
+    screen: Screen,
+    cheats: CheatEngine,
+    recorder: Option<Recorder>,
+    achievements: AchievementEngine,
+    achievement_log: Option<AchievementLog>,
+    unlocks: Option<Sender<Achievement>>,
+    /// Faults already printed
+    faults: u64,
+    paused: bool,
+}
+
+impl Emulator {
+    /// Runs `driver` with its ROM image `rom`, drawing on `screen`, without cheats, recording nor
+    /// achievements.
+    pub fn new(driver: &'static dyn BoardDriver, rom: &[u8], screen: Screen) -> Emulator {
+        let machine = SpaceInvaderMachine::with_driver(driver);
+        let inputs = machine.input_latch();
+        Emulator {
+            cpu: Cpu8080::new(MemoryMap::new(driver, rom), SpaceInvaderDataBus::new(machine)),
+            inputs,
+            scheduler: Scheduler::new(0),
+            screen,
+            cheats: CheatEngine::new(Vec::new()),
+            recorder: None,
+            achievements: AchievementEngine::new(Vec::new()),
+            achievement_log: None,
+            unlocks: None,
+            faults: 0,
+            paused: false,
+        }
+    }
+
+    /// Runs `driver` with its ROM image `rom` (see `FrontendOptions::load_game`), with the screen,
+    /// the fault policy, the cheats, the recording and the achievements of `options`. They are
+    /// disabled if they cannot be loaded or started.
+    pub fn with_options(options: &FrontendOptions, driver: &'static dyn BoardDriver, rom: &[u8]) -> Emulator {
+        let mut emulator = Emulator::new(driver, rom, options.screen(driver));
+        emulator.cpu.data_bus_mut().machine_mut().set_fault_policy(options.fault_policy);
+        emulator.cheats = options.load_cheats(driver, emulator.cpu.memory_map_mut()).unwrap_or_else(|e| {
+            eprintln!("{}, the cheats are disabled", e);
+            CheatEngine::new(Vec::new())
+        });
+        match options.load_achievements(driver) {
+            Ok((achievements, log)) => {
+                emulator.achievements = achievements;
+                emulator.achievement_log = Some(log);
+            },
+            Err(e) => eprintln!("{}, the achievements are disabled", e),
+        }
+        if options.record.is_some() || options.record_audio.is_some() {
+            let (width, height) = (emulator.screen.width(), emulator.screen.height());
+            emulator.recorder = Recorder::create(options.record.as_deref(), options.record_audio.as_deref(), width, height)
+                .map_err(|e| eprintln!("could not start the recording: {}", e))
+                .ok();
+        }
+        emulator
+    }
+
+    pub fn cpu(&self) -> &Cpu8080<SpaceInvaderDataBus> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu8080<SpaceInvaderDataBus> {
+        &mut self.cpu
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+
+    /// Handle on the input ports, to press the buttons from another thread.
+    pub fn input_latch(&self) -> InputLatch {
+        self.inputs.clone()
+    }
+
+    /// Presses or releases an input (see `SpaceInvaderMachine::set_input`).
+    pub fn set_input(&self, input: Input, pressed: bool) {
+        self.inputs.set_input(input, pressed);
+    }
+
+    pub fn cheats(&self) -> &CheatEngine {
+        &self.cheats
+    }
+
+    /// Replaces the cheats, the previous ones are disabled first.
+    pub fn set_cheats(&mut self, cheats: CheatEngine) {
+        for index in 0..self.cheats.cheats().len() {
+            self.cheats.set_enabled(index, false, self.cpu.memory_map_mut());
+        }
+        self.cheats = cheats;
+    }
+
+    /// Enables the cheat `index` if it is disabled, and the other way round. Returns whether it is
+    /// now enabled, `None` if there is no such cheat.
+    pub fn toggle_cheat(&mut self, index: usize) -> Option<bool> {
+        self.cheats.toggle(index, self.cpu.memory_map_mut())
+    }
+
+    pub fn achievements(&self) -> &AchievementEngine {
+        &self.achievements
+    }
+
+    pub fn achievements_mut(&mut self) -> &mut AchievementEngine {
+        &mut self.achievements
+    }
+
+    /// Replaces the achievements, the unlocks are no longer added to the log.
+    pub fn set_achievements(&mut self, achievements: AchievementEngine) {
+        self.achievements = achievements;
+        self.achievement_log = None;
+    }
+
+    /// Sends the achievements unlocked from now on to `unlocks`.
+    pub fn send_unlocks(&mut self, unlocks: Sender<Achievement>) {
+        self.unlocks = Some(unlocks);
+    }
+
+    /// Number of frames since the start (or since the last state loaded)
+    pub fn frames(&self) -> u64 {
+        self.scheduler.frames()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Time to emulate up to the end of the current frame, a whole frame while paused
+    pub fn time_to_next_frame(&self) -> Duration {
+        if self.paused {
+            return FRAME_DURATION;
+        }
+        let cycles = self.scheduler.next_frame().saturating_sub(self.scheduler.target());
+        Duration::from_nanos((cycles * 1_000_000_000).div_ceil(CPU_FREQUENCY))
+    }
+
+    /// Emulates `duration` of the machine, unless it is paused.
+    pub fn run<A: AudioSink + ?Sized>(&mut self, duration: Duration, audio: &mut A) {
+        if !self.paused {
+            self.scheduler.add_time(duration);
+            self.run_scheduled(audio);
+        }
+    }
+
+    /// Emulates up to the end of the frame (the next vertical blank), unless it is paused.
+    pub fn run_frame<A: AudioSink + ?Sized>(&mut self, audio: &mut A) {
+        if !self.paused {
+            self.step(1, audio);
+        }
+    }
+
+    /// Emulates `frames` frames, even while paused (the first one up to the next vertical blank).
+    pub fn step<A: AudioSink + ?Sized>(&mut self, frames: u32, audio: &mut A) {
+        for _ in 0..frames {
+            self.scheduler.add_frame();
+            self.run_scheduled(audio);
+        }
+    }
+
+    /// Restores a state built by `save_state` (e.g. from `cpu`), the next frame starts now.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        load_state(&mut self.cpu, state)?;
+        self.scheduler.resync(self.cpu.cycles());
+        self.achievements.restart();
+        Ok(())
+    }
+
+    /// Executes a command of the frontend, its errors are printed.
+    pub fn execute<A: AudioSink + ?Sized>(&mut self, command: Command, audio: &mut A) {
+        match command {
+            Command::ToggleMute => {
+                let muted = audio.is_muted();
+                audio.set_muted(!muted);
+            },
+            Command::SaveState(path) => {
+                if let Err(e) = save_state_file(&path, &self.cpu) {
+                    eprintln!("could not save the state in {}: {}", path.display(), e);
+                }
+            },
+            Command::LoadState(path) => {
+                let loaded = fs::read(&path).map_err(|e| e.to_string())
+                    .and_then(|state| self.load_state(&state).map_err(|e| e.to_string()));
+                if let Err(e) = loaded {
+                    eprintln!("could not load the state from {}: {}", path.display(), e);
+                }
+            },
+            Command::ToggleRecording(format) => {
+                let (width, height) = (self.screen.width(), self.screen.height());
+                toggle_recording(&mut self.recorder, format, width, height);
+            },
+            Command::Screenshot(directory, scale) => {
+                match self.screen.screenshot(&directory, scale) {
+                    Ok(path) => eprintln!("screenshot saved in {}", path.display()),
+                    Err(e) => eprintln!("could not save the screenshot in {}: {}", directory.display(), e),
+                }
+            },
+            Command::ToggleCheat(index) => {
+                match self.toggle_cheat(index) {
+                    Some(enabled) => {
+                        let name = &self.cheats.cheats()[index].name;
+                        eprintln!("cheat {} {}", name, if enabled { "enabled" } else { "disabled" });
+                    },
+                    None => eprintln!("there is no cheat {}", index + 1),
+                }
+            },
+        }
+    }
+
+    /// Finishes the recording, if any.
+    pub fn finish(&mut self) {
+        stop_recording(&mut self.recorder);
+    }
+
+    fn run_scheduled<A: AudioSink + ?Sized>(&mut self, audio: &mut A) {
+        let cycles = self.cpu.cycles();
+        let halted = self.cpu.data_bus().machine().is_halted();
+        let mut unlocked = Vec::new();
+        // the sound follows the latches at each interrupt, not only at the end of the run
+        let mut played = cycles;
+        {
+            let Emulator { ref mut cpu, ref mut scheduler, ref mut screen, ref cheats, ref mut recorder, ref mut achievements, .. } = *self;
+            scheduler.run(cpu, |cpu, interrupt| {
+                audio.update(cpu.data_bus().machine().sound_latches(), cpu.cycles() - played);
+                played = cpu.cycles();
+                screen.sample_beam(cpu.memory(), interrupt);
+                if interrupt == VBLANK_INTERRUPT {
+                    cheats.apply(cpu.memory_map_mut());
+                    screen.update(cpu.memory());
+                    record_frame(recorder, screen.pixels(), cpu.data_bus().machine().sound_latches());
+                    if cheats.any_enabled() {
+                        achievements.restart();
+                    } else {
+                        unlocked.extend(achievements.update(cpu.memory_map()));
+                    }
+                }
+            });
+        }
+        for index in unlocked {
+            self.announce_unlock(index);
+        }
+        let machine = self.cpu.data_bus().machine();
+        self.faults = print_faults(machine.faults(), self.faults);
+        if machine.is_halted() && !halted {
+            eprintln!("the emulation is halted, the state of the machine can still be saved");
+        }
+        audio.update(machine.sound_latches(), self.cpu.cycles() - played);
+    }
+
+    fn announce_unlock(&self, index: usize) {
+        let achievement = &self.achievements.achievements()[index];
+        eprintln!("achievement unlocked: {} ({})", achievement.name, achievement.description);
+        if let Some(ref log) = self.achievement_log {
+            if let Err(e) = log.append(&achievement.name) {
+                eprintln!("could not add the achievement to {}: {}", log.path().display(), e);
+            }
+        }
+        if let Some(ref unlocks) = self.unlocks {
+            let _ = unlocks.send(achievement.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{ Duration, Instant };
+    use achievement::{ parse_achievements, AchievementEngine };
+    use audio::{ AudioOutput, AudioSettings, NullBackend };
+    use board::{ Input, INVADERS };
+    use cheat::{ parse_cheats, CheatEngine };
+    use crt::CrtSettings;
+    use emulator::*;
+    use options::FrontendOptions;
+    use sound::SoundLatches;
+
+    /// Keeps the cycles of each update
+    #[derive(Default)]
+    struct UpdateLog {
+        updates: Vec<u64>,
+    }
+
+    impl AudioSink for UpdateLog {
+        fn update(&mut self, _latches: SoundLatches, cycles: u64) {
+            self.updates.push(cycles);
+        }
+
+        fn is_muted(&self) -> bool {
+            false
+        }
+
+        fn set_muted(&mut self, _muted: bool) {}
+    }
+
+    #[test]
+    fn frames_come_from_the_emulation_thread() {
+        let options = FrontendOptions::from_args(vec!["--no-audio".to_string()].into_iter()).unwrap();
+        let mut emulator = EmulatorThread::spawn(&options, &INVADERS, ::INVADERS_ROM);
+        assert_eq!(224 * 256 * 4, emulator.pixels().len());
+
+        emulator.set_input(Input::Coin, true);
+        let start = Instant::now();
+        let mut frames = 0;
+        while frames < 3 {
+            assert!(start.elapsed() < Duration::from_secs(10), "no frame received");
+            if emulator.update_frame() {
+                frames += 1;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        emulator.stop();
+        // the last frame published before the stop
+        emulator.update_frame();
+        // ignored once stopped, without panicking
+        emulator.send(Command::ToggleCheat(0));
+        emulator.send(Command::ToggleMute);
+        assert!(!emulator.update_frame());
+    }
+
+    #[test]
+    fn audio_is_updated_at_each_interrupt() {
+        let mut emulator = Emulator::new(&INVADERS, ::INVADERS_ROM, Screen::new(CrtSettings::disabled(), None));
+        let mut audio = UpdateLog::default();
+        emulator.step(3, &mut audio);
+
+        // the two interrupts of each frame, then the cycles run after the second one
+        assert_eq!(9, audio.updates.len());
+        assert_eq!(emulator.cpu().cycles(), audio.updates.iter().sum::<u64>());
+    }
+
+    #[test]
+    fn achievements_are_checked_without_cheats() {
+        let mut emulator = Emulator::new(&INVADERS, ::INVADERS_ROM, Screen::new(CrtSettings::disabled(), None));
+        let mut audio = AudioOutput::new(Box::new(NullBackend::new(44100)), AudioSettings::default());
+        let achievements = parse_achievements("achievement Score\nrequire bcd 0x20f8 >= 5000").unwrap();
+        emulator.set_achievements(AchievementEngine::new(achievements));
+        let (sender, unlocks) = mpsc::channel();
+        emulator.send_unlocks(sender);
+        // after the RAM is cleared
+        emulator.step(10, &mut audio);
+
+        let mut cheats = CheatEngine::new(parse_cheats("cheat a\nwrite 0x2300 0").unwrap());
+        cheats.set_enabled(0, true, emulator.cpu_mut().memory_map_mut());
+        emulator.set_cheats(cheats);
+        emulator.cpu_mut().memory_map_mut().poke(0x20f9, 0x50);
+        emulator.step(2, &mut audio);
+        assert!(unlocks.try_recv().is_err());
+
+        // the score must be reached again without the cheat
+        emulator.toggle_cheat(0);
+        emulator.step(2, &mut audio);
+        assert!(unlocks.try_recv().is_err());
+        emulator.cpu_mut().memory_map_mut().poke(0x20f9, 0x49);
+        emulator.step(1, &mut audio);
+        emulator.cpu_mut().memory_map_mut().poke(0x20f9, 0x50);
+        emulator.step(1, &mut audio);
+        assert_eq!("Score", unlocks.try_recv().unwrap().name);
+        assert!(emulator.achievements().is_unlocked(0));
+    }
+}
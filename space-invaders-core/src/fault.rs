@@ -0,0 +1,134 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of faults kept by the `FaultLog`, the older ones are only counted
+pub const MAX_LOGGED_FAULTS: usize = 64;
+
+/// What the machine does when the game uses a port the board does not have (patched ROM, bootleg,
+/// corrupted state...). An `IN` from such a port reads 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// The access is ignored
+    Ignore,
+    /// The access is ignored and logged
+    #[default]
+    Log,
+    /// The access is logged and the machine stops: the cpu does not execute anything else, and
+    /// keeps the state it had after the faulty instruction
+    Halt,
+}
+
+impl FromStr for FaultPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<FaultPolicy, String> {
+        match name {
+            "ignore" => Ok(FaultPolicy::Ignore),
+            "log" => Ok(FaultPolicy::Log),
+            "halt" => Ok(FaultPolicy::Halt),
+            _ => Err(format!("unknown fault policy {}", name)),
+        }
+    }
+}
+
+/// Access to a port the board does not have
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fault {
+    /// Address of the `IN` or `OUT` instruction
+    pub pc: u16,
+    pub port: u8,
+    /// Value written by an `OUT`, `None` for an `IN`
+    pub value: Option<u8>,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "OUT to unknown port {} (value 0x{:02x}) at 0x{:04x}", self.port, value, self.pc),
+            None => write!(f, "IN from unknown port {} at 0x{:04x}", self.port, self.pc),
+        }
+    }
+}
+
+/// Last faults of the machine, for the frontends to show.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultLog {
+    faults: VecDeque<Fault>,
+    total: u64,
+}
+
+impl FaultLog {
+    pub fn push(&mut self, fault: Fault) {
+        if self.faults.len() == MAX_LOGGED_FAULTS {
+            self.faults.pop_front();
+        }
+        self.faults.push_back(fault);
+        self.total += 1;
+    }
+
+    /// The last `MAX_LOGGED_FAULTS` faults, oldest first
+    pub fn faults(&self) -> impl Iterator<Item = &Fault> {
+        self.faults.iter()
+    }
+
+    /// Number of faults since the machine was created or the log cleared, including the ones no
+    /// longer kept
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Faults logged after the first `seen` ones, to show the new faults since the last check
+    pub fn since(&self, seen: u64) -> impl Iterator<Item = &Fault> {
+        let new = self.total.saturating_sub(seen).min(self.faults.len() as u64) as usize;
+        self.faults.iter().skip(self.faults.len() - new)
+    }
+
+    pub fn clear(&mut self) {
+        self.faults.clear();
+        self.total = 0;
+    }
+}
+
+/// Prints the faults of `log` after the first `seen` ones, and returns their new total.
+pub(crate) fn print_faults(log: &FaultLog, seen: u64) -> u64 {
+    for fault in log.since(seen) {
+        eprintln!("{}", fault);
+    }
+    log.total()
+}
+
+#[cfg(test)]
+mod tests {
+    use fault::*;
+
+    fn fault(pc: u16) -> Fault {
+        Fault { pc, port: 7, value: None }
+    }
+
+    #[test]
+    fn log_keeps_the_last_faults() {
+        let mut log = FaultLog::default();
+        for pc in 0..100 {
+            log.push(fault(pc));
+        }
+        assert_eq!(100, log.total());
+        assert_eq!(MAX_LOGGED_FAULTS, log.faults().count());
+        assert_eq!(Some(&fault(36)), log.faults().next());
+        assert_eq!(vec![&fault(98), &fault(99)], log.since(98).collect::<Vec<_>>());
+        assert_eq!(MAX_LOGGED_FAULTS, log.since(0).count());
+    }
+
+    #[test]
+    fn faults_are_readable() {
+        assert_eq!("IN from unknown port 7 at 0x1234", fault(0x1234).to_string());
+        let out = Fault { pc: 0x0a, port: 9, value: Some(0xff) };
+        assert_eq!("OUT to unknown port 9 (value 0xff) at 0x000a", out.to_string());
+        assert_eq!(Ok(FaultPolicy::Halt), "halt".parse());
+        assert!("panic".parse::<FaultPolicy>().is_err());
+    }
+}
@@ -0,0 +1,419 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::ops::Range;
+
+use png::encode_png;
+use upscale::{ Upscaler, MAX_NEAREST_FACTOR };
+
+/// Width of the screen once rotated (the monitor is mounted vertically in the cabinet)
+pub const SCREEN_WIDTH: usize = 224;
+/// Height of the screen once rotated
+pub const SCREEN_HEIGHT: usize = 256;
+
+/// Start of the video RAM in memory
+pub const VRAM_START: usize = 0x2400;
+/// End (exclusive) of the video RAM in memory
+pub const VRAM_END: usize = 0x4000;
+
+/// Start of the colour RAM of the colour boards (Space Invaders Part II...) in memory
+pub const COLOR_RAM_START: usize = 0xc400;
+/// End (exclusive) of the colour RAM
+pub const COLOR_RAM_END: usize = 0xc800;
+
+/*
+ The beam of the monitor scans the lines of the video RAM (the columns of the rotated screen, from
+ left to right), 60 times per second. The board sends RST 1 when it reaches the middle of the
+ screen and RST 2 at the vertical blank. The game redraws the objects of the first lines after RST
+ 1 and the others after RST 2, while the beam is away, so the monitor shows the first lines as they
+ are at RST 1 and the others as they are at RST 2.
+*/
+
+/// RST sent by the board when the beam reaches `MID_SCREEN_LINE`
+pub const MID_SCREEN_INTERRUPT: u8 = 1;
+/// RST sent by the board at the vertical blank
+pub const VBLANK_INTERRUPT: u8 = 2;
+/// Line of the video RAM scanned by the beam at RST 1 (as in MAME)
+pub const MID_SCREEN_LINE: usize = 96;
+/// Number of lines of the video RAM
+pub const VRAM_LINES: usize = SCREEN_WIDTH;
+
+/// Colours of the 8 colour codes of the colour boards (RGBA)
+pub type ColorPalette = [[u8; 4]; 8];
+
+/// Default colour of the lit pixels (RGBA)
+pub const WHITE: [u8; 4] = [255, 255, 255, 255];
+/// Default colour of the background (RGBA)
+pub const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+// The monitor is black and white, the colours of the cabinet come from strips of cellophane glued
+// on the screen: red at the top for the flying saucer, green at the bottom for the shields, the
+// player and the remaining lives.
+const OVERLAY_RED: [u8; 4] = [255, 40, 40, 255];
+const OVERLAY_GREEN: [u8; 4] = [40, 255, 40, 255];
+const OVERLAY_RED_ROWS: (usize, usize) = (32, 64);
+const OVERLAY_GREEN_ROWS: (usize, usize) = (184, 240);
+/// Below the green strip, only the remaining lives are covered (not the credits)
+const OVERLAY_LIVES: ((usize, usize), (usize, usize)) = ((16, 134), (240, SCREEN_HEIGHT));
+
+/*
+ The colour boards have a colour RAM with one byte per cell of 8x8 pixels: the 8 bytes of video
+ RAM at the same position in 8 consecutive columns. Its 3 low bits are the colour code of the lit
+ pixels of the cell. The code addresses the colour PROM, whose 3 low bits drive the red, blue and
+ green guns of the monitor (without the PROM, the code drives them directly).
+*/
+
+/// Number of video RAM bytes in a column of the screen
+const COLUMN_BYTES: usize = SCREEN_HEIGHT / 8;
+
+/// RGBA image of the screen, decoded from the video RAM.
+///
+/// The video RAM is 1 bit per pixel, stored column by column from the bottom left corner (the
+/// monitor is rotated). The frame keeps a copy of the video RAM it was decoded from so that only
+/// the bytes which changed are decoded again, and likewise for the colour RAM.
+pub struct Frame {
+    pixels: Vec<u8>,
+    vram: Vec<u8>,
+    colors: Colors,
+    color_ram: Vec<u8>,
+    /// Colours of the codes of the colour RAM, `None` on the black and white boards
+    color_palette: Option<ColorPalette>,
+}
+
+#[derive(Clone, Copy)]
+struct Colors {
+    foreground: [u8; 4],
+    background: [u8; 4],
+    overlay: bool,
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame {
+            pixels: BLACK.iter().cloned().cycle().take(SCREEN_WIDTH * SCREEN_HEIGHT * 4).collect(),
+            vram: vec![0; VRAM_END - VRAM_START],
+            colors: Colors { foreground: WHITE, background: BLACK, overlay: false },
+            color_ram: vec![0; COLOR_RAM_END - COLOR_RAM_START],
+            color_palette: None,
+        }
+    }
+
+    pub fn palette(&self) -> ([u8; 4], [u8; 4]) {
+        (self.colors.foreground, self.colors.background)
+    }
+
+    /// Sets the RGBA colours of the lit pixels and of the background, and redraws the whole frame.
+    pub fn set_palette(&mut self, foreground: [u8; 4], background: [u8; 4]) {
+        self.colors.foreground = foreground;
+        self.colors.background = background;
+        self.redraw();
+    }
+
+    pub fn overlay(&self) -> bool {
+        self.colors.overlay
+    }
+
+    /// Colours the lit pixels under the red and green strips of the cabinet, and redraws the whole
+    /// frame.
+    pub fn set_overlay(&mut self, overlay: bool) {
+        self.colors.overlay = overlay;
+        self.redraw();
+    }
+
+    pub fn color_palette(&self) -> Option<ColorPalette> {
+        self.color_palette
+    }
+
+    /// Colours the cells with the colour RAM (see `update_color_ram`), or back in black and white
+    /// with `None`, and redraws the whole frame. The palette and the overlay are not used for the
+    /// lit pixels of a colour frame.
+    pub fn set_color_palette(&mut self, color_palette: Option<ColorPalette>) {
+        self.color_palette = color_palette;
+        self.redraw();
+    }
+
+    /// Decodes the video RAM `vram` (`VRAM_END - VRAM_START` bytes). Returns `false` if nothing
+    /// changed since the last update.
+    pub fn update(&mut self, vram: &[u8]) -> bool {
+        self.update_lines(vram, 0..VRAM_LINES)
+    }
+
+    /// Decodes the `lines` of the video RAM `vram` (`VRAM_END - VRAM_START` bytes), and keeps the
+    /// other lines as they were. Returns `false` if none of these lines changed since the last
+    /// update.
+    pub fn update_lines(&mut self, vram: &[u8], lines: Range<usize>) -> bool {
+        let mut changed = false;
+        let start = lines.start * COLUMN_BYTES;
+        let bytes = &vram[start..(lines.end * COLUMN_BYTES)];
+        for (i, px_byte) in bytes.iter().enumerate().map(|(i, px_byte)| (start + i, px_byte)) {
+            if *px_byte != self.vram[i] {
+                self.vram[i] = *px_byte;
+                self.decode(i);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Redraws the cells whose colour changed in the colour RAM `color_ram` (`COLOR_RAM_END -
+    /// COLOR_RAM_START` bytes). Returns `false` if nothing changed since the last update. The
+    /// colour RAM is ignored without a colour palette.
+    pub fn update_color_ram(&mut self, color_ram: &[u8]) -> bool {
+        if self.color_palette.is_none() {
+            return false;
+        }
+        let mut changed = false;
+        for (cell, color) in color_ram.iter().enumerate() {
+            if *color != self.color_ram[cell] {
+                self.color_ram[cell] = *color;
+                let vram_len = self.vram.len();
+                for index in cell_vram_indexes(cell).filter(|&index| index < vram_len) {
+                    self.decode(index);
+                }
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// RGBA pixels, row by row
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Encodes the frame, with its palette and overlay, in PNG. Each pixel is repeated `scale`
+    /// times in both directions (1 to `MAX_NEAREST_FACTOR`).
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let scale = scale.clamp(1, MAX_NEAREST_FACTOR);
+        if scale == 1 {
+            return encode_png(SCREEN_WIDTH, SCREEN_HEIGHT, &self.pixels);
+        }
+        let mut scaled = Vec::new();
+        Upscaler::Nearest(scale).upscale(&self.pixels, SCREEN_WIDTH, SCREEN_HEIGHT, &mut scaled);
+        encode_png(SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale, &scaled)
+    }
+
+    fn redraw(&mut self) {
+        for i in 0..self.vram.len() {
+            self.decode(i);
+        }
+    }
+
+    fn decode(&mut self, index: usize) {
+        let cell_color = self.color_palette
+            .map(|palette| palette[(self.color_ram[vram_index_to_cell(index)] & 0x07) as usize]);
+        decode_byte(&mut self.pixels, index, self.vram[index], &self.colors, cell_color.as_ref());
+    }
+}
+
+/// Lines of the video RAM scanned by the beam since the previous interrupt, when the board sends
+/// RST `interrupt`.
+pub fn beam_lines(interrupt: u8) -> Range<usize> {
+    if interrupt == MID_SCREEN_INTERRUPT {
+        0..MID_SCREEN_LINE
+    } else {
+        MID_SCREEN_LINE..VRAM_LINES
+    }
+}
+
+/// Palette of the colour PROM `prom`, or of the colour codes themselves without it.
+pub fn color_palette(prom: Option<&[u8]>) -> ColorPalette {
+    let mut palette = [BLACK; 8];
+    for (code, color) in palette.iter_mut().enumerate() {
+        let rbg = prom.map_or(code as u8, |prom| prom[code]);
+        let gun = |bit: u8| if rbg & (1 << bit) != 0 { 255 } else { 0 };
+        *color = [gun(0), gun(2), gun(1), 255];
+    }
+    palette
+}
+
+impl Colors {
+    /// Colour of a lit pixel
+    fn lit(&self, x: usize, y: usize) -> &[u8; 4] {
+        if !self.overlay {
+            return &self.foreground;
+        }
+        let ((lives_left, lives_right), (lives_top, lives_bottom)) = OVERLAY_LIVES;
+        if y >= OVERLAY_RED_ROWS.0 && y < OVERLAY_RED_ROWS.1 {
+            &OVERLAY_RED
+        } else if (y >= OVERLAY_GREEN_ROWS.0 && y < OVERLAY_GREEN_ROWS.1)
+            || (y >= lives_top && y < lives_bottom && x >= lives_left && x < lives_right) {
+            &OVERLAY_GREEN
+        } else {
+            &self.foreground
+        }
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Frame {
+        Frame::new()
+    }
+}
+
+/// `cell_color` is the colour of the lit pixels on the colour boards
+fn decode_byte(pixels: &mut [u8], index: usize, px_byte: u8, colors: &Colors, cell_color: Option<&[u8; 4]>) {
+    let (x_source, y_source) = memory_buffer_index_to_coordinates(index);
+    let x_target = y_source;
+
+    for bit in 0..8 {
+        let y_target = SCREEN_HEIGHT - 1 - x_source - bit;
+        let color = if px_byte & (1 << bit) == 0 {
+            &colors.background
+        } else {
+            cell_color.unwrap_or_else(|| colors.lit(x_target, y_target))
+        };
+        let offset = (y_target * SCREEN_WIDTH + x_target) * 4;
+        pixels[offset..(offset + 4)].copy_from_slice(color);
+    }
+}
+
+/// Colour RAM cell of a video RAM byte: 8 columns wide, 1 byte (8 pixels) high
+fn vram_index_to_cell(index: usize) -> usize {
+    (index / (8 * COLUMN_BYTES)) * COLUMN_BYTES + index % COLUMN_BYTES
+}
+
+/// Video RAM bytes of a colour RAM cell
+fn cell_vram_indexes(cell: usize) -> impl Iterator<Item = usize> {
+    let first_column = (cell / COLUMN_BYTES) * 8;
+    (first_column..(first_column + 8)).map(move |column| column * COLUMN_BYTES + cell % COLUMN_BYTES)
+}
+
+fn memory_buffer_index_to_coordinates(index: usize) -> (usize, usize) {
+    let x = (index * 8) % SCREEN_HEIGHT;
+    let y = (index * 8) / SCREEN_HEIGHT;
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use frame::*;
+
+    fn pixel(frame: &Frame, x: usize, y: usize) -> &[u8] {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        &frame.pixels()[offset..(offset + 4)]
+    }
+
+    #[test]
+    fn decode_rotated_pixels() {
+        let mut frame = Frame::new();
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        // first byte : bottom left corner, bit 0 is the lowest pixel
+        vram[0] = 0b0000_0001;
+        // second column starts 32 bytes later
+        vram[32] = 0b1000_0000;
+
+        assert!(frame.update(&vram));
+        assert_eq!(&[255, 255, 255, 255], pixel(&frame, 0, 255));
+        assert_eq!(&[0, 0, 0, 255], pixel(&frame, 0, 254));
+        assert_eq!(&[255, 255, 255, 255], pixel(&frame, 1, 248));
+        assert_eq!(2, frame.pixels().chunks(4).filter(|px| px[0] == 255).count());
+    }
+
+    #[test]
+    fn update_reports_changes() {
+        let mut frame = Frame::new();
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        assert!(!frame.update(&vram));
+
+        vram[100] = 0xff;
+        assert!(frame.update(&vram));
+        assert!(!frame.update(&vram));
+
+        vram[100] = 0;
+        assert!(frame.update(&vram));
+        assert!(frame.pixels().chunks(4).all(|px| px == [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn lines_are_decoded_separately() {
+        let mut frame = Frame::new();
+        let vram = vec![0xff; VRAM_END - VRAM_START];
+        assert!(frame.update_lines(&vram, beam_lines(MID_SCREEN_INTERRUPT)));
+        // a line is a column of the screen
+        assert_eq!(&WHITE, pixel(&frame, MID_SCREEN_LINE - 1, 0));
+        assert_eq!(&BLACK, pixel(&frame, MID_SCREEN_LINE, 0));
+
+        assert!(frame.update_lines(&vram, beam_lines(VBLANK_INTERRUPT)));
+        assert!(frame.pixels().chunks(4).all(|px| px == WHITE));
+        assert!(!frame.update(&vram));
+    }
+
+    #[test]
+    fn palette_redraws_the_frame() {
+        let mut frame = Frame::new();
+        let mut vram = vec![0; VRAM_END - VRAM_START];
+        vram[0] = 0b0000_0001;
+        frame.update(&vram);
+
+        frame.set_palette([0, 255, 0, 255], [0, 0, 64, 255]);
+        assert_eq!(&[0, 255, 0, 255], pixel(&frame, 0, 255));
+        assert_eq!(&[0, 0, 64, 255], pixel(&frame, 0, 254));
+    }
+
+    #[test]
+    fn overlay_colours_the_strips() {
+        let mut frame = Frame::new();
+        let vram = vec![0xff; VRAM_END - VRAM_START];
+        frame.update(&vram);
+        frame.set_overlay(true);
+
+        assert_eq!(&WHITE, pixel(&frame, 100, 10));
+        assert_eq!(&OVERLAY_RED, pixel(&frame, 100, 40));
+        assert_eq!(&OVERLAY_GREEN, pixel(&frame, 100, 200));
+        assert_eq!(&OVERLAY_GREEN, pixel(&frame, 20, 250));
+        assert_eq!(&WHITE, pixel(&frame, 200, 250));
+
+        frame.set_overlay(false);
+        assert_eq!(&WHITE, pixel(&frame, 100, 40));
+    }
+
+    #[test]
+    fn color_ram_colours_the_cells() {
+        let mut frame = Frame::new();
+        let vram = vec![0xff; VRAM_END - VRAM_START];
+        let mut color_ram = vec![0; COLOR_RAM_END - COLOR_RAM_START];
+        frame.update(&vram);
+        // ignored in black and white
+        color_ram[0] = 1;
+        assert!(!frame.update_color_ram(&color_ram));
+
+        frame.set_color_palette(Some(color_palette(None)));
+        assert!(frame.update_color_ram(&color_ram));
+        assert!(!frame.update_color_ram(&color_ram));
+        // first cell: bottom left corner, 8 pixels wide and high
+        let red = [255, 0, 0, 255];
+        assert_eq!(&red, pixel(&frame, 0, 255));
+        assert_eq!(&red, pixel(&frame, 7, 248));
+        assert_eq!(&BLACK, pixel(&frame, 8, 255));
+        assert_eq!(&BLACK, pixel(&frame, 0, 247));
+
+        // the cell above, green
+        color_ram[1] = 4;
+        frame.update_color_ram(&color_ram);
+        assert_eq!(&[0, 255, 0, 255], pixel(&frame, 3, 240));
+
+        frame.set_color_palette(None);
+        assert_eq!(&WHITE, pixel(&frame, 0, 255));
+    }
+
+    #[test]
+    fn prom_palette() {
+        let palette = color_palette(Some(&[0, 7, 2, 3, 4, 5, 6, 1]));
+        assert_eq!(BLACK, palette[0]);
+        assert_eq!(WHITE, palette[1]);
+        assert_eq!([0, 0, 255, 255], palette[2]);
+        assert_eq!([255, 0, 0, 255], palette[7]);
+    }
+
+    #[test]
+    fn png_is_scaled() {
+        let frame = Frame::new();
+        let png = frame.to_png(2);
+        // width and height in the IHDR chunk
+        assert_eq!(&[0, 0, 1, 192, 0, 0, 2, 0], &png[16..24]);
+        assert_eq!(&[0, 0, 0, 224, 0, 0, 1, 0], &frame.to_png(1)[16..24]);
+    }
+}
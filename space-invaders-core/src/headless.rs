@@ -0,0 +1,54 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::io;
+
+use cpu::Cpu8080;
+use fault::print_faults;
+use machine::{ SpaceInvaderDataBus, SpaceInvaderMachine };
+use memory::MemoryMap;
+use options::FrontendOptions;
+use record::Recorder;
+use scheduler::Scheduler;
+use screen::Screen;
+
+/// Runs the game without a window (`--headless`), as fast as possible, for `options.frames` frames
+/// and records them if `--record` or `--record-audio` are given. The cheats of `--cheat` are
+/// enabled.
+pub fn run_headless(options: &FrontendOptions) -> io::Result<()> {
+    let (driver, rom) = options.load_game().map_err(io::Error::other)?;
+    let mut memory = MemoryMap::new(driver, &rom);
+    let cheats = options.load_cheats(driver, &mut memory).map_err(io::Error::other)?;
+    let mut machine = SpaceInvaderMachine::with_driver(driver);
+    machine.set_fault_policy(options.fault_policy);
+    let mut cpu = Cpu8080::new(memory, SpaceInvaderDataBus::new(machine));
+
+    let mut screen = options.screen(driver);
+    let mut recorder = Recorder::create(
+        options.record.as_deref(), options.record_audio.as_deref(), screen.width(), screen.height())?;
+
+    let mut scheduler = Scheduler::new(cpu.cycles());
+    let mut faults = 0;
+    for frame in 0..options.frames {
+        run_frame(&mut cpu, &mut scheduler, &mut screen);
+        cheats.apply(cpu.memory_map_mut());
+        let machine = cpu.data_bus().machine();
+        faults = print_faults(machine.faults(), faults);
+        if machine.is_halted() {
+            recorder.finish()?;
+            let message = format!("the emulation was halted by a fault at frame {}", frame);
+            return Err(io::Error::other(message));
+        }
+        screen.update(cpu.memory());
+        recorder.record_frame(screen.pixels(), cpu.data_bus().machine().sound_latches())?;
+    }
+    recorder.finish()
+}
+
+/// Emulates one frame with `scheduler`, up to the vertical blank. The `screen` samples the beam
+/// before each interrupt.
+fn run_frame(cpu: &mut Cpu8080<SpaceInvaderDataBus>, scheduler: &mut Scheduler, screen: &mut Screen) {
+    scheduler.add_frame();
+    scheduler.run(cpu, |cpu, interrupt| screen.sample_beam(cpu.memory(), interrupt));
+}
@@ -0,0 +1,430 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+use std::sync::mpsc::{ Receiver, TryRecvError };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use board::Input;
+use emulator::{ Command, Emulator };
+use options::FrontendOptions;
+use screen::Screen;
+use sound::SoundLatches;
+
+/// Duration of a frame of the monitor, at 60 Hz
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_666_667);
+
+/// After a stall, `HostLoop::step` emulates at most this number of frames at once to catch up,
+/// the rest of the time is skipped.
+pub const MAX_CATCH_UP_FRAMES: u32 = 5;
+
+/// Where the frames go: a window, a texture, a terminal...
+pub trait VideoSink {
+    /// Shows the screen, after each emulated frame where it changed.
+    fn present(&mut self, screen: &Screen);
+}
+
+/// Where the sound goes: an `AudioOutput`, or samples the host plays on its own.
+pub trait AudioSink {
+    /// The machine ran `cycles` cycles, and the sound ports hold `latches`.
+    fn update(&mut self, latches: SoundLatches, cycles: u64);
+
+    fn is_muted(&self) -> bool;
+
+    fn set_muted(&mut self, muted: bool);
+}
+
+/// Request of the host to the emulation
+#[derive(Debug)]
+pub enum HostEvent {
+    /// An input of the cabinet is pressed or released
+    Input(Input, bool),
+    Command(Command),
+    /// Runs a function on the emulation, between two runs
+    Call(EmulatorCall),
+    /// Stops `HostLoop::run`
+    Quit,
+}
+
+/// Function run by the `HostLoop` on its emulator and its audio sink, e.g. to answer a request of
+/// the remote control (see `RemoteServer`).
+pub struct EmulatorCall(Box<CallFunction>);
+
+type CallFunction = dyn FnOnce(&mut Emulator, &mut dyn AudioSink) + Send;
+
+impl EmulatorCall {
+    pub fn new<F: FnOnce(&mut Emulator, &mut dyn AudioSink) + Send + 'static>(function: F) -> EmulatorCall {
+        EmulatorCall(Box::new(function))
+    }
+
+    pub fn call(self, emulator: &mut Emulator, audio: &mut dyn AudioSink) {
+        (self.0)(emulator, audio)
+    }
+}
+
+impl fmt::Debug for EmulatorCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EmulatorCall")
+    }
+}
+
+/// Where the events come from: the keyboard, a channel from the thread of a window, a script...
+pub trait InputSource {
+    /// Next pending event, `None` if there is none for now.
+    fn poll(&mut self) -> Option<HostEvent>;
+}
+
+/// The events sent on the channel, until it is closed (which quits).
+impl InputSource for Receiver<HostEvent> {
+    fn poll(&mut self) -> Option<HostEvent> {
+        match self.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(HostEvent::Quit),
+        }
+    }
+}
+
+/// Time of the host, which sets the speed of the emulation.
+pub trait Clock {
+    /// Time elapsed since an arbitrary origin
+    fn now(&self) -> Duration;
+
+    /// Waits until `now` reaches `time`. The clocks of the hosts calling `HostLoop::step` on their
+    /// own schedule (e.g. on each frame of a browser) do not wait.
+    fn wait_until(&mut self, time: Duration);
+}
+
+/// Clock of the system, which puts the thread to sleep to wait.
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { origin: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn wait_until(&mut self, time: Duration) {
+        thread::sleep(time.saturating_sub(self.now()));
+    }
+}
+
+/// What the keys (or the buttons, the touches...) of a frontend do, whatever the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Coin,
+    P1Start,
+    P2Start,
+    /// The cannon controls are the ones of both players, the game only reads the ones of the
+    /// player whose turn it is.
+    Fire,
+    Left,
+    Right,
+    ToggleMute,
+    SaveState,
+    LoadState,
+    ToggleRecording,
+    Screenshot,
+    /// Toggles the cheat with this index
+    ToggleCheat(usize),
+}
+
+impl Control {
+    /// Inputs of the cabinet pressed and released with the control, none for the commands.
+    pub fn inputs(self) -> &'static [Input] {
+        match self {
+            Control::Coin => &[Input::Coin],
+            Control::P1Start => &[Input::P1Start],
+            Control::P2Start => &[Input::P2Start],
+            Control::Fire => &[Input::P1Fire, Input::P2Fire],
+            Control::Left => &[Input::P1Left, Input::P2Left],
+            Control::Right => &[Input::P1Right, Input::P2Right],
+            _ => &[],
+        }
+    }
+
+    /// Command sent when the control is pressed, with the files and the formats of `options`.
+    pub fn command(self, options: &FrontendOptions) -> Option<Command> {
+        match self {
+            Control::ToggleMute => Some(Command::ToggleMute),
+            Control::SaveState => Some(Command::SaveState(options.state_file.clone())),
+            Control::LoadState => Some(Command::LoadState(options.state_file.clone())),
+            Control::ToggleRecording => Some(Command::ToggleRecording(options.record_format)),
+            Control::Screenshot => Some(Command::Screenshot(options.screenshot_dir.clone(), options.screenshot_scale)),
+            Control::ToggleCheat(index) => Some(Command::ToggleCheat(index)),
+            _ => None,
+        }
+    }
+}
+
+/// Runs an `Emulator` against a host: the events of its `InputSource` go to the machine, which
+/// runs at the speed of its `Clock`, and the screen and the sound go to its `VideoSink` and its
+/// `AudioSink`.
+///
+/// The frontends with a loop of their own (a browser, a test...) call `step` (or `run_for`,
+/// `run_frame`) when they want, the others give the loop to `run`.
+pub struct HostLoop<V, A, I, C> {
+    emulator: Emulator,
+    video: V,
+    audio: A,
+    input: I,
+    clock: C,
+    /// Time of the clock at the last `step`, `None` before the first one
+    time: Option<Duration>,
+    max_catch_up: Duration,
+}
+
+impl<V: VideoSink, A: AudioSink, I: InputSource, C: Clock> HostLoop<V, A, I, C> {
+    pub fn new(emulator: Emulator, video: V, audio: A, input: I, clock: C) -> HostLoop<V, A, I, C> {
+        HostLoop {
+            emulator,
+            video,
+            audio,
+            input,
+            clock,
+            time: None,
+            max_catch_up: FRAME_DURATION * MAX_CATCH_UP_FRAMES,
+        }
+    }
+
+    /// Emulates at most `frames` frames on each `step` (`MAX_CATCH_UP_FRAMES` by default).
+    pub fn set_max_catch_up_frames(&mut self, frames: u32) {
+        self.max_catch_up = FRAME_DURATION * frames;
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+
+    pub fn video(&self) -> &V {
+        &self.video
+    }
+
+    pub fn video_mut(&mut self) -> &mut V {
+        &mut self.video
+    }
+
+    pub fn audio(&self) -> &A {
+        &self.audio
+    }
+
+    pub fn audio_mut(&mut self) -> &mut A {
+        &mut self.audio
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    /// Runs the emulation at the speed of the clock until the input source quits, then finishes
+    /// the recording.
+    pub fn run(&mut self) {
+        while self.step() {
+            let next_frame = self.time.unwrap_or_default() + self.emulator.time_to_next_frame();
+            self.clock.wait_until(next_frame);
+        }
+        self.emulator.finish();
+    }
+
+    /// Emulates the time elapsed on the clock since the last call (the first call only sets the
+    /// origin), up to the maximum catch up. Returns false if the input source quits.
+    pub fn step(&mut self) -> bool {
+        let now = self.clock.now();
+        let elapsed = self.time.map_or(Duration::default(), |time| now.saturating_sub(time));
+        self.time = Some(now);
+        self.run_for(elapsed.min(self.max_catch_up))
+    }
+
+    /// Handles the pending events, then emulates `duration` of the machine. Returns false if the
+    /// input source quits.
+    pub fn run_for(&mut self, duration: Duration) -> bool {
+        if !self.handle_events() {
+            return false;
+        }
+        self.emulator.run(duration, &mut self.audio);
+        self.present();
+        true
+    }
+
+    /// Handles the pending events, then emulates up to the end of the frame. Returns false if the
+    /// input source quits.
+    pub fn run_frame(&mut self) -> bool {
+        if !self.handle_events() {
+            return false;
+        }
+        self.emulator.run_frame(&mut self.audio);
+        self.present();
+        true
+    }
+
+    fn handle_events(&mut self) -> bool {
+        while let Some(event) = self.input.poll() {
+            match event {
+                HostEvent::Input(input, pressed) => self.emulator.set_input(input, pressed),
+                HostEvent::Command(command) => self.emulator.execute(command, &mut self.audio),
+                HostEvent::Call(call) => call.call(&mut self.emulator, &mut self.audio),
+                HostEvent::Quit => return false,
+            }
+        }
+        true
+    }
+
+    fn present(&mut self) {
+        if self.emulator.screen_mut().take_changed() {
+            self.video.present(self.emulator.screen());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::time::Duration;
+    use board::{ Input, INVADERS };
+    use crt::CrtSettings;
+    use emulator::{ Command, Emulator };
+    use host::*;
+    use machine::CPU_FREQUENCY;
+    use screen::Screen;
+    use sound::SoundLatches;
+
+    /// Counts the frames shown
+    #[derive(Default)]
+    struct MockVideo {
+        frames: u64,
+    }
+
+    impl VideoSink for MockVideo {
+        fn present(&mut self, screen: &Screen) {
+            assert_eq!(screen.width() * screen.height() * 4, screen.pixels().len());
+            self.frames += 1;
+        }
+    }
+
+    /// Counts the cycles played
+    #[derive(Default)]
+    struct MockAudio {
+        cycles: u64,
+        muted: bool,
+    }
+
+    impl AudioSink for MockAudio {
+        fn update(&mut self, _latches: SoundLatches, cycles: u64) {
+            self.cycles += cycles;
+        }
+
+        fn is_muted(&self) -> bool {
+            self.muted
+        }
+
+        fn set_muted(&mut self, muted: bool) {
+            self.muted = muted;
+        }
+    }
+
+    /// Gives its events one by one, then quits
+    struct MockInput {
+        events: VecDeque<Option<HostEvent>>,
+    }
+
+    impl InputSource for MockInput {
+        fn poll(&mut self) -> Option<HostEvent> {
+            self.events.pop_front().unwrap_or(Some(HostEvent::Quit))
+        }
+    }
+
+    /// Clock which jumps to the time it is asked to wait for
+    #[derive(Default)]
+    struct MockClock {
+        now: Duration,
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Duration {
+            self.now
+        }
+
+        fn wait_until(&mut self, time: Duration) {
+            self.now = self.now.max(time);
+        }
+    }
+
+    fn emulator() -> Emulator {
+        Emulator::new(&INVADERS, ::INVADERS_ROM, Screen::new(CrtSettings::disabled(), None))
+    }
+
+    #[test]
+    fn run_emulates_at_the_speed_of_the_clock() {
+        // an empty poll ends each step, so the loop quits at the 61st one
+        let mut events: VecDeque<_> = (0..60).map(|_| None).collect();
+        events.push_front(Some(HostEvent::Command(Command::ToggleMute)));
+        events.push_front(Some(HostEvent::Input(Input::Coin, true)));
+        let input = MockInput { events };
+        let mut host = HostLoop::new(emulator(), MockVideo::default(), MockAudio::default(), input, MockClock::default());
+        host.run();
+
+        // the first step only sets the origin, the others wait for the end of a frame
+        assert_eq!(59, host.emulator().frames());
+        let (cycles, expected) = (host.audio().cycles, 59 * CPU_FREQUENCY / 60);
+        assert!((expected..(expected + 100)).contains(&cycles), "{} cycles", cycles);
+        assert!(host.audio().muted);
+        assert!(host.video().frames > 0 && host.video().frames <= 59);
+        assert_eq!(1, host.emulator().input_latch().ports()[1] & 1);
+    }
+
+    #[test]
+    fn step_catches_up_a_limited_number_of_frames() {
+        let input = MockInput { events: (0..3).map(|_| None).collect() };
+        let mut host = HostLoop::new(emulator(), MockVideo::default(), MockAudio::default(), input, MockClock::default());
+        assert!(host.step());
+        host.clock_mut().now = Duration::from_secs(60);
+        assert!(host.step());
+        assert_eq!(MAX_CATCH_UP_FRAMES as u64, host.emulator().frames());
+
+        host.set_max_catch_up_frames(10);
+        host.clock_mut().now = Duration::from_secs(120);
+        assert!(host.step());
+        assert_eq!(MAX_CATCH_UP_FRAMES as u64 + 10, host.emulator().frames());
+        assert!(!host.run_frame());
+    }
+
+    #[test]
+    fn paused_emulator_only_steps() {
+        let pause = EmulatorCall::new(|emulator, audio| {
+            emulator.set_paused(true);
+            emulator.step(2, audio);
+        });
+        let events = vec![Some(HostEvent::Call(pause)), None, None].into_iter().collect();
+        let mut host = HostLoop::new(emulator(), MockVideo::default(), MockAudio::default(), MockInput { events }, MockClock::default());
+        assert!(host.step());
+        assert_eq!(2, host.emulator().frames());
+        host.clock_mut().now = Duration::from_secs(1);
+        assert!(host.step());
+        assert_eq!(2, host.emulator().frames());
+        assert_eq!(FRAME_DURATION, host.emulator().time_to_next_frame());
+        assert!(host.audio().cycles >= 2 * CPU_FREQUENCY / 60);
+    }
+}
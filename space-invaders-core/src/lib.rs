@@ -2,10 +2,62 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+extern crate flate2;
+extern crate gif;
 extern crate intel_8080_emu;
 
+mod achievement;
+mod audio;
+mod board;
+mod cheat;
+mod cpu;
+mod crt;
+mod emulator;
+mod fault;
+mod frame;
+mod headless;
+mod host;
 mod machine;
+mod memory;
+mod options;
+mod patch;
+mod png;
+mod record;
+mod remote;
+mod rom;
+mod scheduler;
+mod screen;
+mod sound;
+mod status;
+mod state;
+mod triple_buffer;
+mod upscale;
 
+pub use achievement::*;
+pub use audio::*;
+pub use board::*;
+pub use cheat::*;
+pub use cpu::*;
+pub use crt::*;
+pub use emulator::*;
+pub use fault::*;
+pub use frame::*;
+pub use headless::*;
+pub use host::*;
 pub use machine::*;
+pub use memory::*;
+pub use options::*;
+pub use patch::*;
+pub use png::*;
+pub use record::*;
+pub use remote::*;
+pub use rom::*;
+pub use scheduler::*;
+pub use screen::*;
+pub use sound::*;
+pub use status::*;
+pub use state::*;
+pub use triple_buffer::*;
+pub use upscale::*;
 
-pub const INVADERS_ROM: &'static [u8] = include_bytes!(env!("ROM_PATH"));
\ No newline at end of file
+pub const INVADERS_ROM: &[u8] = include_bytes!(env!("ROM_PATH"));
\ No newline at end of file
@@ -3,166 +3,416 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use intel_8080_emu::proc_state::DataBus;
-use std::rc::Rc;
-use std::cell::RefCell;
-
-/*
- From : http://computerarcheology.com/Arcade/SpaceInvaders/Hardware.html
-    Port 0
-    bit 0 DIP4 (Seems to be self-test-request read at power up)
-    bit 1 Always 1
-    bit 2 Always 1
-    bit 3 Always 1
-    bit 4 Fire
-    bit 5 Left
-    bit 6 Right
-    bit 7 ? tied to demux port 7 ?
-
-    Port 1
-    bit 0 = CREDIT (1 if deposit)
-    bit 1 = 2P start (1 if pressed)
-    bit 2 = 1P start (1 if pressed)
-    bit 3 = Always 1
-    bit 4 = 1P shot (1 if pressed)
-    bit 5 = 1P left (1 if pressed)
-    bit 6 = 1P right (1 if pressed)
-    bit 7 = Not connected
-
-    Port 2
-    bit 0 = DIP3 00 = 3 ships  10 = 5 ships
-    bit 1 = DIP5 01 = 4 ships  11 = 6 ships
-    bit 2 = Tilt
-    bit 3 = DIP6 0 = extra ship at 1500, 1 = extra ship at 1000
-    bit 4 = P2 shot (1 if pressed)
-    bit 5 = P2 left (1 if pressed)
-    bit 6 = P2 right (1 if pressed)
-    bit 7 = DIP7 Coin info displayed in demo screen 0=ON
-
-    Port 3
-    bit 0-7 Shift register data
-
-*/ 
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU8, Ordering };
+use board::{ BoardDriver, DipSwitches, Input, INVADERS };
+use cpu::Cpu8080;
+use fault::{ Fault, FaultLog, FaultPolicy };
+use sound::SoundLatches;
+use state::{ StateError, StateReader, StateWriter };
 
+/// Frequency of the intel 8080 on the space invaders board, in Hz
+pub const CPU_FREQUENCY: u64 = 2_000_000;
+
+/// Input ports 0 to 2 of the board: the buttons and the DIP switches.
+///
+/// The latch is shared by the machine and its clones, which let the frontends press the buttons
+/// without borrowing the machine, from any thread. The game sees them on its next IN.
+#[derive(Clone)]
+pub struct InputLatch {
+    driver: &'static dyn BoardDriver,
+    ports: Arc<[AtomicU8; 3]>,
+}
+
+impl InputLatch {
+    fn new(driver: &'static dyn BoardDriver) -> InputLatch {
+        let idle = driver.ports().idle;
+        let ports = [AtomicU8::new(idle[0]), AtomicU8::new(idle[1]), AtomicU8::new(idle[2])];
+        InputLatch { driver, ports: Arc::new(ports) }
+    }
+
+    /// Presses or releases an input. Inputs the game does not use are ignored.
+    pub fn set_input(&self, input: Input, pressed: bool) {
+        for &(_, bit) in self.driver.ports().inputs.iter().filter(|&&(i, _)| i == input) {
+            let port = &self.ports[bit.port as usize];
+            if pressed != bit.active_low {
+                port.fetch_or(1 << bit.bit, Ordering::Relaxed);
+            } else {
+                port.fetch_and(!(1 << bit.bit), Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn dip_switches(&self) -> DipSwitches {
+        self.driver.decode_dip_switches(&self.ports())
+    }
+
+    /// Sets the DIP switches, `lives` is clamped between 3 and 6.
+    pub fn set_dip_switches(&self, dip_switches: DipSwitches) {
+        for (index, port) in self.ports.iter().enumerate() {
+            // the inputs pressed meanwhile are kept
+            let _ = port.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                let mut ports = self.ports();
+                ports[index] = value;
+                self.driver.encode_dip_switches(dip_switches, &mut ports);
+                Some(ports[index])
+            });
+        }
+    }
+
+    /// Values of the ports 0 to 2
+    pub fn ports(&self) -> [u8; 3] {
+        [self.port(0), self.port(1), self.port(2)]
+    }
+
+    fn port(&self, port: u8) -> u8 {
+        self.ports[port as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// The board shared by space invaders and the other games of its family (see `BoardDriver`).
 pub struct SpaceInvaderMachine {
-    port_0: u8,
-    port_1: u8,
-    port_2: u8,
+    driver: &'static dyn BoardDriver,
+    inputs: InputLatch,
     shift_value: u16,
     shift_offset: u8,
+    sound_latches: SoundLatches,
+    fault_policy: FaultPolicy,
+    faults: FaultLog,
+    /// Fault of the instruction being executed, logged by `Cpu8080::step` with its address
+    pending_fault: Cell<Option<Fault>>,
+    halted: bool,
 }
 
-const START_P2_OFFSET: u8 = 1;
-const START_P1_OFFSET: u8 = 2;
-const FIRE_BTN_OFFSET: u8 = 4;
-const LEFT_BTN_OFFSET: u8 = 5;
-const RIGHT_BTN_OFFSET: u8 = 6;
-
 impl SpaceInvaderMachine {
+    /// Board running space invaders
     pub fn new() -> SpaceInvaderMachine {
+        SpaceInvaderMachine::with_driver(&INVADERS)
+    }
+
+    pub fn with_driver(driver: &'static dyn BoardDriver) -> SpaceInvaderMachine {
         SpaceInvaderMachine {
+            driver,
+            inputs: InputLatch::new(driver),
             shift_value: 0,
             shift_offset: 0,
-            port_0: 0b00001111,
-            port_1: 0b00001000,
-            port_2: 0b00000000,
-        }
+            sound_latches: SoundLatches::default(),
+            fault_policy: FaultPolicy::default(),
+            faults: FaultLog::default(),
+            pending_fault: Cell::new(None),
+            halted: false,
         }
+    }
+
+    pub fn driver(&self) -> &'static dyn BoardDriver {
+        self.driver
+    }
+
+    /// Values last written by the game on the sound ports.
+    pub fn sound_latches(&self) -> SoundLatches {
+        self.sound_latches
+    }
+
+    pub fn fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    pub fn set_fault_policy(&mut self, fault_policy: FaultPolicy) {
+        self.fault_policy = fault_policy;
+    }
+
+    /// Accesses to the ports the board does not have (see `FaultPolicy`)
+    pub fn faults(&self) -> &FaultLog {
+        &self.faults
+    }
+
+    pub fn clear_faults(&mut self) {
+        self.faults.clear();
+    }
+
+    /// Whether a fault stopped the machine (see `FaultPolicy::Halt`)
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Restarts the machine stopped by a fault, at the instruction after the faulty one.
+    pub fn resume(&mut self) {
+        self.halted = false;
+    }
+
+    /// Handle on the input ports, to press the buttons from the frontend.
+    pub fn input_latch(&self) -> InputLatch {
+        self.inputs.clone()
+    }
+
+    pub fn dip_switches(&self) -> DipSwitches {
+        self.inputs.dip_switches()
+    }
+
+    /// Sets the DIP switches, `lives` is clamped between 3 and 6.
+    pub fn set_dip_switches(&mut self, dip_switches: DipSwitches) {
+        self.inputs.set_dip_switches(dip_switches);
+    }
+
+    /// Presses or releases an input. Inputs the game does not use are ignored.
+    pub fn set_input(&mut self, input: Input, pressed: bool) {
+        self.inputs.set_input(input, pressed);
+    }
 
     pub fn insert_coin(&mut self, pressed: bool) {
-        self.port_1 = (self.port_1 & !1) | pressed as u8;
+        self.set_input(Input::Coin, pressed);
     }
 
     pub fn p1_start_button(&mut self, pressed:bool) {
-        self.port_1 = (self.port_1 & !(1 << START_P1_OFFSET)) | ((pressed as u8) << START_P1_OFFSET);
+        self.set_input(Input::P1Start, pressed);
     }
 
     pub fn p2_start_button(&mut self, pressed:bool) {
-        self.port_1 = (self.port_1 & !(1 << START_P2_OFFSET)) | ((pressed as u8) << START_P2_OFFSET);
+        self.set_input(Input::P2Start, pressed);
     }
 
     pub fn p1_left_button(&mut self, pressed: bool) {
-        self.port_1 = (self.port_1 & !(1 << LEFT_BTN_OFFSET)) | ((pressed as u8) << LEFT_BTN_OFFSET);
+        self.set_input(Input::P1Left, pressed);
     }
 
     pub fn p1_right_button(&mut self, pressed: bool) {
-        self.port_1 = (self.port_1 & !(1 << RIGHT_BTN_OFFSET)) | ((pressed as u8) << RIGHT_BTN_OFFSET);
+        self.set_input(Input::P1Right, pressed);
     }
 
     pub fn p1_fire_button(&mut self, pressed: bool) {
-        self.port_1 = (self.port_1 & !(1 << FIRE_BTN_OFFSET)) | ((pressed as u8) << FIRE_BTN_OFFSET);
+        self.set_input(Input::P1Fire, pressed);
     }
 
     pub fn p2_left_button(&mut self, pressed: bool) {
-        self.port_2 = (self.port_2 & !(1 << LEFT_BTN_OFFSET)) | ((pressed as u8) << LEFT_BTN_OFFSET);
+        self.set_input(Input::P2Left, pressed);
     }
 
     pub fn p2_right_button(&mut self, pressed: bool) {
-        self.port_2 = (self.port_2 & !(1 << RIGHT_BTN_OFFSET)) | ((pressed as u8) << RIGHT_BTN_OFFSET);
+        self.set_input(Input::P2Right, pressed);
     }
 
     pub fn p2_fire_button(&mut self, pressed: bool) {
-        self.port_2 = (self.port_2 & !(1 << FIRE_BTN_OFFSET)) | ((pressed as u8) << FIRE_BTN_OFFSET);
+        self.set_input(Input::P2Fire, pressed);
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        for port in self.inputs.ports().iter() {
+            writer.u8(*port);
+        }
+        writer.u16(self.shift_value);
+        writer.u8(self.shift_offset);
+        writer.u8(self.sound_latches.port_3);
+        writer.u8(self.sound_latches.port_5);
+    }
+
+    /// Restores the machine, except for the inputs and DIP switches.
+    pub(crate) fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        // input ports are only read to stay in sync with `save`
+        reader.u8()?;
+        reader.u8()?;
+        reader.u8()?;
+        let shift_value = reader.u16()?;
+        let shift_offset = reader.u8()?;
+        let port_3 = reader.u8()?;
+        let port_5 = reader.u8()?;
+
+        self.shift_value = shift_value;
+        self.shift_offset = shift_offset;
+        self.sound_latches = SoundLatches { port_3, port_5 };
+        // the restored machine runs, even if a fault stopped this one
+        self.halted = false;
+        Ok(())
     }
 
     fn read_port(&self, port: u8) -> u8 {
+        let ports = self.driver.ports();
         match port {
-            0 => self.port_0,
-            1 => self.port_1,
-            2 => self.port_2,
-            3 => ((self.shift_value << (self.shift_offset as u16)) >> 8) as u8,
-            _ => panic!("unknown in port"),
+            0..=2 => self.inputs.port(port),
+            _ if port == ports.shift_result => ((self.shift_value << (self.shift_offset as u16)) >> 8) as u8,
+            _ => {
+                self.fault(port, None);
+                0
+            },
         }
     }
 
     fn write_port(&mut self, port: u8, value: u8) {
-         match port {
-            2 => self.shift_offset = value & 0x07,
-            3 => (/* TODO play sound*/),
-            4 => {
-                self.shift_value >>= 8;
-                self.shift_value |= (value as u16) << 8;
-            },
-            5 => (/* TODO play sound*/),
-            6 => (/* TODO should reset*/),
-            _ => panic!("unknown out port"),
+        let ports = self.driver.ports();
+        if port == ports.shift_offset {
+            self.shift_offset = value & 0x07;
+        } else if port == ports.shift_data {
+            self.shift_value >>= 8;
+            self.shift_value |= (value as u16) << 8;
+        } else if port == ports.sound[0] {
+            self.sound_latches.port_3 = value;
+        } else if port == ports.sound[1] {
+            self.sound_latches.port_5 = value;
+        } else if port == ports.watchdog {
+            // TODO should reset
+        } else {
+            self.fault(port, Some(value));
+        }
+    }
+
+    fn fault(&self, port: u8, value: Option<u8>) {
+        if self.fault_policy != FaultPolicy::Ignore {
+            // the address is not known here
+            self.pending_fault.set(Some(Fault { pc: 0, port, value }));
+        }
+    }
+
+    fn log_fault(&mut self, pc: u16) {
+        if let Some(fault) = self.pending_fault.take() {
+            self.faults.push(Fault { pc, ..fault });
+            self.halted |= self.fault_policy == FaultPolicy::Halt;
         }
     }
 }
 
+impl Default for SpaceInvaderMachine {
+    fn default() -> SpaceInvaderMachine {
+        SpaceInvaderMachine::new()
+    }
+}
+
+/// Data bus of the cpu, which owns the machine: the frontends reach it with `Cpu8080::data_bus`,
+/// and press the buttons through its `InputLatch`.
 pub struct SpaceInvaderDataBus {
-    machine_ref: Rc<RefCell<SpaceInvaderMachine>>,
+    machine: SpaceInvaderMachine,
 }
 
 impl SpaceInvaderDataBus {
-    pub fn new(machine_ref: Rc<RefCell<SpaceInvaderMachine>>) -> SpaceInvaderDataBus {
-        SpaceInvaderDataBus { machine_ref }
+    pub fn new(machine: SpaceInvaderMachine) -> SpaceInvaderDataBus {
+        SpaceInvaderDataBus { machine }
+    }
+
+    pub fn machine(&self) -> &SpaceInvaderMachine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut SpaceInvaderMachine {
+        &mut self.machine
     }
 }
 
 impl DataBus for SpaceInvaderDataBus {
 
     fn read_port(&self, port: u8) -> u8 {
-        self.machine_ref.borrow_mut().read_port(port)
+        self.machine.read_port(port)
     }
 
     fn write_port(&mut self, port: u8, value:u8) {
-         self.machine_ref.borrow_mut().write_port(port, value)
+         self.machine.write_port(port, value)
+    }
+}
+
+impl Cpu8080<SpaceInvaderDataBus> {
+    /// Emulates the next instruction like `emulate` and logs its faults, or only lets the time pass
+    /// when a fault stopped the machine.
+    pub fn step(&mut self) {
+        if self.data_bus().machine.halted {
+            self.idle();
+            return;
+        }
+        let pc = self.registers().pc;
+        self.emulate();
+        self.data_bus_mut().machine.log_fault(pc);
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use board::{ DipSwitches, Input, LunarRescue };
+    use cpu::Cpu8080;
+    use fault::{ Fault, FaultPolicy };
     use machine::SpaceInvaderDataBus;
     use machine::SpaceInvaderMachine;
+    use memory::MemoryMap;
     use intel_8080_emu::proc_state::DataBus;
-    use std::rc::Rc;
-    use std::cell::RefCell;
+
+    /// Cpu running `IN 7` and `OUT 9`, ports the board does not have
+    fn faulty_cpu(fault_policy: FaultPolicy) -> Cpu8080<SpaceInvaderDataBus> {
+        let mut machine = SpaceInvaderMachine::new();
+        machine.set_fault_policy(fault_policy);
+        let memory = MemoryMap::new(machine.driver(), &[0xdb, 0x07, 0xd3, 0x09]);
+        Cpu8080::new(memory, SpaceInvaderDataBus::new(machine))
+    }
+
+    #[test]
+    fn dip_switches() {
+        let mut machine = SpaceInvaderMachine::new();
+        assert_eq!(DipSwitches::default(), machine.dip_switches());
+
+        machine.p2_fire_button(true);
+        let dip_switches = DipSwitches { lives: 5, extra_life_at_1000: true, coin_info: false };
+        machine.set_dip_switches(dip_switches);
+        assert_eq!(dip_switches, machine.dip_switches());
+        assert_eq!(0b1001_1010, machine.read_port(2));
+    }
+
+    #[test]
+    fn inputs_follow_the_port_map() {
+        let mut machine = SpaceInvaderMachine::new();
+        machine.set_input(Input::Coin, true);
+        machine.set_input(Input::P2Left, true);
+        assert_eq!(0b0000_1001, machine.read_port(1));
+        assert_eq!(0b0010_0000, machine.read_port(2));
+
+        let mut machine = SpaceInvaderMachine::with_driver(&LunarRescue);
+        assert_eq!(0b0000_1001, machine.read_port(1));
+        machine.insert_coin(true);
+        assert_eq!(0b0000_1000, machine.read_port(1));
+    }
+
+    #[test]
+    fn input_latch_is_shared() {
+        let machine = SpaceInvaderMachine::new();
+        let latch = machine.input_latch();
+        latch.set_input(Input::P1Fire, true);
+        assert_eq!(0b0001_1000, machine.read_port(1));
+        latch.set_input(Input::P1Fire, false);
+        assert_eq!(0b0000_1000, machine.read_port(1));
+    }
+
+    #[test]
+    fn unknown_ports_are_logged() {
+        let mut cpu = faulty_cpu(FaultPolicy::Log);
+        cpu.step();
+        cpu.step();
+        let machine = cpu.data_bus().machine();
+        let faults: Vec<&Fault> = machine.faults().faults().collect();
+        assert_eq!(vec![&Fault { pc: 0, port: 7, value: None }, &Fault { pc: 2, port: 9, value: Some(0) }], faults);
+        assert!(!machine.is_halted());
+        assert_eq!(4, cpu.registers().pc);
+
+        let mut cpu = faulty_cpu(FaultPolicy::Ignore);
+        cpu.step();
+        cpu.step();
+        assert_eq!(0, cpu.data_bus().machine().faults().total());
+    }
+
+    #[test]
+    fn halt_on_fault() {
+        let mut cpu = faulty_cpu(FaultPolicy::Halt);
+        cpu.step();
+        let cycles = cpu.cycles();
+        cpu.step();
+        assert!(cpu.data_bus().machine().is_halted());
+        assert_eq!(2, cpu.registers().pc);
+        assert!(cpu.cycles() > cycles);
+
+        cpu.data_bus_mut().machine_mut().resume();
+        cpu.step();
+        assert_eq!(4, cpu.registers().pc);
+        assert_eq!(2, cpu.data_bus().machine().faults().total());
+    }
 
     #[test] 
     fn shift(){
         let machine = SpaceInvaderMachine::new();
-        let mut shifter = SpaceInvaderDataBus::new(Rc::new(RefCell::new(machine)));
+        let mut shifter = SpaceInvaderDataBus::new(machine);
         shifter.write_port(4, 0xee);
         shifter.write_port(4, 0xff);
         assert_eq!(0xff, shifter.read_port(3));
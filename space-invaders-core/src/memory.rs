@@ -0,0 +1,292 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use board::BoardDriver;
+
+/*
+ Address decoding of the board, as in MAME (mw8080bw and 8080bw drivers). The cpu only decodes
+ 15 address lines, so 0x8000-0xffff mirrors 0x0000-0x7fff:
+
+    0x0000-0x1fff  ROM
+    0x2000-0x3fff  RAM (the video RAM from 0x2400)
+    0x4000-0x5fff  ROM, only used by the bigger games
+    0x6000-0x7fff  mirror of the RAM
+
+ The colour boards also decode 0xc000-0xdfff (mirrored at 0xe000) for the colour RAM.
+
+ The memory is stored in 64 KiB at the addresses the game uses most, so that the video RAM and
+ the colour RAM are found at their usual address in `MemoryMap::bytes`.
+*/
+
+/// Size of the address space of the 8080
+pub const MEMORY_SIZE: usize = 0x10000;
+
+const RAM_START: usize = 0x2000;
+const UNMIRRORED_END: usize = 0x4000;
+const COLOR_RAM_AREA: usize = 0xc000;
+
+/// What an access to the memory did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// Write to the ROM, which is ignored
+    RomWrite,
+}
+
+/// Access to the memory reported to the hooks of the `MemoryMap`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    /// Address used by the cpu, before the mirroring
+    pub address: u16,
+    /// Value read or written
+    pub value: u8,
+}
+
+/// Function called on the accesses of the cpu to the memory
+pub type MemoryHook = Box<dyn FnMut(&MemoryAccess) + Send>;
+
+/// 8 KiB of the address space
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Region {
+    /// Where the region is stored in the memory
+    start: usize,
+    writable: bool,
+}
+
+const REGION_BITS: u32 = 13;
+const REGION_MASK: usize = (1 << REGION_BITS) - 1;
+
+/// Where an address ends up
+enum Target {
+    Rom(usize),
+    Ram(usize),
+}
+
+/// Memory seen by the cpu: ROM write protection and mirrors of the board, and hooks to observe the
+/// accesses (a debugger, a cheat finder...).
+pub struct MemoryMap {
+    bytes: Box<[u8]>,
+    regions: [Region; 8],
+    rom_writes: u64,
+    hooks: Vec<MemoryHook>,
+}
+
+impl MemoryMap {
+    /// Memory of the board running `driver`, with its ROM image `rom` (see `assemble_rom`).
+    pub fn new(driver: &dyn BoardDriver, rom: &[u8]) -> MemoryMap {
+        let mut regions = [Region { start: 0, writable: false }; 8];
+        for (index, region) in regions.iter_mut().enumerate() {
+            let address = index << REGION_BITS;
+            *region = if driver.has_color_ram() && address >= COLOR_RAM_AREA {
+                Region { start: COLOR_RAM_AREA, writable: true }
+            } else {
+                match address & 0x7fff {
+                    RAM_START | 0x6000 => Region { start: RAM_START, writable: true },
+                    rom => Region { start: rom, writable: false },
+                }
+            };
+        }
+        MemoryMap::with_regions(rom, regions)
+    }
+
+    /// Memory without ROM nor mirror, starting with `program`. For the tests of programs which do
+    /// not need the board.
+    pub fn flat(program: &[u8]) -> MemoryMap {
+        let mut regions = [Region { start: 0, writable: true }; 8];
+        for (index, region) in regions.iter_mut().enumerate() {
+            region.start = index << REGION_BITS;
+        }
+        MemoryMap::with_regions(program, regions)
+    }
+
+    fn with_regions(content: &[u8], regions: [Region; 8]) -> MemoryMap {
+        let mut bytes = vec![0; MEMORY_SIZE].into_boxed_slice();
+        bytes[0..content.len()].copy_from_slice(content);
+        MemoryMap { bytes, regions, rom_writes: 0, hooks: Vec::new() }
+    }
+
+    /// Reads a byte like the cpu, and reports it to the hooks.
+    #[inline]
+    pub fn read(&mut self, address: u16) -> u8 {
+        let value = self.peek(address);
+        self.report(AccessKind::Read, address, value);
+        value
+    }
+
+    /// Reads a byte like the cpu, without reporting it.
+    #[inline]
+    pub fn peek(&self, address: u16) -> u8 {
+        match self.target(address) {
+            Target::Rom(index) | Target::Ram(index) => self.bytes[index],
+        }
+    }
+
+    /// Bytes from `address` to the first mirrored address, read without reporting them. The cpu
+    /// reads its opcodes from there, and falls back to `peek` near the mirrors.
+    #[inline]
+    pub fn unmirrored(&self, address: u16) -> &[u8] {
+        // the ROM and the RAM below their mirrors are at their own address
+        self.bytes.get((address as usize)..UNMIRRORED_END).unwrap_or(&[])
+    }
+
+    /// Writes a byte like the cpu: writes to the ROM are ignored and counted.
+    #[inline]
+    pub fn write(&mut self, address: u16, value: u8) {
+        let kind = match self.target(address) {
+            Target::Ram(index) => {
+                self.bytes[index] = value;
+                AccessKind::Write
+            },
+            Target::Rom(_) => {
+                self.rom_writes += 1;
+                AccessKind::RomWrite
+            },
+        };
+        self.report(kind, address, value);
+    }
+
+    /// Writes a byte where the cpu reads it, ROM included, without reporting it (for the cheats).
+    pub fn poke(&mut self, address: u16, value: u8) {
+        match self.target(address) {
+            Target::Rom(index) | Target::Ram(index) => self.bytes[index] = value,
+        }
+    }
+
+    /// Number of writes to the ROM since the memory was created
+    pub fn rom_writes(&self) -> u64 {
+        self.rom_writes
+    }
+
+    /// Calls `hook` on every read and write of the cpu, except the reads of the opcodes.
+    pub fn add_hook(&mut self, hook: MemoryHook) {
+        self.hooks.push(hook);
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    /// The whole memory, ROM included, at the addresses without mirror.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The whole memory, to restore a state or to patch the ROM. Nothing is protected, mirrored
+    /// or reported.
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    #[inline]
+    fn target(&self, address: u16) -> Target {
+        let address = address as usize;
+        let region = self.regions[address >> REGION_BITS];
+        let index = region.start | (address & REGION_MASK);
+        if region.writable { Target::Ram(index) } else { Target::Rom(index) }
+    }
+
+    #[inline]
+    fn report(&mut self, kind: AccessKind, address: u16, value: u8) {
+        if !self.hooks.is_empty() {
+            let access = MemoryAccess { kind, address, value };
+            for hook in self.hooks.iter_mut() {
+                hook(&access);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{ Arc, Mutex };
+    use board::{ LunarRescue, INVADERS };
+    use memory::*;
+
+    #[test]
+    fn rom_is_write_protected() {
+        let mut memory = MemoryMap::new(&INVADERS, &[1, 2, 3]);
+        assert_eq!(MEMORY_SIZE, memory.bytes().len());
+        memory.write(0x0001, 42);
+        memory.write(0x4001, 42);
+        memory.write(0x8001, 42);
+        assert_eq!(2, memory.peek(0x0001));
+        assert_eq!(2, memory.peek(0x8001));
+        assert_eq!(0, memory.peek(0x4001));
+        assert_eq!(3, memory.rom_writes());
+
+        memory.bytes_mut()[0x0001] = 42;
+        assert_eq!(42, memory.peek(0x0001));
+        memory.poke(0x8002, 43);
+        assert_eq!(43, memory.peek(0x0002));
+        assert_eq!(3, memory.rom_writes());
+    }
+
+    #[test]
+    fn ram_is_mirrored() {
+        let mut memory = MemoryMap::new(&INVADERS, &[]);
+        memory.write(0x2400, 1);
+        memory.write(0x7fff, 2);
+        assert_eq!(1, memory.peek(0x6400));
+        assert_eq!(1, memory.peek(0xa400));
+        assert_eq!(1, memory.peek(0xe400));
+        assert_eq!(2, memory.bytes()[0x3fff]);
+        assert_eq!(&[2], memory.unmirrored(0x3fff));
+        assert!(memory.unmirrored(0x6000).is_empty());
+        assert_eq!(0, memory.rom_writes());
+        // each byte of RAM is seen at 4 addresses
+        assert_eq!(8, (0..=0xffff).filter(|&address| memory.peek(address) != 0).count());
+    }
+
+    #[test]
+    fn color_ram_of_the_color_boards() {
+        let mut memory = MemoryMap::new(&LunarRescue, &[]);
+        memory.write(0xc400, 5);
+        assert_eq!(5, memory.bytes()[0xc400]);
+        assert_eq!(5, memory.peek(0xe400));
+        assert_eq!(0, memory.peek(0x4400));
+
+        // 0xc000 is the ROM of the second area on the other boards
+        let mut memory = MemoryMap::new(&INVADERS, &[]);
+        memory.write(0xc400, 5);
+        assert_eq!(0, memory.bytes()[0xc400]);
+        assert_eq!(1, memory.rom_writes());
+    }
+
+    #[test]
+    fn hooks_see_the_accesses() {
+        let accesses = Arc::new(Mutex::new(Vec::new()));
+        let mut memory = MemoryMap::new(&INVADERS, &[7]);
+        let seen = accesses.clone();
+        memory.add_hook(Box::new(move |access| seen.lock().unwrap().push(*access)));
+        memory.write(0x6000, 1);
+        memory.write(0x0000, 1);
+        memory.read(0x0000);
+        memory.peek(0x2000);
+        memory.unmirrored(0x0000);
+        assert_eq!(
+            vec![
+                MemoryAccess { kind: AccessKind::Write, address: 0x6000, value: 1 },
+                MemoryAccess { kind: AccessKind::RomWrite, address: 0x0000, value: 1 },
+                MemoryAccess { kind: AccessKind::Read, address: 0x0000, value: 7 },
+            ],
+            *accesses.lock().unwrap());
+
+        memory.clear_hooks();
+        memory.read(0x0000);
+        assert_eq!(3, accesses.lock().unwrap().len());
+    }
+
+    #[test]
+    fn flat_memory_is_writable() {
+        let mut memory = MemoryMap::flat(&[1, 2]);
+        assert_eq!(&[1, 2, 0], &memory.bytes()[0..3]);
+        memory.write(0x0000, 3);
+        memory.write(0xe000, 4);
+        assert_eq!(3, memory.peek(0x0000));
+        assert_eq!(4, memory.bytes()[0xe000]);
+        assert_eq!(0, memory.rom_writes());
+    }
+}
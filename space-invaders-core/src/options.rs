@@ -0,0 +1,537 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+use achievement::{ parse_achievements, AchievementEngine, AchievementLog, INVADERS_ACHIEVEMENTS };
+use audio::{ AudioBackend, AudioOutput, AudioSettings, NullBackend, WavBackend };
+use board::{ find_driver, BoardDriver, DRIVERS, INVADERS };
+use cheat::{ import_mame_cheats, parse_cheats, CheatEngine, INVADERS_CHEATS };
+use crt::CrtSettings;
+use fault::FaultPolicy;
+use frame::{ color_palette, ColorPalette };
+use memory::MemoryMap;
+use patch::apply_patch;
+use record::VideoFormat;
+use rom::{ assemble_rom, image_size };
+use screen::Screen;
+use upscale::{ Upscaler, MAX_NEAREST_FACTOR };
+use INVADERS_ROM;
+
+pub const USAGE: &str = "\
+Options:
+    --game <name>             game to run, only invaders (the default) is supported
+    --roms <dir>              directory of the ROM files of the game (as named in MAME), instead
+                              of the embedded ROM
+    --patch <file>            apply an IPS or BPS patch to the ROM (can be repeated, the patches
+                              are applied in order)
+    --audio-buffer <frames>   number of frames per audio device callback (default: the one of
+                              the device)
+    --audio-latency <ms>      audio queued in the device (default 60)
+    --audio-wav <file>        write the sound in a WAV file instead of playing it
+    --no-audio                disable the sound
+    --state <file>            file written by the save state key (F5) and read by the load
+                              state key (F9) (default space-invaders.state)
+    --crt                     emulate the CRT monitor (phosphor, scanlines, glow and curvature)
+    --phosphor-decay <0-1>    brightness kept by the phosphor from one frame to the next
+    --scanlines <0-1>         darkening of every other scanline
+    --glow <0-1>              light bleeding around the lit pixels
+    --curvature <0-1>         curvature of the screen
+    --upscaler <name>         pixel-art upscaling of the screen: nearest<1-8>, scale2x, scale3x,
+                              epx or xbr (default none, the screen is stretched)
+    --beam-sync               take each half of the screen when the beam of the monitor scans
+                              it, so that the moving objects do not tear
+    --record <file>           record the screen from the start in a gif, png (APNG) or y4m file
+    --record-audio <file>     record the sound from the start in a WAV file
+    --record-format <format>  format of the recordings started with F10: gif, apng or y4m
+                              (default gif)
+    --headless                run without window, as fast as possible, to record with --record
+                              and --record-audio
+    --frames <count>          number of frames emulated in headless mode (default 600, 10 s)
+    --overlay                 colour the screen like the cellophane strips of the cabinet
+    --screenshot-dir <dir>    directory of the screenshots taken with F12 (default .)
+    --screenshot-scale <1-8>  size of the screenshot pixels (default 1)
+    --fault-policy <policy>   access to a port the board does not have: ignore, log (print it
+                              and go on, default) or halt (print it and stop the game)
+    --cheats <file>           cheat file, or MAME cheat file (.xml), whose cheats are toggled
+                              with the keys 1 to 9 (default: the cheats of space invaders)
+    --cheat <number>          enable a cheat from the start (can be repeated)
+    --achievements <file>     achievement file (default: the achievements of space invaders)
+    --achievement-log <file>  file where the unlocked achievements are kept (default
+                              space-invaders-achievements.log)
+    --remote <socket>         serve the JSON-RPC remote control on this unix socket";
+
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const DEFAULT_STATE_FILE: &str = "space-invaders.state";
+const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+const DEFAULT_SCREENSHOT_DIR: &str = ".";
+const DEFAULT_ACHIEVEMENT_LOG: &str = "space-invaders-achievements.log";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioOutputKind {
+    Device,
+    Wav(PathBuf),
+    Disabled,
+}
+
+/// Command line options of the native frontends.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrontendOptions {
+    /// Short name of the game (see `BoardDriver::name`)
+    pub game: String,
+    pub roms: Option<PathBuf>,
+    /// IPS or BPS patches of the ROM image, applied in this order
+    pub patches: Vec<PathBuf>,
+    pub audio: AudioSettings,
+    pub audio_output: AudioOutputKind,
+    pub state_file: PathBuf,
+    pub crt: CrtSettings,
+    pub upscaler: Option<Upscaler>,
+    pub beam_sync: bool,
+    /// Files recorded from the start
+    pub record: Option<PathBuf>,
+    pub record_audio: Option<PathBuf>,
+    pub record_format: VideoFormat,
+    pub headless: bool,
+    pub frames: u64,
+    pub overlay: bool,
+    pub screenshot_dir: PathBuf,
+    pub screenshot_scale: usize,
+    pub fault_policy: FaultPolicy,
+    pub cheats: Option<PathBuf>,
+    /// Indices of the cheats enabled from the start
+    pub enabled_cheats: Vec<usize>,
+    pub achievements: Option<PathBuf>,
+    pub achievement_log: PathBuf,
+    /// Unix socket of the remote control (see `RemoteServer`)
+    pub remote: Option<PathBuf>,
+}
+
+impl Default for FrontendOptions {
+    fn default() -> FrontendOptions {
+        FrontendOptions {
+            game: INVADERS.name().to_string(),
+            roms: None,
+            patches: Vec::new(),
+            audio: AudioSettings::default(),
+            audio_output: AudioOutputKind::Device,
+            state_file: PathBuf::from(DEFAULT_STATE_FILE),
+            crt: CrtSettings::disabled(),
+            upscaler: None,
+            beam_sync: false,
+            record: None,
+            record_audio: None,
+            record_format: VideoFormat::Gif,
+            headless: false,
+            frames: DEFAULT_HEADLESS_FRAMES,
+            overlay: false,
+            screenshot_dir: PathBuf::from(DEFAULT_SCREENSHOT_DIR),
+            screenshot_scale: 1,
+            fault_policy: FaultPolicy::default(),
+            cheats: None,
+            enabled_cheats: Vec::new(),
+            achievements: None,
+            achievement_log: PathBuf::from(DEFAULT_ACHIEVEMENT_LOG),
+            remote: None,
+        }
+    }
+}
+
+impl FrontendOptions {
+
+    /// Parses the arguments (without the program name).
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<FrontendOptions, String> {
+        let mut options = FrontendOptions::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--game" => {
+                    let game: String = parse_value(&arg, args.next())?;
+                    if find_driver(&game).is_none() {
+                        let names: Vec<&str> = DRIVERS.iter().map(|driver| driver.name()).collect();
+                        return Err(format!("unknown game {}, the supported ones are {}", game, names.join(", ")));
+                    }
+                    options.game = game;
+                },
+                "--roms" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.roms = Some(PathBuf::from(path));
+                },
+                "--patch" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.patches.push(PathBuf::from(path));
+                },
+                "--audio-buffer" => options.audio.buffer_size = Some(parse_value(&arg, args.next())?),
+                "--audio-latency" => {
+                    options.audio.latency = Duration::from_millis(parse_value(&arg, args.next())?)
+                },
+                "--audio-wav" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.audio_output = AudioOutputKind::Wav(PathBuf::from(path));
+                },
+                "--no-audio" => options.audio_output = AudioOutputKind::Disabled,
+                "--state" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.state_file = PathBuf::from(path);
+                },
+                "--crt" => options.crt = CrtSettings::arcade(),
+                "--phosphor-decay" => options.crt.phosphor_decay = parse_value(&arg, args.next())?,
+                "--scanlines" => options.crt.scanlines = parse_value(&arg, args.next())?,
+                "--glow" => options.crt.glow = parse_value(&arg, args.next())?,
+                "--curvature" => options.crt.curvature = parse_value(&arg, args.next())?,
+                "--upscaler" => options.upscaler = Some(parse_value(&arg, args.next())?),
+                "--beam-sync" => options.beam_sync = true,
+                "--record" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    if VideoFormat::from_path(path.as_ref()).is_none() {
+                        return Err(format!("{} is not a gif, png or y4m file", path));
+                    }
+                    options.record = Some(PathBuf::from(path));
+                },
+                "--record-audio" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.record_audio = Some(PathBuf::from(path));
+                },
+                "--record-format" => options.record_format = parse_value(&arg, args.next())?,
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = parse_value(&arg, args.next())?,
+                "--overlay" => options.overlay = true,
+                "--screenshot-dir" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.screenshot_dir = PathBuf::from(path);
+                },
+                "--screenshot-scale" => {
+                    options.screenshot_scale = parse_value(&arg, args.next())?;
+                    if !(1..=MAX_NEAREST_FACTOR).contains(&options.screenshot_scale) {
+                        return Err(format!("the screenshot scale must be between 1 and {}", MAX_NEAREST_FACTOR));
+                    }
+                },
+                "--fault-policy" => options.fault_policy = parse_value(&arg, args.next())?,
+                "--cheats" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.cheats = Some(PathBuf::from(path));
+                },
+                "--cheat" => {
+                    let number: usize = parse_value(&arg, args.next())?;
+                    if number == 0 {
+                        return Err("the cheats are numbered from 1".to_string());
+                    }
+                    options.enabled_cheats.push(number - 1);
+                },
+                "--achievements" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.achievements = Some(PathBuf::from(path));
+                },
+                "--achievement-log" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.achievement_log = PathBuf::from(path);
+                },
+                "--remote" => {
+                    let path: String = parse_value(&arg, args.next())?;
+                    options.remote = Some(PathBuf::from(path));
+                },
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Finds the game and reads its ROM, from `--roms` or else the one embedded in the crate, and
+    /// applies the patches of `--patch`.
+    pub fn load_game(&self) -> Result<(&'static dyn BoardDriver, Vec<u8>), String> {
+        let driver = find_driver(&self.game).ok_or_else(|| format!("unknown game {}", self.game))?;
+        let mut rom = match self.roms {
+            Some(ref dir) => read_rom_dir(driver, dir)?,
+            None if driver.name() == INVADERS.name() => INVADERS_ROM.to_vec(),
+            None => return Err(format!(
+                "the ROM of {} is not embedded, give the directory of its files with --roms", driver.title())),
+        };
+        for path in &self.patches {
+            let patch = fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+            rom = apply_patch(&rom, &patch).map_err(|e| format!("could not apply {}: {}", path.display(), e))?;
+            // the board has no room for more
+            if rom.len() != image_size(driver) {
+                return Err(format!("{} changes the size of the ROM image", path.display()));
+            }
+        }
+        Ok((driver, rom))
+    }
+
+    /// Palette of the colour RAM of the game, if it has one, from its colour PROM in `--roms` or
+    /// else from the colour codes.
+    pub fn color_palette(&self, driver: &dyn BoardDriver) -> Option<ColorPalette> {
+        if !driver.has_color_ram() {
+            return None;
+        }
+        let prom = match (&self.roms, driver.color_prom()) {
+            (Some(dir), Some(prom)) => match fs::read(dir.join(prom.name)) {
+                Ok(data) if data.len() == prom.size => Some(data),
+                _ => {
+                    eprintln!("could not read the colour PROM {}, the colours are approximated", prom.name);
+                    None
+                },
+            },
+            _ => None,
+        };
+        Some(color_palette(prom.as_deref()))
+    }
+
+    /// Screen of `driver` with the crt filter, the upscaler, the overlay, the beam sync and the
+    /// colour palette of the options.
+    pub fn screen(&self, driver: &dyn BoardDriver) -> Screen {
+        let mut screen = Screen::new(self.crt, self.upscaler);
+        screen.set_overlay(self.overlay);
+        screen.set_beam_sync(self.beam_sync);
+        screen.set_color_palette(self.color_palette(driver));
+        screen
+    }
+
+    /// Reads the cheats of `--cheats`, or else the ones of the game if it has some, and enables
+    /// the ones of `--cheat` in `memory`.
+    pub fn load_cheats(&self, driver: &dyn BoardDriver, memory: &mut MemoryMap) -> Result<CheatEngine, String> {
+        let cheats = match self.cheats {
+            Some(ref path) => {
+                let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+                let invalid = |e| format!("invalid cheat file {}: {}", path.display(), e);
+                if path.extension().is_some_and(|extension| extension == "xml") {
+                    let imported = import_mame_cheats(&text).map_err(invalid)?;
+                    for name in imported.skipped {
+                        eprintln!("the cheat {} could not be imported", name);
+                    }
+                    imported.cheats
+                } else {
+                    parse_cheats(&text).map_err(invalid)?
+                }
+            },
+            None if driver.name() == INVADERS.name() => parse_cheats(INVADERS_CHEATS).map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        };
+        let mut engine = CheatEngine::new(cheats);
+        for &index in &self.enabled_cheats {
+            if index >= engine.cheats().len() {
+                return Err(format!("there is no cheat {}", index + 1));
+            }
+            engine.set_enabled(index, true, memory);
+        }
+        Ok(engine)
+    }
+
+    /// Reads the achievements of `--achievements`, or else the ones of the game if it has some,
+    /// with the ones already unlocked in the log of `--achievement-log`.
+    pub fn load_achievements(&self, driver: &dyn BoardDriver) -> Result<(AchievementEngine, AchievementLog), String> {
+        let achievements = match self.achievements {
+            Some(ref path) => {
+                let text = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+                parse_achievements(&text).map_err(|e| format!("invalid achievement file {}: {}", path.display(), e))?
+            },
+            None if driver.name() == INVADERS.name() => parse_achievements(INVADERS_ACHIEVEMENTS).map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        };
+        let mut engine = AchievementEngine::new(achievements);
+        let log = AchievementLog::new(&self.achievement_log, driver.name());
+        let unlocked = log.read().map_err(|e| format!("could not read {}: {}", self.achievement_log.display(), e))?;
+        for name in unlocked {
+            engine.mark_unlocked(&name);
+        }
+        Ok((engine, log))
+    }
+
+    /// Opens the audio output, falling back to a silent one if it is not available.
+    pub fn open_audio(&self) -> AudioOutput {
+        let backend: Box<dyn AudioBackend> = match self.audio_output {
+            AudioOutputKind::Device => open_device(self.audio),
+            AudioOutputKind::Wav(ref path) => {
+                match WavBackend::create(path, DEFAULT_SAMPLE_RATE) {
+                    Ok(backend) => Box::new(backend),
+                    Err(e) => {
+                        eprintln!("could not create {}: {}", path.display(), e);
+                        Box::new(NullBackend::new(DEFAULT_SAMPLE_RATE))
+                    },
+                }
+            },
+            AudioOutputKind::Disabled => Box::new(NullBackend::new(DEFAULT_SAMPLE_RATE)),
+        };
+        AudioOutput::new(backend, self.audio)
+    }
+}
+
+#[cfg(feature = "cpal")]
+fn open_device(settings: AudioSettings) -> Box<dyn AudioBackend> {
+    use audio::CpalBackend;
+
+    match CpalBackend::open(settings) {
+        Ok(backend) => Box::new(backend),
+        Err(e) => {
+            eprintln!("could not open the audio device: {}", e);
+            Box::new(NullBackend::new(DEFAULT_SAMPLE_RATE))
+        },
+    }
+}
+
+#[cfg(not(feature = "cpal"))]
+fn open_device(_settings: AudioSettings) -> Box<dyn AudioBackend> {
+    eprintln!("built without audio device support, the sound is disabled");
+    Box::new(NullBackend::new(DEFAULT_SAMPLE_RATE))
+}
+
+/// Reads the files of the ROM set of `driver` in `dir`, or the single image `<name>.rom` if none
+/// of them is there.
+fn read_rom_dir(driver: &dyn BoardDriver, dir: &Path) -> Result<Vec<u8>, String> {
+    let mut files: Vec<(String, Vec<u8>)> = driver.rom_files().iter()
+        .filter_map(|file| fs::read(dir.join(file.name)).ok().map(|data| (file.name.to_string(), data)))
+        .collect();
+    if files.is_empty() {
+        let image = dir.join(format!("{}.rom", driver.name()));
+        let data = fs::read(&image).map_err(|e| format!("could not read {}: {}", image.display(), e))?;
+        files.push((image.display().to_string(), data));
+    }
+    let files: Vec<(&str, &[u8])> = files.iter().map(|(name, data)| (name.as_str(), data.as_slice())).collect();
+    assemble_rom(driver, &files).map_err(|e| e.to_string())
+}
+
+fn parse_value<T: ::std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .ok_or_else(|| format!("missing value for {}", option))?
+        .parse()
+        .map_err(|_| format!("invalid value for {}", option))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use options::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn parse(args: &[&str]) -> Result<FrontendOptions, String> {
+        FrontendOptions::from_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parse_audio_options() {
+        let options = parse(&["--audio-latency", "100", "--audio-buffer", "256", "--audio-wav", "out.wav"]).unwrap();
+        assert_eq!(Duration::from_millis(100), options.audio.latency);
+        assert_eq!(Some(256), options.audio.buffer_size);
+        assert_eq!(None, parse(&[]).unwrap().audio.buffer_size);
+        assert_eq!(AudioOutputKind::Wav("out.wav".into()), options.audio_output);
+    }
+
+    #[test]
+    fn parse_state_file() {
+        assert_eq!(PathBuf::from("space-invaders.state"), parse(&[]).unwrap().state_file);
+        assert_eq!(PathBuf::from("game.state"), parse(&["--state", "game.state"]).unwrap().state_file);
+    }
+
+    #[test]
+    fn parse_crt_options() {
+        assert!(parse(&[]).unwrap().crt.is_disabled());
+        let options = parse(&["--crt", "--glow", "0"]).unwrap();
+        assert_eq!(CrtSettings { glow: 0.0, ..CrtSettings::arcade() }, options.crt);
+        assert!(!options.beam_sync);
+        assert!(parse(&["--beam-sync"]).unwrap().beam_sync);
+    }
+
+    #[test]
+    fn parse_upscaler() {
+        assert_eq!(None, parse(&[]).unwrap().upscaler);
+        assert_eq!(Some(Upscaler::Nearest(3)), parse(&["--upscaler", "nearest3"]).unwrap().upscaler);
+        assert_eq!(Some(Upscaler::Xbr), parse(&["--upscaler", "xbr"]).unwrap().upscaler);
+        assert!(parse(&["--upscaler", "blur"]).is_err());
+    }
+
+    #[test]
+    fn parse_record_options() {
+        let options = parse(&["--headless", "--frames", "120", "--record", "clip.y4m", "--record-audio", "clip.wav"]).unwrap();
+        assert!(options.headless);
+        assert_eq!(120, options.frames);
+        assert_eq!(Some(PathBuf::from("clip.y4m")), options.record);
+        assert_eq!(Some(PathBuf::from("clip.wav")), options.record_audio);
+        assert_eq!(VideoFormat::Apng, parse(&["--record-format", "apng"]).unwrap().record_format);
+        assert!(parse(&["--record", "clip.mp4"]).is_err());
+    }
+
+    #[test]
+    fn parse_screenshot_options() {
+        let options = parse(&[]).unwrap();
+        assert!(!options.overlay);
+        assert_eq!(PathBuf::from("."), options.screenshot_dir);
+        assert_eq!(1, options.screenshot_scale);
+
+        let options = parse(&["--overlay", "--screenshot-dir", "shots", "--screenshot-scale", "3"]).unwrap();
+        assert!(options.overlay);
+        assert_eq!(PathBuf::from("shots"), options.screenshot_dir);
+        assert_eq!(3, options.screenshot_scale);
+        assert!(parse(&["--screenshot-scale", "0"]).is_err());
+        assert!(parse(&["--screenshot-scale", "9"]).is_err());
+    }
+
+    #[test]
+    fn parse_fault_policy() {
+        assert_eq!(FaultPolicy::Log, parse(&[]).unwrap().fault_policy);
+        assert_eq!(FaultPolicy::Halt, parse(&["--fault-policy", "halt"]).unwrap().fault_policy);
+        assert!(parse(&["--fault-policy", "panic"]).is_err());
+    }
+
+    #[test]
+    fn parse_game() {
+        let options = parse(&[]).unwrap();
+        assert_eq!("invaders", options.game);
+        assert_eq!("invaders", options.load_game().unwrap().0.name());
+
+        let options = parse(&["--game", "invaders", "--roms", "roms/invaders"]).unwrap();
+        assert_eq!(Some(PathBuf::from("roms/invaders")), options.roms);
+        assert!(parse(&["--game", "lrescue"]).is_err());
+        assert!(parse(&["--game", "pacman"]).is_err());
+    }
+
+    #[test]
+    fn parse_patches() {
+        assert!(parse(&[]).unwrap().patches.is_empty());
+        let options = parse(&["--patch", "fix.ips", "--patch", "hack.bps"]).unwrap();
+        assert_eq!(vec![PathBuf::from("fix.ips"), PathBuf::from("hack.bps")], options.patches);
+        assert!(options.load_game().err().unwrap().starts_with("could not read fix.ips"));
+    }
+
+    #[test]
+    fn parse_cheat_options() {
+        let mut memory = MemoryMap::new(&INVADERS, &[]);
+        let options = parse(&["--cheat", "2", "--cheat", "3"]).unwrap();
+        assert_eq!(vec![1, 2], options.enabled_cheats);
+        let engine = options.load_cheats(&INVADERS, &mut memory).unwrap();
+        assert_eq!("Infinite lives", engine.cheats()[0].name);
+        assert!(!engine.is_enabled(0) && engine.is_enabled(1));
+
+        assert!(parse(&["--cheat", "4"]).unwrap().load_cheats(&INVADERS, &mut memory).is_err());
+        assert!(parse(&["--cheat", "0"]).is_err());
+        let options = parse(&["--cheats", "missing.cheats"]).unwrap();
+        assert_eq!(Some(PathBuf::from("missing.cheats")), options.cheats);
+        assert!(options.load_cheats(&INVADERS, &mut memory).is_err());
+    }
+
+    #[test]
+    fn parse_achievement_options() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(PathBuf::from("space-invaders-achievements.log"), options.achievement_log);
+        let (engine, _) = parse(&["--achievement-log", "missing.log"]).unwrap().load_achievements(&INVADERS).unwrap();
+        assert_eq!("UFO hunter", engine.achievements()[0].name);
+        assert!(!engine.is_unlocked(0));
+        let options = parse(&["--achievements", "missing.achievements"]).unwrap();
+        assert_eq!(Some(PathBuf::from("missing.achievements")), options.achievements);
+        assert!(options.load_achievements(&INVADERS).is_err());
+    }
+
+    #[test]
+    fn parse_remote_socket() {
+        assert_eq!(None, parse(&[]).unwrap().remote);
+        assert_eq!(Some(PathBuf::from("/tmp/invaders.sock")), parse(&["--remote", "/tmp/invaders.sock"]).unwrap().remote);
+    }
+
+    #[test]
+    fn reject_invalid_options() {
+        assert!(parse(&["--audio-latency"]).is_err());
+        assert!(parse(&["--audio-latency", "soon"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+    }
+}
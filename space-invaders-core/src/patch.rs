@@ -0,0 +1,320 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::error::Error;
+use std::fmt;
+
+use rom::crc32;
+
+/*
+ ROM hacks and fixes are distributed as patches of the ROM image, in one of two formats:
+
+ IPS: "PATCH", then records until "EOF" (optionally followed by the size the image is truncated
+ to, on 3 bytes). A record is an offset (3 bytes) and a size (2 bytes) followed by that many bytes,
+ or, if the size is 0, by a count (2 bytes) and the byte repeated count times. All the numbers are
+ big endian. There is no checksum.
+
+ BPS: "BPS1", the sizes of the source and of the target and the metadata (variable length
+ numbers), then actions until the footer: the crc32 of the source, of the target and of the patch
+ (without this last crc). Each action copies bytes to the target, from the source at the same
+ offset, from the patch, or from anywhere in the source or in the target (relatively to the
+ previous copy of the same kind).
+*/
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const BPS_FOOTER_LEN: usize = 12;
+/// Largest target of a BPS patch: the ROM images of the boards fit in the 64 KiB of the cpu
+const MAX_TARGET_SIZE: usize = 0x10000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// The data is neither an IPS nor a BPS patch
+    UnknownFormat,
+    /// The patch ends in the middle of a record
+    Truncated,
+    /// An action of a BPS patch reads or writes out of its source or its target
+    OutOfBounds,
+    /// The crc32 of a BPS patch does not match: it is corrupted
+    CorruptedPatch,
+    /// The image is not the one the BPS patch was made for
+    WrongSource { expected: u32, actual: u32 },
+    /// The patched image is not the one expected by the BPS patch
+    WrongTarget { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::UnknownFormat => write!(f, "not an IPS or BPS patch"),
+            PatchError::Truncated => write!(f, "truncated patch"),
+            PatchError::OutOfBounds => write!(f, "the patch goes past the end of the image"),
+            PatchError::CorruptedPatch => write!(f, "corrupted patch (wrong crc32)"),
+            PatchError::WrongSource { expected, actual } => write!(
+                f, "the patch is for another ROM (crc32 {:08x} instead of {:08x})", expected, actual),
+            PatchError::WrongTarget { expected, actual } => write!(
+                f, "the patched ROM is wrong (crc32 {:08x} instead of {:08x})", actual, expected),
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+/// Applies an IPS or BPS `patch` (recognized by its header) to the ROM image `rom`, and returns the
+/// patched image. The checksums of BPS patches are verified.
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, &patch[IPS_MAGIC.len()..])
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+fn apply_ips(rom: &[u8], records: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut image = rom.to_vec();
+    let mut reader = PatchReader { data: records };
+    loop {
+        if reader.data.starts_with(IPS_EOF) {
+            reader.bytes(IPS_EOF.len())?;
+            if !reader.data.is_empty() {
+                let size = reader.big_endian(3)?;
+                image.truncate(size);
+            }
+            return Ok(image);
+        }
+        let offset = reader.big_endian(3)?;
+        let size = reader.big_endian(2)?;
+        let (size, bytes) = if size == 0 {
+            let count = reader.big_endian(2)?;
+            (count, None)
+        } else {
+            (size, Some(reader.bytes(size)?))
+        };
+        if image.len() < offset + size {
+            image.resize(offset + size, 0);
+        }
+        match bytes {
+            Some(bytes) => image[offset..(offset + size)].copy_from_slice(bytes),
+            None => {
+                let value = reader.bytes(1)?[0];
+                image[offset..(offset + size)].iter_mut().for_each(|byte| *byte = value);
+            },
+        }
+    }
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_LEN {
+        return Err(PatchError::Truncated);
+    }
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER_LEN);
+    let footer_crc = |index: usize| {
+        let bytes = &footer[(index * 4)..(index * 4 + 4)];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    };
+    if crc32(&patch[..(patch.len() - 4)]) != footer_crc(2) {
+        return Err(PatchError::CorruptedPatch);
+    }
+    let (expected, actual) = (footer_crc(0), crc32(rom));
+    if expected != actual {
+        return Err(PatchError::WrongSource { expected, actual });
+    }
+
+    let mut reader = PatchReader { data: &body[BPS_MAGIC.len()..] };
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    if source_size != rom.len() || target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::OutOfBounds);
+    }
+    let mut target = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0, 0);
+    while !reader.data.is_empty() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        if target.len() + length > target_size {
+            return Err(PatchError::OutOfBounds);
+        }
+        match action & 3 {
+            // source read, at the offset of the output
+            0 => {
+                let start = target.len();
+                target.extend_from_slice(rom.get(start..(start + length)).ok_or(PatchError::OutOfBounds)?);
+            },
+            // target read, from the patch
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // source copy
+            2 => {
+                source_offset = relative_offset(source_offset, reader.number()?)?;
+                let bytes = rom.get(source_offset..(source_offset + length)).ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(bytes);
+                source_offset += length;
+            },
+            // target copy, byte after byte since it can copy what it writes
+            _ => {
+                target_offset = relative_offset(target_offset, reader.number()?)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+    let (expected, actual) = (footer_crc(1), crc32(&target));
+    if target.len() != target_size || expected != actual {
+        return Err(PatchError::WrongTarget { expected, actual });
+    }
+    Ok(target)
+}
+
+/// Moves `offset` by a signed BPS number (the lowest bit is the sign).
+fn relative_offset(offset: usize, number: usize) -> Result<usize, PatchError> {
+    let distance = number >> 1;
+    if number & 1 == 1 {
+        offset.checked_sub(distance).ok_or(PatchError::OutOfBounds)
+    } else {
+        Ok(offset + distance)
+    }
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PatchReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        if self.data.len() < len {
+            return Err(PatchError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn big_endian(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self.bytes(len)?.iter().fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// Variable length number of BPS: 7 bits per byte, the last one has its high bit set, and each
+    /// byte after the first adds its weight so that every number has a single encoding.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut number: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.bytes(1)?[0];
+            number = (byte as usize & 0x7f).checked_mul(shift)
+                .and_then(|value| number.checked_add(value))
+                .ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_shl(7).filter(|&shift| shift != 0).ok_or(PatchError::OutOfBounds)?;
+            number = number.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use patch::*;
+    use rom::crc32;
+
+    fn bps_number(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    /// BPS patch of `source` to `target` with the given actions
+    fn bps_patch(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        bps_number(source.len(), &mut patch);
+        bps_number(target.len(), &mut patch);
+        bps_number(0, &mut patch);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_records() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xaa, 0xbb]);
+        // run of 3 bytes
+        patch.extend_from_slice(&[0, 0, 4, 0, 0, 0, 3, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(vec![0, 0xaa, 0xbb, 3, 0xcc, 0xcc, 0xcc], apply_patch(&[0, 1, 2, 3, 4, 5], &patch).unwrap());
+
+        patch.extend_from_slice(&[0, 0, 2]);
+        assert_eq!(vec![0, 0xaa], apply_patch(&[0, 1, 2, 3, 4, 5], &patch).unwrap());
+        assert_eq!(Err(PatchError::Truncated), apply_patch(&[], &patch[0..9]));
+    }
+
+    #[test]
+    fn bps_actions() {
+        let source = [1, 2, 3, 4, 5, 6];
+        let target = [1, 2, 9, 5, 6, 6, 6, 6, 1, 2];
+        let mut actions = Vec::new();
+        // source read of 2 bytes, target read of 1
+        bps_number(1 << 2, &mut actions);
+        bps_number(1, &mut actions);
+        actions.push(9);
+        // source copy of 2 bytes from 4, target copy of 3 from 4 (repeating the 6 it copies)
+        bps_number((1 << 2) | 2, &mut actions);
+        bps_number(4 << 1, &mut actions);
+        bps_number((2 << 2) | 3, &mut actions);
+        bps_number(4 << 1, &mut actions);
+        // source copy of 2 bytes from 0, backwards from 6
+        bps_number((1 << 2) | 2, &mut actions);
+        bps_number((6 << 1) | 1, &mut actions);
+        let patch = bps_patch(&source, &target, &actions);
+        assert_eq!(target.to_vec(), apply_patch(&source, &patch).unwrap());
+
+        // the patch of another image
+        let wrong_source = [0; 6];
+        assert_eq!(
+            Err(PatchError::WrongSource { expected: crc32(&source), actual: crc32(&wrong_source) }),
+            apply_patch(&wrong_source, &patch));
+        let mut corrupted = patch.clone();
+        corrupted[8] ^= 1;
+        assert_eq!(Err(PatchError::CorruptedPatch), apply_patch(&source, &corrupted));
+    }
+
+    #[test]
+    fn bps_target_is_verified() {
+        let source = [1, 2];
+        let mut actions = Vec::new();
+        bps_number(1 << 2, &mut actions);
+        // the patch claims to give [1, 3]
+        let patch = bps_patch(&source, &[1, 3], &actions);
+        assert!(matches!(apply_patch(&source, &patch), Err(PatchError::WrongTarget { .. })));
+
+        // a consistent patch with a huge target is rejected before anything is allocated
+        let mut patch = BPS_MAGIC.to_vec();
+        bps_number(source.len(), &mut patch);
+        bps_number(usize::MAX >> 8, &mut patch);
+        bps_number(0, &mut patch);
+        patch.extend_from_slice(&crc32(&source).to_le_bytes());
+        patch.extend_from_slice(&[0; 4]);
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(Err(PatchError::OutOfBounds), apply_patch(&source, &patch));
+        assert_eq!(Err(PatchError::UnknownFormat), apply_patch(&source, b"UPS1"));
+    }
+}
@@ -0,0 +1,202 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::io;
+use std::io::{ Seek, SeekFrom, Write };
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use rom::crc32;
+
+/*
+ Minimal PNG and APNG writer, for RGBA 8 bits images. An APNG is a PNG whose first frame is the
+ usual image, followed by the other frames in `fdAT` chunks, each one preceded by a `fcTL` chunk
+ giving its duration. Viewers without APNG support show the first frame.
+*/
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const COLOR_TYPE_RGBA: u8 = 6;
+
+/// Encodes a RGBA image in PNG.
+pub fn encode_png(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    let mut png = Vec::new();
+    write_header(&mut png, width, height).unwrap();
+    write_chunk(&mut png, b"IDAT", &compress(width, rgba).unwrap()).unwrap();
+    write_chunk(&mut png, b"IEND", &[]).unwrap();
+    png
+}
+
+/// Writes RGBA frames in an animated PNG, at a fixed frame rate.
+///
+/// The frame count in the header is only correct once `finish` is called.
+pub struct ApngEncoder<W: Write + Seek> {
+    writer: W,
+    width: usize,
+    height: usize,
+    /// Duration of a frame, in seconds (numerator, denominator)
+    delay: (u16, u16),
+    frames: u32,
+    /// Sequence number of the next `fcTL` or `fdAT` chunk
+    sequence: u32,
+    /// Position of the `acTL` chunk
+    animation_control: u64,
+}
+
+impl<W: Write + Seek> ApngEncoder<W> {
+    pub fn new(mut writer: W, width: usize, height: usize, frame_rate: u16) -> io::Result<ApngEncoder<W>> {
+        write_header(&mut writer, width, height)?;
+        let animation_control = writer.stream_position()?;
+        write_chunk(&mut writer, b"acTL", &animation_control_data(0))?;
+        Ok(ApngEncoder {
+            writer,
+            width,
+            height,
+            delay: (1, frame_rate),
+            frames: 0,
+            sequence: 0,
+            animation_control,
+        })
+    }
+
+    pub fn add_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        assert_eq!(self.width * self.height * 4, rgba.len(), "the frame does not match the size");
+        let mut frame_control = Vec::with_capacity(26);
+        frame_control.extend_from_slice(&self.sequence.to_be_bytes());
+        frame_control.extend_from_slice(&(self.width as u32).to_be_bytes());
+        frame_control.extend_from_slice(&(self.height as u32).to_be_bytes());
+        frame_control.extend_from_slice(&[0; 8]); // x and y offsets
+        frame_control.extend_from_slice(&self.delay.0.to_be_bytes());
+        frame_control.extend_from_slice(&self.delay.1.to_be_bytes());
+        frame_control.extend_from_slice(&[0, 0]); // no dispose, no blend: the frame is replaced
+        write_chunk(&mut self.writer, b"fcTL", &frame_control)?;
+        self.sequence += 1;
+
+        let data = compress(self.width, rgba)?;
+        if self.frames == 0 {
+            write_chunk(&mut self.writer, b"IDAT", &data)?;
+        } else {
+            let mut frame_data = Vec::with_capacity(data.len() + 4);
+            frame_data.extend_from_slice(&self.sequence.to_be_bytes());
+            frame_data.extend_from_slice(&data);
+            write_chunk(&mut self.writer, b"fdAT", &frame_data)?;
+            self.sequence += 1;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the end of the file and the frame count, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.frames == 0 {
+            // a PNG needs at least an image
+            let black = vec![0; self.width * self.height * 4];
+            self.add_frame(&black)?;
+        }
+        write_chunk(&mut self.writer, b"IEND", &[])?;
+        self.writer.seek(SeekFrom::Start(self.animation_control))?;
+        write_chunk(&mut self.writer, b"acTL", &animation_control_data(self.frames))?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Frame count, and play count (0: loop forever)
+fn animation_control_data(frames: u32) -> [u8; 8] {
+    let mut data = [0; 8];
+    data[0..4].copy_from_slice(&frames.to_be_bytes());
+    data
+}
+
+fn write_header<W: Write>(writer: &mut W, width: usize, height: usize) -> io::Result<()> {
+    writer.write_all(&SIGNATURE)?;
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, deflate, adaptive filtering, no interlacing
+    header.extend_from_slice(&[8, COLOR_TYPE_RGBA, 0, 0, 0]);
+    write_chunk(writer, b"IHDR", &header)
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc_data = Vec::with_capacity(data.len() + 4);
+    crc_data.extend_from_slice(kind);
+    crc_data.extend_from_slice(data);
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(&crc_data)?;
+    writer.write_all(&crc32(&crc_data).to_be_bytes())
+}
+
+/// Compressed image data: each row starts with its filter type (0, none).
+fn compress(width: usize, rgba: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    for row in rgba.chunks(width * 4) {
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{ Cursor, Read };
+
+    use flate2::read::ZlibDecoder;
+
+    use png::*;
+
+    /// (kind, data) of each chunk, checking their crc
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&SIGNATURE, &png[0..8]);
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let crc = &rest[(8 + length)..(12 + length)];
+            assert_eq!(&crc32(&rest[4..(8 + length)]).to_be_bytes(), crc);
+            chunks.push((String::from_utf8(rest[4..8].to_vec()).unwrap(), rest[8..(8 + length)].to_vec()));
+            rest = &rest[(12 + length)..];
+        }
+        chunks
+    }
+
+    fn decompress(data: &[u8]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        ZlibDecoder::new(data).read_to_end(&mut raw).unwrap();
+        raw
+    }
+
+    #[test]
+    fn png_chunks() {
+        let rgba = [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 1, 2, 3, 4];
+        let chunks = chunks(&encode_png(2, 2, &rgba));
+        let kinds: Vec<&str> = chunks.iter().map(|chunk| chunk.0.as_str()).collect();
+        assert_eq!(vec!["IHDR", "IDAT", "IEND"], kinds);
+        assert_eq!(vec![0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0], chunks[0].1);
+        assert_eq!(
+            vec![0, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 0, 255, 255, 1, 2, 3, 4],
+            decompress(&chunks[1].1));
+    }
+
+    #[test]
+    fn apng_frames() {
+        let mut encoder = ApngEncoder::new(Cursor::new(Vec::new()), 1, 1, 60).unwrap();
+        for value in 0..3 {
+            encoder.add_frame(&[value, value, value, 255]).unwrap();
+        }
+        let png = encoder.finish().unwrap().into_inner();
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|chunk| chunk.0.as_str()).collect();
+        assert_eq!(vec!["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND"], kinds);
+        // 3 frames, looping
+        assert_eq!(vec![0, 0, 0, 3, 0, 0, 0, 0], chunks[1].1);
+        // sequence numbers and 1/60 s delay
+        assert_eq!(&[0, 0, 0, 3], &chunks[6].1[0..4]);
+        assert_eq!(&[0, 1, 0, 60], &chunks[6].1[20..24]);
+        assert_eq!(&[0, 0, 0, 4], &chunks[7].1[0..4]);
+        assert_eq!(vec![0, 2, 2, 2, 255], decompress(&chunks[7].1[4..]));
+    }
+}
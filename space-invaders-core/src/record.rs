@@ -0,0 +1,398 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{ BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use std::str::FromStr;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use gif;
+use gif::SetParameter;
+
+use audio::{ AudioBackend, WavBackend };
+use png::ApngEncoder;
+use sound::{ SoundLatches, Synth };
+
+/*
+ Recording of the emulated output. The recorder is given every emulated frame, and the frames are
+ written at exactly 60 per second of emulated time, whatever the speed of the host. The sound is
+ synthesized again by the recorder from the latches of each frame, so that the WAV file always has
+ the same duration as the video:
+
+    ffmpeg -i space-invaders.y4m -i space-invaders.wav space-invaders.mp4
+*/
+
+/// Frames per second of the recordings (one per vertical blank)
+pub const RECORD_FRAME_RATE: u16 = 60;
+const RECORD_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// Animated GIF, small but limited to 256 colours and 1/100 s delays
+    Gif,
+    /// Animated PNG, lossless
+    Apng,
+    /// Raw YUV 4:4:4 video, for ffmpeg
+    Y4m,
+}
+
+impl VideoFormat {
+    /// Extension of the files (`png` for APNG, which are also PNG files)
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Apng => "png",
+            VideoFormat::Y4m => "y4m",
+        }
+    }
+
+    /// Format matching the extension of `path`
+    pub fn from_path(path: &Path) -> Option<VideoFormat> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gif") => Some(VideoFormat::Gif),
+            Some("png") | Some("apng") => Some(VideoFormat::Apng),
+            Some("y4m") => Some(VideoFormat::Y4m),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VideoFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VideoFormat::Gif => write!(f, "gif"),
+            VideoFormat::Apng => write!(f, "apng"),
+            VideoFormat::Y4m => write!(f, "y4m"),
+        }
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<VideoFormat, String> {
+        match name {
+            "gif" => Ok(VideoFormat::Gif),
+            "apng" => Ok(VideoFormat::Apng),
+            "y4m" => Ok(VideoFormat::Y4m),
+            _ => Err(format!("unknown video format {}", name)),
+        }
+    }
+}
+
+/// Writes RGBA frames in an animated GIF, looping forever.
+pub struct GifEncoder<W: Write> {
+    encoder: gif::Encoder<W>,
+    width: u16,
+    height: u16,
+    frames: u64,
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(writer: W, width: usize, height: usize) -> io::Result<GifEncoder<W>> {
+        let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &[])?;
+        encoder.set(gif::Repeat::Infinite)?;
+        Ok(GifEncoder { encoder, width: width as u16, height: height as u16, frames: 0 })
+    }
+
+    pub fn add_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        let mut frame = match palette_pixels(rgba) {
+            Some((palette, pixels)) => gif::Frame::from_palette_pixels(self.width, self.height, &pixels, &palette, None),
+            // more than 256 colours (crt filter or xbr), they are quantized
+            None => gif::Frame::from_rgba(self.width, self.height, &mut rgba.to_vec()),
+        };
+        frame.delay = gif_delay(self.frames);
+        self.encoder.write_frame(&frame)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the end of the file.
+    pub fn finish(self) {
+        // the trailer is written when the encoder is dropped
+    }
+}
+
+/// Delay of the frame `index`, in 1/100 s. Frames of 1/60 s are not possible, the delays alternate
+/// between 1 and 2 so that the frames are on time on average.
+fn gif_delay(index: u64) -> u16 {
+    let frame_rate = RECORD_FRAME_RATE as u64;
+    ((index + 1) * 100 / frame_rate - index * 100 / frame_rate) as u16
+}
+
+/// Palette (RGB) and indices of the pixels, if there are 256 colours or less
+fn palette_pixels(rgba: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut pixels = Vec::with_capacity(rgba.len() / 4);
+    for px in rgba.chunks(4) {
+        let color = [px[0], px[1], px[2]];
+        let index = match colors.get(&color) {
+            Some(&index) => index,
+            None if colors.len() < 256 => {
+                let index = colors.len() as u8;
+                colors.insert(color, index);
+                palette.extend_from_slice(&color);
+                index
+            },
+            None => return None,
+        };
+        pixels.push(index);
+    }
+    Some((palette, pixels))
+}
+
+/// Writes RGBA frames in a YUV4MPEG2 video (4:4:4, limited range BT.601).
+pub struct Y4mEncoder<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mEncoder<W> {
+    pub fn new(mut writer: W, width: usize, height: usize) -> io::Result<Y4mEncoder<W>> {
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, RECORD_FRAME_RATE)?;
+        Ok(Y4mEncoder { writer, width, height, planes: vec![0; width * height * 3] })
+    }
+
+    pub fn add_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        let size = self.width * self.height;
+        assert_eq!(size * 4, rgba.len(), "the frame does not match the size");
+        for (i, px) in rgba.chunks(4).enumerate() {
+            let (y, u, v) = yuv(px[0], px[1], px[2]);
+            self.planes[i] = y;
+            self.planes[size + i] = u;
+            self.planes[2 * size + i] = v;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+enum VideoEncoder {
+    Gif(GifEncoder<BufWriter<File>>),
+    Apng(ApngEncoder<BufWriter<File>>),
+    Y4m(Y4mEncoder<BufWriter<File>>),
+}
+
+/// Records the frames in a video file and the sound in a WAV file.
+pub struct Recorder {
+    video: Option<VideoEncoder>,
+    audio: Option<WavBackend<BufWriter<File>>>,
+    synth: Synth,
+    frames: u64,
+}
+
+impl Recorder {
+    /// Starts a recording of `width` x `height` frames. The format of the video is given by the
+    /// extension of its file (`gif`, `png`, `apng` or `y4m`).
+    pub fn create(video: Option<&Path>, audio: Option<&Path>, width: usize, height: usize) -> io::Result<Recorder> {
+        let video = match video {
+            Some(path) => {
+                let format = VideoFormat::from_path(path).ok_or_else(|| io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a gif, png or y4m file", path.display())))?;
+                let writer = BufWriter::new(File::create(path)?);
+                Some(match format {
+                    VideoFormat::Gif => VideoEncoder::Gif(GifEncoder::new(writer, width, height)?),
+                    VideoFormat::Apng => VideoEncoder::Apng(ApngEncoder::new(writer, width, height, RECORD_FRAME_RATE)?),
+                    VideoFormat::Y4m => VideoEncoder::Y4m(Y4mEncoder::new(writer, width, height)?),
+                })
+            },
+            None => None,
+        };
+        let audio = match audio {
+            Some(path) => Some(WavBackend::create(path, RECORD_SAMPLE_RATE)?),
+            None => None,
+        };
+        Ok(Recorder { video, audio, synth: Synth::new(RECORD_SAMPLE_RATE), frames: 0 })
+    }
+
+    /// Starts a recording in `space-invaders-<timestamp>.<extension>` and
+    /// `space-invaders-<timestamp>.wav`, in the current directory. Returns the name of the video.
+    pub fn create_timestamped(format: VideoFormat, width: usize, height: usize) -> io::Result<(Recorder, PathBuf)> {
+        let name = format!("space-invaders-{}", timestamp());
+        let video = PathBuf::from(format!("{}.{}", name, format.extension()));
+        let audio = PathBuf::from(format!("{}.wav", name));
+        let recorder = Recorder::create(Some(&video), Some(&audio), width, height)?;
+        Ok((recorder, video))
+    }
+
+    /// Adds an emulated frame: its RGBA pixels, and the sound `latches` during the frame.
+    pub fn record_frame(&mut self, rgba: &[u8], latches: SoundLatches) -> io::Result<()> {
+        match self.video {
+            Some(VideoEncoder::Gif(ref mut encoder)) => encoder.add_frame(rgba)?,
+            Some(VideoEncoder::Apng(ref mut encoder)) => encoder.add_frame(rgba)?,
+            Some(VideoEncoder::Y4m(ref mut encoder)) => encoder.add_frame(rgba)?,
+            None => (),
+        }
+        if let Some(ref mut audio) = self.audio {
+            let frame_rate = RECORD_FRAME_RATE as u64;
+            let sample_rate = RECORD_SAMPLE_RATE as u64;
+            let sample_count = (self.frames + 1) * sample_rate / frame_rate - self.frames * sample_rate / frame_rate;
+            let mut samples = vec![0.0; sample_count as usize];
+            self.synth.set_latches(latches);
+            self.synth.render(&mut samples);
+            audio.queue(&samples);
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Writes the end of the files.
+    pub fn finish(self) -> io::Result<()> {
+        match self.video {
+            Some(VideoEncoder::Gif(encoder)) => encoder.finish(),
+            Some(VideoEncoder::Apng(encoder)) => { encoder.finish()?; },
+            Some(VideoEncoder::Y4m(encoder)) => { encoder.finish()?; },
+            None => (),
+        }
+        if let Some(audio) = self.audio {
+            audio.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Starts a timestamped recording if there is none, or finishes the current one (record key of the
+/// native frontends). Errors are printed.
+pub fn toggle_recording(recorder: &mut Option<Recorder>, format: VideoFormat, width: usize, height: usize) {
+    if recorder.is_some() {
+        stop_recording(recorder);
+        return;
+    }
+    match Recorder::create_timestamped(format, width, height) {
+        Ok((started, video)) => {
+            eprintln!("recording in {}", video.display());
+            *recorder = Some(started);
+        },
+        Err(e) => eprintln!("could not start the recording: {}", e),
+    }
+}
+
+/// Finishes the current recording, if any. Errors are printed.
+pub fn stop_recording(recorder: &mut Option<Recorder>) {
+    if let Some(current) = recorder.take() {
+        let frames = current.frames();
+        match current.finish() {
+            Ok(()) => eprintln!("recorded {} frames", frames),
+            Err(e) => eprintln!("could not finish the recording: {}", e),
+        }
+    }
+}
+
+/// Records a frame if a recording is running. It is stopped if the files cannot be written.
+pub fn record_frame(recorder: &mut Option<Recorder>, rgba: &[u8], latches: SoundLatches) {
+    let failed = match *recorder {
+        Some(ref mut current) => current.record_frame(rgba, latches).is_err(),
+        None => false,
+    };
+    if failed {
+        eprintln!("could not write the recording, it is stopped");
+        *recorder = None;
+    }
+}
+
+/// Current UTC date and time as `YYYYMMDD-HHMMSS`, for file names.
+pub fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format_timestamp(seconds)
+}
+
+fn format_timestamp(unix_seconds: u64) -> String {
+    // civil date from the number of days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (unix_seconds / 86400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time = unix_seconds % 86400;
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use record::*;
+
+    #[test]
+    fn gif_delays_average_one_sixtieth() {
+        let delays: Vec<u16> = (0..6).map(gif_delay).collect();
+        assert_eq!(vec![1, 2, 2, 1, 2, 2], delays);
+        assert_eq!(100, (0..60).map(gif_delay).sum::<u16>());
+    }
+
+    #[test]
+    fn gif_frames() {
+        let mut encoder = GifEncoder::new(Vec::new(), 2, 1).unwrap();
+        encoder.add_frame(&[255, 255, 255, 255, 0, 0, 0, 255]).unwrap();
+        encoder.add_frame(&[0, 0, 0, 255, 0, 0, 0, 255]).unwrap();
+        assert_eq!(2, encoder.frames);
+        assert_eq!(
+            Some((vec![255, 255, 255, 0, 0, 0], vec![0, 1])),
+            palette_pixels(&[255, 255, 255, 255, 0, 0, 0, 255]));
+        let many_colors: Vec<u8> = (0..300u32).flat_map(|i| vec![i as u8, (i >> 8) as u8, 0, 255]).collect();
+        assert_eq!(None, palette_pixels(&many_colors));
+    }
+
+    #[test]
+    fn y4m_frames() {
+        let mut encoder = Y4mEncoder::new(Cursor::new(Vec::new()), 2, 1).unwrap();
+        encoder.add_frame(&[255, 255, 255, 255, 0, 0, 0, 255]).unwrap();
+        let video = encoder.finish().unwrap().into_inner();
+        let header = b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444\n";
+        assert_eq!(&header[..], &video[..header.len()]);
+        assert_eq!(b"FRAME\n\xeb\x10\x80\x80\x80\x80", &video[header.len()..]);
+    }
+
+    #[test]
+    fn video_formats() {
+        assert_eq!(Some(VideoFormat::Apng), VideoFormat::from_path(Path::new("clip.png")));
+        assert_eq!(Some(VideoFormat::Y4m), VideoFormat::from_path(Path::new("clip.y4m")));
+        assert_eq!(None, VideoFormat::from_path(Path::new("clip.mp4")));
+        for format in &[VideoFormat::Gif, VideoFormat::Apng, VideoFormat::Y4m] {
+            assert_eq!(Ok(*format), format.to_string().parse());
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!("19700101-000000", format_timestamp(0));
+        assert_eq!("20231114-221320", format_timestamp(1_700_000_000));
+        assert_eq!("20240229-120000", format_timestamp(1_709_208_000));
+    }
+}
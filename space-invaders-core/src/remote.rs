@@ -0,0 +1,360 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+/*
+ The remote control lets other programs (tests, tools...) drive a running emulation: a JSON-RPC 2.0
+ server on a unix socket, which reads one request per line and writes one response per line. The
+ requests without id are notifications, without response. The methods are:
+
+ - `press` and `release` {"input"}: an input of the cabinet (`coin`, `p1-start`, `p2-start`,
+   `p1-fire`, `p1-left`, `p1-right`, the same for `p2`, or `tilt`)
+ - `pause` and `resume`: the emulation skips the time while it is paused
+ - `step` {"frames" (default 1)}: emulates frames, even while paused, returns {"frames"}, the
+   number of frames since the start
+ - `read_memory` {"address", "length" (default 1)}: returns the bytes, as the cpu reads them
+ - `write_memory` {"address", "bytes"}: writes bytes where the cpu reads them, ROM included
+ - `screenshot` {"directory", "scale"}: saves a screenshot, returns {"path"}
+ - `save_state` and `load_state` {"path"}: returns {"path"}
+ - `game_state`: returns {"game", "title", "frames", "cycles", "paused", "halted", "muted",
+   "high_score" (null if the game has no known one), "faults", "ports" (the input ports 0 to 2),
+   "achievements" (the names of the unlocked ones)}
+
+ The files default to the ones of the options (`--state`, `--screenshot-dir`...). The requests run
+ on the emulation thread between two runs, like the commands of the frontend.
+*/
+
+#[cfg(all(unix, feature = "remote"))]
+pub use self::server::RemoteServer;
+
+#[cfg(not(all(unix, feature = "remote")))]
+pub use self::disabled::RemoteServer;
+
+#[cfg(all(unix, feature = "remote"))]
+mod server {
+    extern crate serde_json;
+
+    use std::fmt;
+    use std::fs;
+    use std::io::{ self, BufRead, BufReader, Write };
+    use std::os::unix::net::{ UnixListener, UnixStream };
+    use std::path::{ Path, PathBuf };
+    use std::sync::atomic::{ AtomicBool, Ordering };
+    use std::sync::mpsc::{ self, Sender };
+    use std::sync::Arc;
+    use std::thread;
+
+    use self::serde_json::{ json, Map, Value };
+
+    use board::Input;
+    use emulator::Emulator;
+    use host::{ AudioSink, EmulatorCall, HostEvent };
+    use memory::MEMORY_SIZE;
+    use options::FrontendOptions;
+    use state::{ high_score, save_state_file };
+    use upscale::MAX_NEAREST_FACTOR;
+
+    const PARSE_ERROR: i64 = -32700;
+    const INVALID_REQUEST: i64 = -32600;
+    const METHOD_NOT_FOUND: i64 = -32601;
+    const INVALID_PARAMS: i64 = -32602;
+    /// The request is valid but failed (a file, a stopped emulation...)
+    const SERVER_ERROR: i64 = -32000;
+
+    /// Frames emulated by a `step` at most (a minute)
+    const MAX_STEP_FRAMES: u64 = 3600;
+
+    /// Server of the remote control, listening on its socket until dropped (the socket file is
+    /// removed then).
+    pub struct RemoteServer {
+        path: PathBuf,
+        stopped: Arc<AtomicBool>,
+    }
+
+    impl RemoteServer {
+        /// Listens on the unix socket `path`, and sends the requests to the emulation through
+        /// `events`. The socket file left by a crash is replaced, not the one of a running server.
+        pub fn start(path: &Path, options: &FrontendOptions, events: Sender<HostEvent>) -> io::Result<RemoteServer> {
+            let listener = match UnixListener::bind(path) {
+                Err(ref e) if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+                    fs::remove_file(path)?;
+                    UnixListener::bind(path)?
+                },
+                listener => listener?,
+            };
+            let stopped = Arc::new(AtomicBool::new(false));
+            let server_stopped = stopped.clone();
+            let options = options.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if server_stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    match stream {
+                        Ok(stream) => {
+                            let connection = Connection { events: events.clone(), options: options.clone() };
+                            thread::spawn(move || {
+                                if let Err(e) = connection.serve(stream) {
+                                    eprintln!("remote control connection closed: {}", e);
+                                }
+                            });
+                        },
+                        Err(e) => eprintln!("remote control connection failed: {}", e),
+                    }
+                }
+            });
+            Ok(RemoteServer { path: path.to_path_buf(), stopped })
+        }
+    }
+
+    impl Drop for RemoteServer {
+        fn drop(&mut self) {
+            self.stopped.store(true, Ordering::SeqCst);
+            // wakes up the thread waiting for a connection
+            let _ = UnixStream::connect(&self.path);
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Error object of a response
+    struct RpcError {
+        code: i64,
+        message: String,
+    }
+
+    impl RpcError {
+        fn new<M: Into<String>>(code: i64, message: M) -> RpcError {
+            RpcError { code, message: message.into() }
+        }
+    }
+
+    fn invalid_params<M: Into<String>>(message: M) -> RpcError {
+        RpcError::new(INVALID_PARAMS, message)
+    }
+
+    fn stopped() -> RpcError {
+        RpcError::new(SERVER_ERROR, "the emulation is stopped")
+    }
+
+    /// A client, served on a thread of its own.
+    struct Connection {
+        events: Sender<HostEvent>,
+        options: FrontendOptions,
+    }
+
+    impl Connection {
+        fn serve(&self, stream: UnixStream) -> io::Result<()> {
+            let mut writer = stream.try_clone()?;
+            for line in BufReader::new(stream).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(response) = self.respond(&line) {
+                    writeln!(writer, "{}", response)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Response to a request, `None` for a notification.
+        fn respond(&self, request: &str) -> Option<Value> {
+            let request: Value = match serde_json::from_str(request) {
+                Ok(request) => request,
+                Err(e) => return Some(response(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())))),
+            };
+            let id = request.get("id").cloned();
+            let version = request.get("jsonrpc").and_then(Value::as_str);
+            let method = request.get("method").and_then(Value::as_str);
+            let (id, result) = match (version, method) {
+                (Some("2.0"), Some(method)) => {
+                    let result = match request.get("params") {
+                        None => self.call(method, &Map::new()),
+                        Some(Value::Object(params)) => self.call(method, params),
+                        Some(_) => Err(invalid_params("the parameters must be an object")),
+                    };
+                    (id?, result)
+                },
+                _ => (id.unwrap_or(Value::Null), Err(RpcError::new(INVALID_REQUEST, "not a JSON-RPC 2.0 request"))),
+            };
+            Some(response(id, result))
+        }
+
+        fn call(&self, method: &str, params: &Map<String, Value>) -> Result<Value, RpcError> {
+            match method {
+                "press" | "release" => {
+                    let input: Input = required(string(params, "input")?, "input")?.parse().map_err(invalid_params)?;
+                    self.events.send(HostEvent::Input(input, method == "press")).map_err(|_| stopped())?;
+                    Ok(Value::Null)
+                },
+                "pause" | "resume" => {
+                    let paused = method == "pause";
+                    self.run(move |emulator, _| {
+                        emulator.set_paused(paused);
+                        Ok(Value::Null)
+                    })
+                },
+                "step" => {
+                    let frames = integer(params, "frames")?.unwrap_or(1);
+                    if frames > MAX_STEP_FRAMES {
+                        return Err(invalid_params(format!("at most {} frames are emulated at once", MAX_STEP_FRAMES)));
+                    }
+                    self.run(move |emulator, audio| {
+                        emulator.step(frames as u32, audio);
+                        Ok(json!({ "frames": emulator.frames() }))
+                    })
+                },
+                "read_memory" => {
+                    let address = required(integer(params, "address")?, "address")?;
+                    let length = integer(params, "length")?.unwrap_or(1);
+                    check_range(address, length)?;
+                    self.run(move |emulator, _| {
+                        let memory = emulator.cpu().memory_map();
+                        let bytes: Vec<u8> = (address..(address + length)).map(|address| memory.peek(address as u16)).collect();
+                        Ok(json!(bytes))
+                    })
+                },
+                "write_memory" => {
+                    let address = required(integer(params, "address")?, "address")?;
+                    let bytes = required(params.get("bytes").and_then(Value::as_array), "bytes")?;
+                    let bytes = bytes.iter()
+                        .map(|byte| byte.as_u64().filter(|&byte| byte <= 0xff).map(|byte| byte as u8))
+                        .collect::<Option<Vec<u8>>>()
+                        .ok_or_else(|| invalid_params("the bytes must be integers from 0 to 255"))?;
+                    check_range(address, bytes.len() as u64)?;
+                    self.run(move |emulator, _| {
+                        let memory = emulator.cpu_mut().memory_map_mut();
+                        for (offset, &byte) in bytes.iter().enumerate() {
+                            memory.poke((address as usize + offset) as u16, byte);
+                        }
+                        Ok(Value::Null)
+                    })
+                },
+                "screenshot" => {
+                    let directory = string(params, "directory")?.map_or(self.options.screenshot_dir.clone(), PathBuf::from);
+                    let scale = integer(params, "scale")?.map_or(self.options.screenshot_scale, |scale| scale as usize);
+                    if !(1..=MAX_NEAREST_FACTOR).contains(&scale) {
+                        return Err(invalid_params(format!("the scale must be between 1 and {}", MAX_NEAREST_FACTOR)));
+                    }
+                    self.run(move |emulator, _| {
+                        let path = emulator.screen().screenshot(&directory, scale).map_err(|e| {
+                            RpcError::new(SERVER_ERROR, format!("could not save the screenshot in {}: {}", directory.display(), e))
+                        })?;
+                        Ok(json!({ "path": path.display().to_string() }))
+                    })
+                },
+                "save_state" => {
+                    let path = string(params, "path")?.map_or(self.options.state_file.clone(), PathBuf::from);
+                    self.run(move |emulator, _| {
+                        save_state_file(&path, emulator.cpu()).map_err(|e| {
+                            RpcError::new(SERVER_ERROR, format!("could not save the state in {}: {}", path.display(), e))
+                        })?;
+                        Ok(json!({ "path": path.display().to_string() }))
+                    })
+                },
+                "load_state" => {
+                    let path = string(params, "path")?.map_or(self.options.state_file.clone(), PathBuf::from);
+                    let state = fs::read(&path).map_err(|e| load_error(&path, &e))?;
+                    self.run(move |emulator, _| {
+                        emulator.load_state(&state).map_err(|e| load_error(&path, &e))?;
+                        Ok(json!({ "path": path.display().to_string() }))
+                    })
+                },
+                "game_state" => {
+                    self.run(|emulator, audio| {
+                        let machine = emulator.cpu().data_bus().machine();
+                        let driver = machine.driver();
+                        let achievements = emulator.achievements();
+                        let unlocked: Vec<&str> = achievements.achievements().iter().enumerate()
+                            .filter(|&(index, _)| achievements.is_unlocked(index))
+                            .map(|(_, achievement)| achievement.name.as_str())
+                            .collect();
+                        Ok(json!({
+                            "game": driver.name(),
+                            "title": driver.title(),
+                            "frames": emulator.frames(),
+                            "cycles": emulator.cpu().cycles(),
+                            "paused": emulator.is_paused(),
+                            "halted": machine.is_halted(),
+                            "muted": audio.is_muted(),
+                            "high_score": high_score(driver, emulator.cpu().memory()),
+                            "faults": machine.faults().total(),
+                            "ports": emulator.input_latch().ports(),
+                            "achievements": unlocked,
+                        }))
+                    })
+                },
+                _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method {}", method))),
+            }
+        }
+
+        /// Runs `function` on the emulation thread, and waits for its result.
+        fn run<F>(&self, function: F) -> Result<Value, RpcError>
+        where F: FnOnce(&mut Emulator, &mut dyn AudioSink) -> Result<Value, RpcError> + Send + 'static {
+            let (sender, receiver) = mpsc::channel();
+            let call = EmulatorCall::new(move |emulator, audio| {
+                let _ = sender.send(function(emulator, audio));
+            });
+            self.events.send(HostEvent::Call(call)).map_err(|_| stopped())?;
+            // the call is dropped without answer if the emulation stops first
+            receiver.recv().map_err(|_| stopped())?
+        }
+    }
+
+    fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+        match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code, "message": e.message } }),
+        }
+    }
+
+    /// Integer parameter `name`, `None` if it is missing
+    fn integer(params: &Map<String, Value>, name: &str) -> Result<Option<u64>, RpcError> {
+        match params.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value.as_u64().map(Some).ok_or_else(|| invalid_params(format!("{} must be a positive integer", name))),
+        }
+    }
+
+    /// String parameter `name`, `None` if it is missing
+    fn string<'a>(params: &'a Map<String, Value>, name: &str) -> Result<Option<&'a str>, RpcError> {
+        match params.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value.as_str().map(Some).ok_or_else(|| invalid_params(format!("{} must be a string", name))),
+        }
+    }
+
+    fn required<T>(value: Option<T>, name: &str) -> Result<T, RpcError> {
+        value.ok_or_else(|| invalid_params(format!("missing parameter {}", name)))
+    }
+
+    fn load_error(path: &Path, e: &dyn fmt::Display) -> RpcError {
+        RpcError::new(SERVER_ERROR, format!("could not load the state from {}: {}", path.display(), e))
+    }
+
+    fn check_range(address: u64, length: u64) -> Result<(), RpcError> {
+        if address.saturating_add(length) > MEMORY_SIZE as u64 {
+            return Err(invalid_params(format!("the memory ends at 0x{:04x}", MEMORY_SIZE)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(unix, feature = "remote")))]
+mod disabled {
+    use std::io;
+    use std::path::Path;
+    use std::sync::mpsc::Sender;
+
+    use host::HostEvent;
+    use options::FrontendOptions;
+
+    /// Stand-in of the server in the builds without the `remote` feature, which cannot start.
+    pub struct RemoteServer;
+
+    impl RemoteServer {
+        pub fn start(_path: &Path, _options: &FrontendOptions, _events: Sender<HostEvent>) -> io::Result<RemoteServer> {
+            Err(io::Error::other("built without remote control support"))
+        }
+    }
+}
@@ -0,0 +1,243 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::error::Error;
+use std::fmt;
+
+use board::{ BoardDriver, DRIVERS, INVADERS };
+
+/*
+ The games are stored in EPROMs on the board (invaders.h, .g, .f and .e in MAME for space
+ invaders), mapped one after the other from 0x0000 and, for the bigger games, from 0x4000. Dumps
+ are found either as these files or as a single image. When their CRC32 is known, the files are
+ recognized by it rather than by their name, which changes from one dump to the other.
+*/
+
+/// Size of the space invaders ROM image
+pub const ROM_SIZE: usize = 0x2000;
+
+/// One file of a ROM set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RomFile {
+    /// Name in the MAME set
+    pub name: &'static str,
+    /// Address of the file in memory
+    pub offset: usize,
+    pub size: usize,
+    pub crc: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RomError {
+    /// A file has neither the size of the image nor the size of a part
+    WrongSize { name: String, size: usize },
+    /// A file has the right size but is not a known dump
+    UnknownDump { name: String, crc: u32 },
+    /// Some parts of the set are missing
+    MissingParts(Vec<&'static str>),
+    /// The same part was given twice
+    DuplicatePart(&'static str),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::WrongSize { ref name, size } => write!(
+                f, "{} is {} bytes, which is neither a ROM image nor a part of one", name, size),
+            RomError::UnknownDump { ref name, crc } => write!(
+                f, "{} (crc32 {:08x}) is not a known dump", name, crc),
+            RomError::MissingParts(ref parts) => write!(f, "missing {}", parts.join(", ")),
+            RomError::DuplicatePart(part) => write!(f, "{} was given twice", part),
+        }
+    }
+}
+
+impl Error for RomError {}
+
+/// Whether `rom` is the known 8 KiB space invaders image.
+pub fn is_known_rom(rom: &[u8]) -> bool {
+    rom.len() == ROM_SIZE && INVADERS.image_crc() == Some(crc32(rom))
+}
+
+/// Size of the image of the ROM of `driver`, mapped from 0x0000 (the areas between the files,
+/// like the RAM, are zeros).
+pub fn image_size(driver: &dyn BoardDriver) -> usize {
+    driver.rom_files().iter().map(|file| file.offset + file.size).max().unwrap_or(0)
+}
+
+/// Builds the ROM image of `driver` from the files of a dump (`(file name, content)`), either a
+/// single image (when its CRC32 is known) or the parts of the set in any order. The colour PROM
+/// may be among the parts, it is not in the image (see `find_color_prom`).
+pub fn assemble_rom(driver: &dyn BoardDriver, files: &[(&str, &[u8])]) -> Result<Vec<u8>, RomError> {
+    let parts = driver.rom_files();
+    let size = image_size(driver);
+    let mut rom = vec![0; size];
+    let mut found = vec![false; parts.len()];
+
+    if let (&[(name, data)], Some(crc)) = (files, driver.image_crc()) {
+        if data.len() == size {
+            check_crc(name, data, crc)?;
+            return Ok(data.to_vec());
+        }
+    }
+    let color_prom = driver.color_prom();
+    for &(name, data) in files {
+        if color_prom.is_some_and(|prom| is_file(&prom, name, data)) {
+            continue;
+        }
+        if !parts.iter().any(|part| part.size == data.len()) {
+            return Err(RomError::WrongSize { name: name.to_string(), size: data.len() });
+        }
+        let index = find_part(parts, name, data)?;
+        if found[index] {
+            return Err(RomError::DuplicatePart(parts[index].name));
+        }
+        found[index] = true;
+        let offset = parts[index].offset;
+        rom[offset..(offset + data.len())].copy_from_slice(data);
+    }
+
+    let missing: Vec<&'static str> = parts.iter().zip(found.iter())
+        .filter(|&(_, found)| !found)
+        .map(|(part, _)| part.name)
+        .collect();
+    if !missing.is_empty() {
+        return Err(RomError::MissingParts(missing));
+    }
+    Ok(rom)
+}
+
+/// Finds the game of a dump among the supported ones, and builds its ROM image (see
+/// `assemble_rom`). The error is the one of the game the files look the most like.
+pub fn identify_rom(files: &[(&str, &[u8])]) -> Result<(&'static dyn BoardDriver, Vec<u8>), RomError> {
+    let mut best_error: Option<RomError> = None;
+    let mut parts_recognized = false;
+    for &driver in DRIVERS.iter() {
+        match assemble_rom(driver, files) {
+            Ok(rom) => return Ok((driver, rom)),
+            Err(e) => {
+                let recognized = matches!(e, RomError::MissingParts(_) | RomError::DuplicatePart(_));
+                if best_error.is_none() || (recognized && !parts_recognized) {
+                    parts_recognized = recognized;
+                    best_error = Some(e);
+                }
+            },
+        }
+    }
+    Err(best_error.expect("there is at least one driver"))
+}
+
+/// Colour PROM of `driver` among the files of a dump, if it has one.
+pub fn find_color_prom<'a>(driver: &dyn BoardDriver, files: &[(&str, &'a [u8])]) -> Option<&'a [u8]> {
+    let prom = driver.color_prom()?;
+    files.iter().find(|&&(name, data)| is_file(&prom, name, data)).map(|&(_, data)| data)
+}
+
+/// Index of the part `data` is, by CRC32 if it is known or else by name.
+fn find_part(parts: &[RomFile], name: &str, data: &[u8]) -> Result<usize, RomError> {
+    let crc = crc32(data);
+    parts.iter()
+        .position(|part| part.crc == Some(crc))
+        .or_else(|| parts.iter().position(|part| part.crc.is_none() && is_file(part, name, data)))
+        .ok_or_else(|| RomError::UnknownDump { name: name.to_string(), crc })
+}
+
+/// Whether `data`, named `name` (with or without its directory), is `file` according to its name
+/// and size.
+fn is_file(file: &RomFile, name: &str, data: &[u8]) -> bool {
+    let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    file.size == data.len() && file.name.eq_ignore_ascii_case(file_name)
+}
+
+fn check_crc(name: &str, data: &[u8], expected: u32) -> Result<(), RomError> {
+    let crc = crc32(data);
+    if crc == expected {
+        Ok(())
+    } else {
+        Err(RomError::UnknownDump { name: name.to_string(), crc })
+    }
+}
+
+/// CRC32 (as in zip files and MAME ROM sets)
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use board::{ BalloonBomber, INVADERS };
+    use rom::*;
+    use INVADERS_ROM;
+
+    const PART_SIZE: usize = 0x800;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn split_files_in_any_order() {
+        if !is_known_rom(INVADERS_ROM) {
+            // built without the ROM
+            return;
+        }
+        let parts: Vec<(&str, &[u8])> = vec![
+            ("e", &INVADERS_ROM[0x1800..0x2000]),
+            ("h", &INVADERS_ROM[0x0000..0x0800]),
+            ("f", &INVADERS_ROM[0x1000..0x1800]),
+            ("g", &INVADERS_ROM[0x0800..0x1000]),
+        ];
+        assert_eq!(INVADERS_ROM, &assemble_rom(&INVADERS, &parts).unwrap()[..]);
+        assert_eq!(INVADERS_ROM, &assemble_rom(&INVADERS, &[("invaders.rom", INVADERS_ROM)]).unwrap()[..]);
+        assert_eq!("invaders", identify_rom(&parts).unwrap().0.name());
+
+        assert_eq!(
+            Err(RomError::MissingParts(vec!["invaders.g", "invaders.f"])),
+            assemble_rom(&INVADERS, &[parts[0], parts[1]]));
+        assert_eq!(Err(RomError::DuplicatePart("invaders.e")), assemble_rom(&INVADERS, &[parts[0], parts[0]]));
+    }
+
+    #[test]
+    fn reject_unknown_dumps() {
+        let zeros = vec![0; PART_SIZE];
+        assert_eq!(
+            Err(RomError::UnknownDump { name: "zeros".to_string(), crc: crc32(&zeros) }),
+            assemble_rom(&INVADERS, &[("zeros", &zeros)]));
+        assert_eq!(
+            Err(RomError::WrongSize { name: "short".to_string(), size: 3 }),
+            assemble_rom(&INVADERS, &[("short", &[1, 2, 3])]));
+        assert_eq!(
+            Err(RomError::UnknownDump { name: "zeros".to_string(), crc: crc32(&zeros) }),
+            identify_rom(&[("zeros", &zeros)]).map(|_| ()));
+    }
+
+    #[test]
+    fn parts_without_crc_are_found_by_name() {
+        let part = |value: u8| vec![value; PART_SIZE];
+        let parts = [part(1), part(2), part(3), part(4), part(5)];
+        let names = ["tn01", "tn02", "dumps/TN03", "tn04", "tn05-1"];
+        let files: Vec<(&str, &[u8])> = names.iter().cloned().zip(parts.iter().map(|p| p.as_slice())).collect();
+
+        let rom = assemble_rom(&BalloonBomber, &files).unwrap();
+        assert_eq!(0x4800, rom.len());
+        assert_eq!(3, rom[0x1000]);
+        assert_eq!(0, rom[0x2000]);
+        assert_eq!(5, rom[0x4000]);
+
+        let prom = vec![7; 0x400];
+        let mut with_prom = files.clone();
+        with_prom.push(("TN06", &prom));
+        assert_eq!(rom, assemble_rom(&BalloonBomber, &with_prom).unwrap());
+        assert_eq!(Some(&prom[..]), find_color_prom(&BalloonBomber, &with_prom));
+        assert_eq!(None, find_color_prom(&BalloonBomber, &files));
+        // only the supported games are identified, by the CRC32 of their files
+        assert!(identify_rom(&files).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::time::Duration;
+
+use cpu::Cpu8080;
+use frame::{ MID_SCREEN_INTERRUPT, VBLANK_INTERRUPT };
+use machine::{ SpaceInvaderDataBus, CPU_FREQUENCY };
+
+/// The board sends RST 1 and RST 2 once per frame, 60 frames per second
+pub const INTERRUPTS_PER_SECOND: u64 = 120;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Decides how far the cpu runs and when it receives the interrupts of the board, in cycles.
+///
+/// The frontends give it time (or cycles, or frames) to emulate, and it converts them to a target
+/// cycle, keeping the fractions of cycle for the next call. The interrupts are placed at exact
+/// positions: the `n`th one is `n / 120` s after the start, rounded down to the cycle, and is sent
+/// at the end of the instruction running at that cycle. The cpu stops at the end of the
+/// instruction running at the target: it overshoots the target by a few cycles, which are taken
+/// from the next run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scheduler {
+    /// Cycle of the start, the interrupts are counted from there
+    origin: u64,
+    /// Interrupts sent since the start
+    interrupts: u64,
+    /// Cycle the cpu has to reach
+    target: u64,
+    /// Time given to `add_time` and not converted to cycles yet, in nanoseconds times the
+    /// frequency of the cpu (less than a cycle)
+    time_debt: u128,
+}
+
+impl Scheduler {
+    /// Scheduler of a cpu at the cycle `cycles`, whose first interrupt is RST 1 half a frame later.
+    pub fn new(cycles: u64) -> Scheduler {
+        Scheduler { origin: cycles, interrupts: 0, target: cycles, time_debt: 0 }
+    }
+
+    /// Starts again from the cycle `cycles` (e.g. after loading a state, which sets the cycles of
+    /// the cpu), with RST 1 half a frame later. The time owed is kept.
+    pub fn resync(&mut self, cycles: u64) {
+        self.origin = cycles;
+        self.interrupts = 0;
+        self.target = cycles;
+    }
+
+    /// Cycle the cpu runs to on the next `run`
+    pub fn target(&self) -> u64 {
+        self.target
+    }
+
+    /// Number of interrupts sent since the start (or the last `resync`)
+    pub fn interrupts(&self) -> u64 {
+        self.interrupts
+    }
+
+    /// Number of frames (vertical blanks) since the start (or the last `resync`)
+    pub fn frames(&self) -> u64 {
+        self.interrupts / 2
+    }
+
+    /// Cycle and RST number of the next interrupt
+    pub fn next_interrupt(&self) -> (u64, u8) {
+        let number = self.interrupts + 1;
+        let rst = if number % 2 == 1 { MID_SCREEN_INTERRUPT } else { VBLANK_INTERRUPT };
+        (self.interrupt_cycle(number), rst)
+    }
+
+    /// Cycle of the next vertical blank, the end of the current frame
+    pub fn next_frame(&self) -> u64 {
+        self.interrupt_cycle((self.interrupts / 2 + 1) * 2)
+    }
+
+    pub fn add_cycles(&mut self, cycles: u64) {
+        self.target += cycles;
+    }
+
+    /// Adds the cycles run by the cpu in `duration`. The fraction of a cycle left is added on the
+    /// next call.
+    pub fn add_time(&mut self, duration: Duration) {
+        let total = duration.as_nanos() * CPU_FREQUENCY as u128 + self.time_debt;
+        self.target += (total / NANOS_PER_SECOND) as u64;
+        self.time_debt = total % NANOS_PER_SECOND;
+    }
+
+    /// Moves the target to the next vertical blank, so that `run` emulates up to the end of the
+    /// current frame.
+    pub fn add_frame(&mut self) {
+        self.target = self.target.max(self.next_frame());
+    }
+
+    /// Runs `cpu` up to the target, and sends it the interrupts on the way (except to a machine
+    /// halted by a fault). `before_interrupt` is called with the RST number before each
+    /// interrupt. Returns the overshoot: the number of cycles run after the target.
+    pub fn run<F>(&mut self, cpu: &mut Cpu8080<SpaceInvaderDataBus>, mut before_interrupt: F) -> u64
+    where F: FnMut(&mut Cpu8080<SpaceInvaderDataBus>, u8) {
+        loop {
+            let (interrupt_cycle, rst) = self.next_interrupt();
+            let stop = self.target.min(interrupt_cycle);
+            while cpu.cycles() < stop {
+                cpu.step();
+            }
+            if cpu.cycles() >= interrupt_cycle {
+                before_interrupt(cpu, rst);
+                // a machine stopped by a fault keeps its state
+                if !cpu.data_bus().machine().is_halted() {
+                    cpu.interrupt(rst);
+                }
+                self.interrupts += 1;
+            }
+            if cpu.cycles() >= self.target {
+                return cpu.cycles() - self.target;
+            }
+        }
+    }
+
+    fn interrupt_cycle(&self, number: u64) -> u64 {
+        self.origin + number * CPU_FREQUENCY / INTERRUPTS_PER_SECOND
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use cpu::Cpu8080;
+    use frame::{ MID_SCREEN_INTERRUPT, VBLANK_INTERRUPT };
+    use machine::{ SpaceInvaderDataBus, SpaceInvaderMachine, CPU_FREQUENCY };
+    use memory::MemoryMap;
+    use scheduler::*;
+
+    /// Longest instruction of the 8080 (conditional CALL taken), plus the RST of an interrupt
+    const MAX_OVERSHOOT: u64 = 17 + 11;
+
+    /// Cpu looping on `JMP` with the interrupts enabled, the RST handlers only enable them again
+    fn looping_cpu() -> Cpu8080<SpaceInvaderDataBus> {
+        let mut rom = vec![0; 0x18];
+        rom[0..4].copy_from_slice(&[0xfb, 0xc3, 0x01, 0x00]);
+        rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]);
+        rom[0x10..0x12].copy_from_slice(&[0xfb, 0xc9]);
+        let machine = SpaceInvaderMachine::new();
+        Cpu8080::new(MemoryMap::new(machine.driver(), &rom), SpaceInvaderDataBus::new(machine))
+    }
+
+    #[test]
+    fn exactly_120_interrupts_per_second() {
+        let mut cpu = looping_cpu();
+        let mut scheduler = Scheduler::new(0);
+        let mut rsts = Vec::new();
+        // 1 s in steps of 7 ms, then 1 s in steps of 333.333 µs (666.666 cycles)
+        for step in (0..142).map(|_| Duration::from_millis(7)).chain(Some(Duration::from_millis(6)))
+            .chain((0..3000).map(|_| Duration::from_nanos(333_333))).chain(Some(Duration::from_nanos(1000))) {
+            scheduler.add_time(step);
+            let overshoot = scheduler.run(&mut cpu, |_, rst| rsts.push(rst));
+            assert!(overshoot < MAX_OVERSHOOT);
+            if scheduler.target() == CPU_FREQUENCY {
+                assert_eq!(INTERRUPTS_PER_SECOND as usize, rsts.len());
+            }
+        }
+        assert_eq!(2 * CPU_FREQUENCY, scheduler.target());
+        assert_eq!(2 * INTERRUPTS_PER_SECOND, scheduler.interrupts());
+        assert_eq!(120, scheduler.frames());
+        assert!(rsts.chunks(2).all(|pair| pair == [MID_SCREEN_INTERRUPT, VBLANK_INTERRUPT]));
+    }
+
+    #[test]
+    fn interrupts_are_sent_at_their_cycle() {
+        let mut cpu = looping_cpu();
+        let mut scheduler = Scheduler::new(0);
+        let mut cycles = Vec::new();
+        for _ in 0..60 {
+            scheduler.add_frame();
+            let overshoot = scheduler.run(&mut cpu, |cpu, _| cycles.push(cpu.cycles()));
+            assert!(overshoot < MAX_OVERSHOOT);
+        }
+        assert_eq!(120, cycles.len());
+        for (number, &cycle) in (1..).zip(&cycles) {
+            let exact = number * CPU_FREQUENCY / INTERRUPTS_PER_SECOND;
+            assert!(cycle >= exact && cycle < exact + MAX_OVERSHOOT, "interrupt {} at {}", number, cycle);
+        }
+        // the overshoot is not added to the next frames
+        assert!(cpu.cycles() >= CPU_FREQUENCY && cpu.cycles() < CPU_FREQUENCY + MAX_OVERSHOOT);
+    }
+
+    #[test]
+    fn small_budgets_add_up() {
+        let mut cpu = looping_cpu();
+        let mut scheduler = Scheduler::new(0);
+        let mut interrupts = 0;
+        // half a cycle at a time
+        for _ in 0..(2 * CPU_FREQUENCY / 10) {
+            scheduler.add_time(Duration::from_nanos(250));
+            scheduler.run(&mut cpu, |_, _| interrupts += 1);
+        }
+        assert_eq!(CPU_FREQUENCY / 10, scheduler.target());
+        assert_eq!(12, interrupts);
+    }
+
+    #[test]
+    fn resync_after_a_state_is_loaded() {
+        let mut scheduler = Scheduler::new(0);
+        scheduler.add_cycles(100_000);
+        scheduler.run(&mut looping_cpu(), |_, _| ());
+        scheduler.resync(5_000_000);
+        assert_eq!(0, scheduler.interrupts());
+        assert_eq!((5_016_666, MID_SCREEN_INTERRUPT), scheduler.next_interrupt());
+        assert_eq!(5_033_333, scheduler.next_frame());
+        scheduler.add_frame();
+        assert_eq!(5_033_333, scheduler.target());
+    }
+}
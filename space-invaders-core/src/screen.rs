@@ -0,0 +1,190 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{ Path, PathBuf };
+
+use crt::{ CrtFilter, CrtSettings };
+use frame::{
+    beam_lines, ColorPalette, Frame, COLOR_RAM_END, COLOR_RAM_START, SCREEN_HEIGHT, SCREEN_WIDTH, VRAM_END, VRAM_START,
+};
+use record::timestamp;
+use upscale::Upscaler;
+
+/// What the frontends display: the decoded frame, with the crt post-processing and the upscaling
+/// if they are enabled.
+pub struct Screen {
+    frame: Frame,
+    crt: Option<CrtFilter>,
+    upscaler: Option<Upscaler>,
+    upscaled: Vec<u8>,
+    /// The video RAM is taken at the interrupts, like the beam of the monitor, rather than at the
+    /// end of the frame
+    beam_sync: bool,
+    /// Lines taken by `sample_beam` which changed since the last `update`
+    beam_changed: bool,
+    /// `pixels` changed since the last `take_changed`
+    changed: bool,
+}
+
+impl Screen {
+    pub fn new(crt: CrtSettings, upscaler: Option<Upscaler>) -> Screen {
+        let mut screen = Screen {
+            frame: Frame::new(),
+            crt: if crt.is_disabled() {
+                None
+            } else {
+                Some(CrtFilter::new(SCREEN_WIDTH, SCREEN_HEIGHT, crt))
+            },
+            upscaler,
+            upscaled: Vec::new(),
+            beam_sync: false,
+            beam_changed: false,
+            changed: true,
+        };
+        screen.upscale();
+        screen
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Sets the RGBA colours of the lit pixels and of the background (see `Frame::set_palette`).
+    pub fn set_palette(&mut self, foreground: [u8; 4], background: [u8; 4]) {
+        self.frame.set_palette(foreground, background);
+        self.redraw();
+    }
+
+    /// Colours the screen like the cellophane strips of the cabinet (see `Frame::set_overlay`).
+    pub fn set_overlay(&mut self, overlay: bool) {
+        self.frame.set_overlay(overlay);
+        self.redraw();
+    }
+
+    /// Sets the crt post-processing, which is removed if every effect is disabled. The current
+    /// frame goes through the filter again.
+    pub fn set_crt(&mut self, settings: CrtSettings) {
+        if settings.is_disabled() {
+            self.crt = None;
+        } else if let Some(ref mut crt) = self.crt {
+            crt.set_settings(settings);
+        } else {
+            self.crt = Some(CrtFilter::new(SCREEN_WIDTH, SCREEN_HEIGHT, settings));
+        }
+        if let Some(ref mut crt) = self.crt {
+            crt.apply(self.frame.pixels());
+        }
+        self.redraw();
+    }
+
+    /// Sets the pixel-art upscaler, the size of `pixels` changes accordingly.
+    pub fn set_upscaler(&mut self, upscaler: Option<Upscaler>) {
+        self.upscaler = upscaler;
+        self.upscaled = Vec::new();
+        self.redraw();
+    }
+
+    /// Writes the frame in `space-invaders-<timestamp>.png` in `directory`, which is created if
+    /// needed. The screenshot has the palette and the overlay, but not the crt post-processing.
+    pub fn screenshot(&self, directory: &Path, scale: usize) -> io::Result<PathBuf> {
+        fs::create_dir_all(directory)?;
+        let path = directory.join(format!("space-invaders-{}.png", timestamp()));
+        fs::write(&path, self.frame.to_png(scale))?;
+        Ok(path)
+    }
+
+    /// Colours the screen with the colour RAM, on the colour boards (see `Frame::set_color_palette`).
+    pub fn set_color_palette(&mut self, color_palette: Option<ColorPalette>) {
+        self.frame.set_color_palette(color_palette);
+        self.redraw();
+    }
+
+    pub fn beam_sync(&self) -> bool {
+        self.beam_sync
+    }
+
+    /// Takes each line of the video RAM when the beam of the monitor scans it (see `sample_beam`)
+    /// instead of all of them in `update`, so that the objects the game moves do not tear.
+    pub fn set_beam_sync(&mut self, beam_sync: bool) {
+        self.beam_sync = beam_sync;
+    }
+
+    /// Decodes the lines of the video RAM scanned by the beam before the interrupt `interrupt`,
+    /// from the memory of the cpu. Must be called just before each interrupt, does nothing without
+    /// `set_beam_sync`.
+    pub fn sample_beam(&mut self, memory: &[u8], interrupt: u8) {
+        if self.beam_sync {
+            self.beam_changed |= self.frame.update_lines(&memory[VRAM_START..VRAM_END], beam_lines(interrupt));
+        }
+    }
+
+    /// Decodes the video RAM (unless it was taken by `sample_beam`) and the colour RAM from the
+    /// memory of the cpu. Must be called once per emulated frame (the phosphor decays from one
+    /// call to the other).
+    pub fn update(&mut self, memory: &[u8]) {
+        let colors_changed = self.frame.update_color_ram(&memory[COLOR_RAM_START..COLOR_RAM_END]);
+        let vram_changed = if self.beam_sync {
+            mem::replace(&mut self.beam_changed, false)
+        } else {
+            self.frame.update(&memory[VRAM_START..VRAM_END])
+        };
+        match self.crt {
+            // the phosphor keeps changing after the video RAM stopped
+            Some(ref mut crt) => {
+                crt.apply(self.frame.pixels());
+                self.changed = true;
+            },
+            None => self.changed |= vram_changed || colors_changed,
+        }
+        if self.changed {
+            self.upscale();
+        }
+    }
+
+    /// Returns whether `pixels` changed since the last call (nothing has to be drawn otherwise).
+    pub fn take_changed(&mut self) -> bool {
+        mem::replace(&mut self.changed, false)
+    }
+
+    /// Width of `pixels`
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH * self.factor()
+    }
+
+    /// Height of `pixels`
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.factor()
+    }
+
+    /// RGBA pixels
+    pub fn pixels(&self) -> &[u8] {
+        match (self.upscaler, &self.crt) {
+            (Some(_), _) => &self.upscaled,
+            (None, Some(crt)) => crt.output(),
+            (None, None) => self.frame.pixels(),
+        }
+    }
+
+    fn factor(&self) -> usize {
+        self.upscaler.map_or(1, |upscaler| upscaler.factor())
+    }
+
+    fn redraw(&mut self) {
+        self.upscale();
+        self.changed = true;
+    }
+
+    fn upscale(&mut self) {
+        if let Some(upscaler) = self.upscaler {
+            let pixels = match self.crt {
+                Some(ref crt) => crt.output(),
+                None => self.frame.pixels(),
+            };
+            upscaler.upscale(pixels, SCREEN_WIDTH, SCREEN_HEIGHT, &mut self.upscaled);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::f32::consts::PI;
+
+/*
+ From : http://computerarcheology.com/Arcade/SpaceInvaders/Hardware.html
+    Port 3 (output)
+    bit 0 = UFO (repeats)
+    bit 1 = Shot
+    bit 2 = Flash (player die)
+    bit 3 = Invader die
+    bit 4 = Extended play
+    bit 5 = AMP enable
+    bit 6 = NC (not wired)
+    bit 7 = NC (not wired)
+
+    Port 5 (output)
+    bit 0 = Fleet movement 1
+    bit 1 = Fleet movement 2
+    bit 2 = Fleet movement 3
+    bit 3 = Fleet movement 4
+    bit 4 = UFO Hit
+    bit 5 = NC (Cocktail mode control ... to flip screen)
+    bit 6 = NC (not wired)
+    bit 7 = NC (not wired)
+
+ The original board produces the sounds with analog circuits (and a SN76477 for the UFO). They
+ are approximated here with square waves and noise.
+*/
+
+const AMP_ENABLE_OFFSET: u8 = 5;
+
+/// Last values written by the game on the sound ports (3 and 5).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SoundLatches {
+    pub port_3: u8,
+    pub port_5: u8,
+}
+
+impl SoundLatches {
+    pub fn is_on(&self, sound: Sound) -> bool {
+        let (port, bit) = sound.latch();
+        let value = if port == 3 { self.port_3 } else { self.port_5 };
+        value & (1 << bit) != 0
+    }
+
+    pub fn amplifier_enabled(&self) -> bool {
+        self.port_3 & (1 << AMP_ENABLE_OFFSET) != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderDeath,
+    ExtraLife,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+impl Sound {
+    pub const ALL: [Sound; 10] = [
+        Sound::Ufo,
+        Sound::Shot,
+        Sound::PlayerDeath,
+        Sound::InvaderDeath,
+        Sound::ExtraLife,
+        Sound::Fleet1,
+        Sound::Fleet2,
+        Sound::Fleet3,
+        Sound::Fleet4,
+        Sound::UfoHit,
+    ];
+
+    /// Port and bit driving the sound
+    fn latch(self) -> (u8, u8) {
+        match self {
+            Sound::Ufo => (3, 0),
+            Sound::Shot => (3, 1),
+            Sound::PlayerDeath => (3, 2),
+            Sound::InvaderDeath => (3, 3),
+            Sound::ExtraLife => (3, 4),
+            Sound::Fleet1 => (5, 0),
+            Sound::Fleet2 => (5, 1),
+            Sound::Fleet3 => (5, 2),
+            Sound::Fleet4 => (5, 3),
+            Sound::UfoHit => (5, 4),
+        }
+    }
+
+    /// Duration of the sound once triggered, `None` if it plays as long as its latch is on.
+    fn duration(self) -> Option<f32> {
+        match self {
+            Sound::Ufo => None,
+            Sound::Shot => Some(0.3),
+            Sound::PlayerDeath => Some(1.0),
+            Sound::InvaderDeath => Some(0.3),
+            Sound::ExtraLife => Some(0.9),
+            Sound::Fleet1 | Sound::Fleet2 | Sound::Fleet3 | Sound::Fleet4 => Some(0.1),
+            Sound::UfoHit => Some(1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Voice {
+    playing: bool,
+    time: f32,
+    phase: f32,
+}
+
+/// A small synthesizer turning the sound latches of the machine into mono samples in `[-1, 1]`.
+///
+/// Triggered sounds start on the rising edge of their latch, the UFO sound plays as long as its
+/// latch is on.
+pub struct Synth {
+    sample_rate: u32,
+    latches: SoundLatches,
+    voices: [Voice; 10],
+    noise: u16,
+    volume: f32,
+    muted: bool,
+}
+
+impl Synth {
+    pub fn new(sample_rate: u32) -> Synth {
+        Synth {
+            sample_rate,
+            latches: SoundLatches::default(),
+            voices: [Voice::default(); 10],
+            noise: 0xace1,
+            volume: 1.0,
+            muted: false,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Sets the output gain, clamped between 0 and 1.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Updates the synthesizer with the latches currently set by the game.
+    pub fn set_latches(&mut self, latches: SoundLatches) {
+        for (voice, sound) in self.voices.iter_mut().zip(Sound::ALL.iter()) {
+            let on = latches.is_on(*sound);
+            match sound.duration() {
+                None => voice.playing = on,
+                Some(_) => if on && !self.latches.is_on(*sound) {
+                    voice.playing = true;
+                    voice.time = 0.0;
+                },
+            }
+        }
+        self.latches = latches;
+    }
+
+    /// Fills `out` with the next samples.
+    pub fn render(&mut self, out: &mut [f32]) {
+        let dt = 1.0 / self.sample_rate as f32;
+        let gain = if self.muted || !self.latches.amplifier_enabled() { 0.0 } else { self.volume };
+        for sample in out.iter_mut() {
+            let noise = self.next_noise();
+            let mut mixed = 0.0;
+            for (voice, sound) in self.voices.iter_mut().zip(Sound::ALL.iter()) {
+                if voice.playing {
+                    mixed += voice_sample(*sound, voice, noise, dt);
+                }
+            }
+            *sample = (mixed * gain).clamp(-1.0, 1.0);
+        }
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        let bit = (self.noise ^ (self.noise >> 2) ^ (self.noise >> 3) ^ (self.noise >> 5)) & 1;
+        self.noise = (self.noise >> 1) | (bit << 15);
+        if self.noise & 1 == 0 { -1.0 } else { 1.0 }
+    }
+}
+
+fn voice_sample(sound: Sound, voice: &mut Voice, noise: f32, dt: f32) -> f32 {
+    let t = voice.time;
+    let envelope = sound.duration().map(|d| 1.0 - t / d).unwrap_or(1.0);
+    let (frequency, value) = match sound {
+        Sound::Ufo => {
+            let frequency = 600.0 + 200.0 * (2.0 * PI * 5.0 * t).sin();
+            (frequency, 0.2 * square(voice.phase))
+        },
+        Sound::Shot => {
+            let frequency = 1500.0 - 4000.0 * t;
+            (frequency, 0.25 * envelope * square(voice.phase))
+        },
+        Sound::PlayerDeath => {
+            (0.0, 0.4 * envelope * noise)
+        },
+        Sound::InvaderDeath => {
+            let frequency = 400.0 - 1000.0 * t;
+            (frequency, envelope * (0.2 * noise + 0.2 * square(voice.phase)))
+        },
+        Sound::ExtraLife => {
+            let on = (t * 10.0) as u32 & 1 == 0;
+            (1000.0, if on { 0.2 * square(voice.phase) } else { 0.0 })
+        },
+        Sound::Fleet1 => (98.0, 0.4 * envelope * square(voice.phase)),
+        Sound::Fleet2 => (87.0, 0.4 * envelope * square(voice.phase)),
+        Sound::Fleet3 => (78.0, 0.4 * envelope * square(voice.phase)),
+        Sound::Fleet4 => (73.0, 0.4 * envelope * square(voice.phase)),
+        Sound::UfoHit => {
+            let frequency = 1200.0 + 400.0 * (2.0 * PI * 12.0 * t).sin();
+            (frequency, 0.2 * envelope * square(voice.phase))
+        },
+    };
+
+    voice.phase = (voice.phase + frequency * dt).fract();
+    voice.time += dt;
+    if let Some(duration) = sound.duration() {
+        voice.playing = voice.time < duration;
+    }
+    value
+}
+
+fn square(phase: f32) -> f32 {
+    if phase < 0.5 { 1.0 } else { -1.0 }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use sound::{ SoundLatches, Synth };
+
+    const AMP: u8 = 1 << 5;
+
+    fn render(synth: &mut Synth, len: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; len];
+        synth.render(&mut samples);
+        samples
+    }
+
+    #[test]
+    fn silent_without_sound() {
+        let mut synth = Synth::new(44100);
+        synth.set_latches(SoundLatches { port_3: AMP, port_5: 0 });
+        assert!(render(&mut synth, 1000).iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn shot_is_triggered_on_rising_edge() {
+        let mut synth = Synth::new(44100);
+        synth.set_latches(SoundLatches { port_3: AMP | 0b10, port_5: 0 });
+        let samples = render(&mut synth, 4410);
+        assert!(samples.iter().any(|s| *s != 0.0));
+        assert!(samples.iter().all(|s| *s >= -1.0 && *s <= 1.0));
+
+        // the shot lasts 0.3s, the latch staying on does not retrigger it
+        synth.set_latches(SoundLatches { port_3: AMP | 0b10, port_5: 0 });
+        render(&mut synth, 44100);
+        assert!(render(&mut synth, 1000).iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn amplifier_and_mute_silence_output() {
+        let mut synth = Synth::new(44100);
+        synth.set_latches(SoundLatches { port_3: 0b1, port_5: 0 });
+        assert!(render(&mut synth, 1000).iter().all(|s| *s == 0.0));
+
+        synth.set_latches(SoundLatches { port_3: AMP | 0b1, port_5: 0 });
+        assert!(render(&mut synth, 1000).iter().any(|s| *s != 0.0));
+
+        synth.set_muted(true);
+        assert!(render(&mut synth, 1000).iter().all(|s| *s == 0.0));
+    }
+}
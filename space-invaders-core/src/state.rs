@@ -0,0 +1,319 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use board::{ BoardDriver, INVADERS };
+use cpu::Cpu8080;
+use frame::{ COLOR_RAM_END, COLOR_RAM_START };
+use machine::SpaceInvaderDataBus;
+
+/*
+ Save states are shared by all the frontends (a state saved in the browser can be loaded in the gtk
+ or piston frontend). All the values are little endian.
+
+    magic       4 bytes  "SIST"
+    version     u8
+    game        length (u8) and short name of the game (since version 2, version 1 states are
+                space invaders ones)
+    cpu         a, b, c, d, e, h, l (u8), sp, pc (u16), psw (u8), interrupt enabled (u8),
+                stopped (u8), cycles (u64)
+    machine     port 0, port 1, port 2 (u8), shift value (u16), shift offset (u8),
+                sound port 3, sound port 5 (u8)
+    ram         0x2000 bytes, from RAM_START to RAM_END
+    colour ram  0x400 bytes, from COLOR_RAM_START to COLOR_RAM_END, only for the games which have
+                one (since version 3)
+*/
+
+const MAGIC: &[u8; 4] = b"SIST";
+const VERSION: u8 = 3;
+/// Version without the colour RAM
+const VERSION_2: u8 = 2;
+/// Version without the game
+const VERSION_1: u8 = 1;
+const CPU_STATE_LEN: usize = 22;
+const MACHINE_STATE_LEN: usize = 8;
+const STATE_LEN: usize = CPU_STATE_LEN + MACHINE_STATE_LEN + (RAM_END - RAM_START);
+
+/// Start of the RAM in memory (the ROM is below)
+pub const RAM_START: usize = 0x2000;
+/// End (exclusive) of the RAM in memory
+pub const RAM_END: usize = 0x4000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state header
+    NotAState,
+    UnsupportedVersion(u8),
+    /// The data ends before the end of the state
+    Truncated,
+    /// The state was saved by another game (short name)
+    OtherGame(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::NotAState => write!(f, "not a space invaders save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            StateError::Truncated => write!(f, "truncated save state"),
+            StateError::OtherGame(ref game) => write!(f, "the state was saved by another game ({})", game),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Serializes the state of the emulation.
+pub fn save_state(cpu: &Cpu8080<SpaceInvaderDataBus>) -> Vec<u8> {
+    let machine = cpu.data_bus().machine();
+    let game = machine.driver().name();
+    let color_ram_len = color_ram_len(machine.driver());
+    let mut writer = StateWriter {
+        data: Vec::with_capacity(MAGIC.len() + 2 + game.len() + STATE_LEN + color_ram_len),
+    };
+    writer.data.extend_from_slice(MAGIC);
+    writer.u8(VERSION);
+    writer.u8(game.len() as u8);
+    writer.data.extend_from_slice(game.as_bytes());
+    cpu.save(&mut writer);
+    machine.save(&mut writer);
+    writer.data.extend_from_slice(&cpu.memory()[RAM_START..RAM_END]);
+    writer.data.extend_from_slice(&cpu.memory()[COLOR_RAM_START..(COLOR_RAM_START + color_ram_len)]);
+    writer.data
+}
+
+fn color_ram_len(driver: &dyn BoardDriver) -> usize {
+    if driver.has_color_ram() { COLOR_RAM_END - COLOR_RAM_START } else { 0 }
+}
+
+/// Restores a state built by `save_state`. Nothing is modified if the state is invalid.
+///
+/// The inputs currently pressed and the DIP switches are kept, they belong to the player rather
+/// than to the state.
+pub fn load_state(cpu: &mut Cpu8080<SpaceInvaderDataBus>, data: &[u8]) -> Result<(), StateError> {
+
+    if data.len() < MAGIC.len() + 1 || &data[0..MAGIC.len()] != MAGIC {
+        return Err(StateError::NotAState);
+    }
+    let mut reader = StateReader { data: &data[MAGIC.len()..] };
+    let version = reader.u8()?;
+    let game = match version {
+        VERSION | VERSION_2 => {
+            let len = reader.u8()? as usize;
+            String::from_utf8_lossy(reader.bytes(len)?).into_owned()
+        },
+        VERSION_1 => INVADERS.name().to_string(),
+        version => return Err(StateError::UnsupportedVersion(version)),
+    };
+    let driver = cpu.data_bus().machine().driver();
+    if game != driver.name() {
+        return Err(StateError::OtherGame(game));
+    }
+
+    // Checked before anything is loaded, so that a truncated state does not leave the emulation
+    // half restored
+    let color_ram_len = if version == VERSION { color_ram_len(driver) } else { 0 };
+    if reader.data.len() < STATE_LEN + color_ram_len {
+        return Err(StateError::Truncated);
+    }
+
+    cpu.load(&mut reader)?;
+    cpu.data_bus_mut().machine_mut().load(&mut reader)?;
+    let ram = reader.bytes(RAM_END - RAM_START)?;
+    cpu.memory_mut()[RAM_START..RAM_END].copy_from_slice(ram);
+    let color_ram = reader.bytes(color_ram_len)?;
+    cpu.memory_mut()[COLOR_RAM_START..(COLOR_RAM_START + color_ram_len)].copy_from_slice(color_ram);
+    Ok(())
+}
+
+/// Saves the state of the emulation in the file `path`.
+pub fn save_state_file(path: &Path, cpu: &Cpu8080<SpaceInvaderDataBus>) -> io::Result<()> {
+    fs::write(path, save_state(cpu))
+}
+
+/// Restores the state saved in the file `path`.
+pub fn load_state_file(path: &Path, cpu: &mut Cpu8080<SpaceInvaderDataBus>) -> io::Result<()> {
+    let data = fs::read(path)?;
+    load_state(cpu, &data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads the high score from the memory, if the game of `driver` has a known one.
+pub fn high_score(driver: &dyn BoardDriver, memory: &[u8]) -> Option<u32> {
+    let address = driver.high_score_address()?;
+    let bcd = memory[address] as u32 | (memory[address + 1] as u32) << 8;
+    Some((0..4).rev().fold(0, |score, digit| score * 10 + ((bcd >> (digit * 4)) & 0xf)))
+}
+
+/// Writes the high score in the memory, if the game of `driver` has a known one. The game
+/// displays only 4 digits, `score` is capped at 9999.
+pub fn set_high_score(driver: &dyn BoardDriver, memory: &mut [u8], score: u32) {
+    if let Some(address) = driver.high_score_address() {
+        let score = score.min(9999);
+        let bcd = (0..4).fold(0, |bcd, digit| bcd | ((score / 10u32.pow(digit)) % 10) << (digit * 4));
+        memory[address] = bcd as u8;
+        memory[address + 1] = (bcd >> 8) as u8;
+    }
+}
+
+pub(crate) struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        self.u8().map(|b| b != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        self.bytes(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use state::*;
+    use board::{ LunarRescue, INVADERS };
+    use memory::MemoryMap;
+    use machine::{ SpaceInvaderDataBus, SpaceInvaderMachine };
+    use INVADERS_ROM;
+
+    fn new_emulation() -> Cpu8080<SpaceInvaderDataBus> {
+        let memory = MemoryMap::new(&INVADERS, INVADERS_ROM);
+        Cpu8080::new(memory, SpaceInvaderDataBus::new(SpaceInvaderMachine::new()))
+    }
+
+    fn run(cpu: &mut Cpu8080<SpaceInvaderDataBus>, frames: u32) {
+        for _ in 0..frames {
+            for rst in 1..3 {
+                let target = cpu.cycles() + 16666;
+                while cpu.cycles() < target {
+                    cpu.emulate();
+                }
+                cpu.interrupt(rst);
+            }
+        }
+    }
+
+    #[test]
+    fn restored_state_runs_the_same() {
+        let mut cpu = new_emulation();
+        run(&mut cpu, 100);
+        let state = save_state(&cpu);
+        run(&mut cpu, 100);
+
+        let mut restored = new_emulation();
+        load_state(&mut restored, &state).unwrap();
+        run(&mut restored, 100);
+
+        assert_eq!(cpu.memory(), restored.memory());
+        assert_eq!(cpu.cycles(), restored.cycles());
+        assert_eq!(cpu.registers().pc, restored.registers().pc);
+    }
+
+    #[test]
+    fn reject_invalid_states() {
+        let mut cpu = new_emulation();
+        let mut state = save_state(&cpu);
+
+        assert_eq!(Err(StateError::Truncated), load_state(&mut cpu, &state[..100]));
+        assert_eq!(Err(StateError::NotAState), load_state(&mut cpu, b"PNG"));
+        state[4] = 42;
+        assert_eq!(Err(StateError::UnsupportedVersion(42)), load_state(&mut cpu, &state));
+    }
+
+    #[test]
+    fn reject_states_of_other_games() {
+        let state = save_state(&new_emulation());
+        assert_eq!(b"\x03\x08invaders", &state[4..14]);
+
+        let lrescue = SpaceInvaderDataBus::new(SpaceInvaderMachine::with_driver(&LunarRescue));
+        let mut cpu = Cpu8080::new(MemoryMap::new(&LunarRescue, &[]), lrescue);
+        assert_eq!(
+            Err(StateError::OtherGame("invaders".to_string())),
+            load_state(&mut cpu, &state));
+    }
+
+    #[test]
+    fn color_ram_is_saved() {
+        let lrescue = SpaceInvaderDataBus::new(SpaceInvaderMachine::with_driver(&LunarRescue));
+        let mut cpu = Cpu8080::new(MemoryMap::new(&LunarRescue, &[]), lrescue);
+        cpu.memory_mut()[COLOR_RAM_START + 10] = 5;
+        let state = save_state(&cpu);
+
+        cpu.memory_mut()[COLOR_RAM_START + 10] = 0;
+        load_state(&mut cpu, &state).unwrap();
+        assert_eq!(5, cpu.memory()[COLOR_RAM_START + 10]);
+        assert_eq!(Err(StateError::Truncated), load_state(&mut cpu, &state[..(state.len() - 1)]));
+    }
+
+    #[test]
+    fn load_version_1_states() {
+        let cpu = new_emulation();
+        let mut state = save_state(&cpu);
+        // version 1 has no game
+        state.drain(4..14);
+        state.insert(4, 1);
+        let mut restored = new_emulation();
+        load_state(&mut restored, &state).unwrap();
+        assert_eq!(cpu.registers().pc, restored.registers().pc);
+    }
+
+    #[test]
+    fn high_score_is_bcd() {
+        let mut memory = vec![0; 0x4000];
+        set_high_score(&INVADERS, &mut memory, 1230);
+        assert_eq!(&[0x30, 0x12], &memory[0x20f4..0x20f6]);
+        assert_eq!(Some(1230), high_score(&INVADERS, &memory));
+        set_high_score(&INVADERS, &mut memory, 123456);
+        assert_eq!(Some(9999), high_score(&INVADERS, &memory));
+        assert_eq!(None, high_score(&LunarRescue, &memory));
+    }
+}
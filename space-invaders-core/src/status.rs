@@ -0,0 +1,96 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::collections::VecDeque;
+use std::time::{ Duration, Instant };
+
+/// Messages waiting at most, the oldest ones are dropped (e.g. after many faults)
+const MAX_PENDING: usize = 10;
+
+/// Messages of a frontend (the achievements unlocked...), shown one after another for the same
+/// time each, on a status line or in the title of the window.
+pub struct StatusMessages {
+    duration: Duration,
+    pending: VecDeque<String>,
+    /// Message shown, and when it is replaced
+    shown: Option<(String, Instant)>,
+}
+
+impl StatusMessages {
+    pub fn new(duration: Duration) -> StatusMessages {
+        StatusMessages { duration, pending: VecDeque::new(), shown: None }
+    }
+
+    /// Shows `message` after the ones already added.
+    pub fn push(&mut self, message: String) {
+        if self.pending.len() == MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(message);
+    }
+
+    /// Replaces the message shown if its time is over at `now`. Returns true if it changed.
+    pub fn update(&mut self, now: Instant) -> bool {
+        if self.shown.as_ref().is_some_and(|&(_, end)| now < end) {
+            return false;
+        }
+        let previous = self.shown.take();
+        self.shown = self.pending.pop_front().map(|message| (message, now + self.duration));
+        previous.is_some() || self.shown.is_some()
+    }
+
+    /// Message to show, if any
+    pub fn shown(&self) -> Option<&str> {
+        self.shown.as_ref().map(|(message, _)| message.as_str())
+    }
+
+    /// Removes the message shown and the pending ones, in order (e.g. to print them on exit).
+    pub fn take_all(&mut self) -> Vec<String> {
+        self.shown.take().map(|(message, _)| message).into_iter().chain(self.pending.drain(..)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{ Duration, Instant };
+    use status::*;
+
+    #[test]
+    fn messages_are_shown_in_turn() {
+        let start = Instant::now();
+        let mut status = StatusMessages::new(Duration::from_secs(5));
+        assert!(!status.update(start));
+        status.push("first".to_string());
+        status.push("second".to_string());
+        assert!(status.update(start));
+        assert_eq!(Some("first"), status.shown());
+        assert!(!status.update(start + Duration::from_secs(4)));
+        assert!(status.update(start + Duration::from_secs(5)));
+        assert_eq!(Some("second"), status.shown());
+        assert!(status.update(start + Duration::from_secs(10)));
+        assert_eq!(None, status.shown());
+        assert!(!status.update(start + Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn oldest_messages_are_dropped() {
+        let mut status = StatusMessages::new(Duration::from_secs(5));
+        for index in 0..(MAX_PENDING + 2) {
+            status.push(index.to_string());
+        }
+        status.update(Instant::now());
+        assert_eq!(Some("2"), status.shown());
+    }
+
+    #[test]
+    fn messages_left_are_taken_in_order() {
+        let mut status = StatusMessages::new(Duration::from_secs(5));
+        status.push("first".to_string());
+        status.update(Instant::now());
+        status.push("second".to_string());
+        assert_eq!(vec!["first".to_string(), "second".to_string()], status.take_all());
+        assert_eq!(None, status.shown());
+        assert!(status.take_all().is_empty());
+    }
+}
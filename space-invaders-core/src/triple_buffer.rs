@@ -0,0 +1,113 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::mem;
+use std::sync::{ Arc, Mutex };
+
+/// Buffer between the writer and the reader, `fresh` until the reader takes it
+struct Back<T> {
+    buffer: T,
+    fresh: bool,
+}
+
+/// Creates the two ends of a triple buffer holding copies of `initial`.
+///
+/// The writer fills its own buffer and publishes it, the reader takes the last published buffer
+/// when it wants to. Each side only locks the buffer in between to swap it with its own, so the
+/// writer never waits for the reader to draw a frame and the reader never waits for the writer to
+/// emulate one. Buffers published while the reader is busy are skipped.
+pub fn triple_buffer<T: Clone>(initial: T) -> (TripleBufferWriter<T>, TripleBufferReader<T>) {
+    let back = Arc::new(Mutex::new(Back { buffer: initial.clone(), fresh: false }));
+    let writer = TripleBufferWriter { back: back.clone(), buffer: initial.clone() };
+    let reader = TripleBufferReader { back, buffer: initial };
+    (writer, reader)
+}
+
+pub struct TripleBufferWriter<T> {
+    back: Arc<Mutex<Back<T>>>,
+    buffer: T,
+}
+
+impl<T> TripleBufferWriter<T> {
+    /// Buffer to fill before `publish`. It holds an older buffer, not the last published one.
+    pub fn buffer_mut(&mut self) -> &mut T {
+        &mut self.buffer
+    }
+
+    /// Makes the buffer available to the reader.
+    pub fn publish(&mut self) {
+        let mut back = self.back.lock().unwrap();
+        mem::swap(&mut back.buffer, &mut self.buffer);
+        back.fresh = true;
+    }
+}
+
+pub struct TripleBufferReader<T> {
+    back: Arc<Mutex<Back<T>>>,
+    buffer: T,
+}
+
+impl<T> TripleBufferReader<T> {
+    /// Takes the last published buffer, returns false if nothing was published since the last call.
+    pub fn update(&mut self) -> bool {
+        let mut back = self.back.lock().unwrap();
+        if !back.fresh {
+            return false;
+        }
+        mem::swap(&mut back.buffer, &mut self.buffer);
+        back.fresh = false;
+        true
+    }
+
+    /// Buffer taken by the last `update`
+    pub fn buffer(&self) -> &T {
+        &self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use triple_buffer::*;
+
+    #[test]
+    fn reader_gets_the_last_published_buffer() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        assert!(!reader.update());
+        assert_eq!(0, *reader.buffer());
+
+        for i in 1..4 {
+            *writer.buffer_mut() = i;
+            writer.publish();
+        }
+        assert!(reader.update());
+        assert_eq!(3, *reader.buffer());
+        assert!(!reader.update());
+        assert_eq!(3, *reader.buffer());
+    }
+
+    #[test]
+    fn buffers_cross_threads() {
+        let (mut writer, mut reader) = triple_buffer(vec![0u32; 16]);
+        let producer = thread::spawn(move || {
+            for i in 1..=1000 {
+                for value in writer.buffer_mut().iter_mut() {
+                    *value = i;
+                }
+                writer.publish();
+            }
+        });
+        let mut last = 0;
+        while last < 1000 {
+            if reader.update() {
+                let first = reader.buffer()[0];
+                // never a buffer being written
+                assert!(reader.buffer().iter().all(|&value| value == first));
+                assert!(first > last);
+                last = first;
+            }
+        }
+        producer.join().unwrap();
+    }
+}
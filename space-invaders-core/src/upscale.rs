@@ -0,0 +1,368 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+use std::str::FromStr;
+
+/*
+ Pixel-art upscalers, working on RGBA frames (usually the decoded 224x256 frame, after the crt
+ filter if any). Each source pixel E becomes a block of factor x factor pixels, computed from its
+ neighbours, named as in the descriptions of the algorithms:
+
+    A B C
+    D E F
+    G H I
+
+ Outside of the frame, the neighbours are the pixels of the border.
+*/
+
+type Pixel = [u8; 4];
+
+/// Maximum factor of `Upscaler::Nearest`
+pub const MAX_NEAREST_FACTOR: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Upscaler {
+    /// Each pixel is repeated (integer scaling, 1 to `MAX_NEAREST_FACTOR`)
+    Nearest(usize),
+    /// AdvMAME2x, smooths the diagonals without adding colours
+    Scale2x,
+    /// AdvMAME3x, the same at 3x
+    Scale3x,
+    /// Eric's Pixel Expansion, the original formulation of Scale2x (the output is the same)
+    Epx,
+    /// 2xBR from Hyllian: edges are detected with color distances on a larger neighbourhood and
+    /// blended, which gives rounder shapes
+    Xbr,
+}
+
+impl Upscaler {
+    /// Width and height multiplier of the output
+    pub fn factor(&self) -> usize {
+        match *self {
+            Upscaler::Nearest(factor) => factor,
+            Upscaler::Scale2x | Upscaler::Epx | Upscaler::Xbr => 2,
+            Upscaler::Scale3x => 3,
+        }
+    }
+
+    /// Upscales `frame` (RGBA, `width` x `height`) in `output`, which is resized to
+    /// `width * factor` x `height * factor` RGBA pixels.
+    pub fn upscale(&self, frame: &[u8], width: usize, height: usize, output: &mut Vec<u8>) {
+        assert_eq!(width * height * 4, frame.len(), "the frame does not match its size");
+        let factor = self.factor();
+        output.resize(width * height * factor * factor * 4, 0);
+
+        let source = Source { frame, width, height };
+        let mut block = [[0; 4]; 9];
+        for y in 0..height {
+            for x in 0..width {
+                let block = &mut block[..(factor * factor)];
+                match *self {
+                    Upscaler::Nearest(_) => {
+                        let pixel = source.get(x, y, 0, 0);
+                        for px in block.iter_mut() {
+                            *px = pixel;
+                        }
+                    },
+                    Upscaler::Scale2x => scale2x(&source.neighbours(x, y), block),
+                    Upscaler::Scale3x => scale3x(&source.neighbours(x, y), block),
+                    Upscaler::Epx => epx(&source.neighbours(x, y), block),
+                    Upscaler::Xbr => xbr(&source, x, y, block),
+                }
+
+                let output_width = width * factor;
+                for (i, px) in block.iter().enumerate() {
+                    let (bx, by) = (i % factor, i / factor);
+                    let offset = ((y * factor + by) * output_width + x * factor + bx) * 4;
+                    output[offset..(offset + 4)].copy_from_slice(px);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Upscaler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Upscaler::Nearest(factor) => write!(f, "nearest{}", factor),
+            Upscaler::Scale2x => write!(f, "scale2x"),
+            Upscaler::Scale3x => write!(f, "scale3x"),
+            Upscaler::Epx => write!(f, "epx"),
+            Upscaler::Xbr => write!(f, "xbr"),
+        }
+    }
+}
+
+/// Parses the names written by `Display` (`nearest` alone is `nearest2`).
+impl FromStr for Upscaler {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Upscaler, String> {
+        match name {
+            "nearest" => Ok(Upscaler::Nearest(2)),
+            "scale2x" => Ok(Upscaler::Scale2x),
+            "scale3x" => Ok(Upscaler::Scale3x),
+            "epx" => Ok(Upscaler::Epx),
+            "xbr" => Ok(Upscaler::Xbr),
+            _ if name.starts_with("nearest") => match name["nearest".len()..].parse() {
+                Ok(factor) if (1..=MAX_NEAREST_FACTOR).contains(&factor) => Ok(Upscaler::Nearest(factor)),
+                _ => Err(format!("invalid nearest factor in {}, expected 1 to {}", name, MAX_NEAREST_FACTOR)),
+            },
+            _ => Err(format!("unknown upscaler {}", name)),
+        }
+    }
+}
+
+struct Source<'a> {
+    frame: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Source<'a> {
+    /// Pixel at (x + dx, y + dy), clamped to the frame
+    fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> Pixel {
+        let x = (x as isize + dx).max(0).min(self.width as isize - 1) as usize;
+        let y = (y as isize + dy).max(0).min(self.height as isize - 1) as usize;
+        let offset = (y * self.width + x) * 4;
+        [self.frame[offset], self.frame[offset + 1], self.frame[offset + 2], self.frame[offset + 3]]
+    }
+
+    /// A to I
+    fn neighbours(&self, x: usize, y: usize) -> [Pixel; 9] {
+        let mut neighbours = [[0; 4]; 9];
+        for (i, px) in neighbours.iter_mut().enumerate() {
+            *px = self.get(x, y, i as isize % 3 - 1, i as isize / 3 - 1);
+        }
+        neighbours
+    }
+}
+
+fn scale2x(n: &[Pixel; 9], block: &mut [Pixel]) {
+    let [_, b, _, d, e, f, _, h, _] = *n;
+    block[0] = if d == b && b != f && d != h { d } else { e };
+    block[1] = if b == f && b != d && f != h { f } else { e };
+    block[2] = if d == h && d != b && h != f { d } else { e };
+    block[3] = if h == f && d != h && b != f { f } else { e };
+}
+
+fn scale3x(n: &[Pixel; 9], block: &mut [Pixel]) {
+    let [a, b, c, d, e, f, g, h, i] = *n;
+    let (db, bf, dh, hf) = (
+        d == b && b != f && d != h,
+        b == f && b != d && f != h,
+        d == h && d != b && h != f,
+        h == f && d != h && b != f,
+    );
+    block[0] = if db { d } else { e };
+    block[1] = if (db && e != c) || (bf && e != a) { b } else { e };
+    block[2] = if bf { f } else { e };
+    block[3] = if (db && e != g) || (dh && e != a) { d } else { e };
+    block[4] = e;
+    block[5] = if (bf && e != i) || (hf && e != c) { f } else { e };
+    block[6] = if dh { d } else { e };
+    block[7] = if (dh && e != i) || (hf && e != g) { h } else { e };
+    block[8] = if hf { f } else { e };
+}
+
+fn epx(n: &[Pixel; 9], block: &mut [Pixel]) {
+    // named P, A (top), B (right), C (left) and D (bottom) in the original description
+    let [_, top, _, left, p, right, _, bottom, _] = *n;
+    let sides = [top, right, left, bottom];
+    let three_identical = sides.iter()
+        .any(|side| sides.iter().filter(|other| *other == side).count() >= 3);
+
+    for px in block.iter_mut() {
+        *px = p;
+    }
+    if !three_identical {
+        if left == top { block[0] = top; }
+        if top == right { block[1] = right; }
+        if bottom == left { block[2] = left; }
+        if right == bottom { block[3] = bottom; }
+    }
+}
+
+// Offsets of the neighbours used by 2xBR for the bottom right corner of the block, the other
+// corners are handled by rotating them:
+//
+//        A1 B1 C1
+//     A0 A  B  C  C4
+//     D0 D  E  F  F4
+//     G0 G  H  I  I4
+//        G5 H5 I5
+const XBR_C: (isize, isize) = (1, -1);
+const XBR_G: (isize, isize) = (-1, 1);
+const XBR_I: (isize, isize) = (1, 1);
+const XBR_F: (isize, isize) = (1, 0);
+const XBR_H: (isize, isize) = (0, 1);
+const XBR_D: (isize, isize) = (-1, 0);
+const XBR_B: (isize, isize) = (0, -1);
+const XBR_F4: (isize, isize) = (2, 0);
+const XBR_H5: (isize, isize) = (0, 2);
+const XBR_I4: (isize, isize) = (2, 1);
+const XBR_I5: (isize, isize) = (1, 2);
+
+fn xbr(source: &Source, x: usize, y: usize, block: &mut [Pixel]) {
+    let e = source.get(x, y, 0, 0);
+    // corner index in the 2x2 block for each quarter turn, starting with the bottom right one
+    for (turns, &corner) in [3, 2, 0, 1].iter().enumerate() {
+        let px = |(dx, dy): (isize, isize)| {
+            let (dx, dy) = rotate(dx, dy, turns);
+            source.get(x, y, dx, dy)
+        };
+        let (f, h) = (px(XBR_F), px(XBR_H));
+        block[corner] = e;
+        if e == f || e == h {
+            continue;
+        }
+        let weight_fh = distance(e, px(XBR_C)) + distance(e, px(XBR_G))
+            + distance(px(XBR_I), px(XBR_F4)) + distance(px(XBR_I), px(XBR_H5))
+            + 4 * distance(h, f);
+        let weight_ei = distance(h, px(XBR_D)) + distance(h, px(XBR_I5))
+            + distance(f, px(XBR_I4)) + distance(f, px(XBR_B))
+            + 4 * distance(e, px(XBR_I));
+        if weight_fh < weight_ei {
+            let edge = if distance(e, f) <= distance(e, h) { f } else { h };
+            block[corner] = blend(e, edge);
+        }
+    }
+}
+
+/// Rotates an offset by `turns` quarter turns, clockwise on the screen
+fn rotate(dx: isize, dy: isize, turns: usize) -> (isize, isize) {
+    (0..turns).fold((dx, dy), |(dx, dy), _| (-dy, dx))
+}
+
+/// Distance between two colours, weighted like their luminance
+fn distance(a: Pixel, b: Pixel) -> u32 {
+    let diff = |channel: usize| (a[channel] as i32 - b[channel] as i32).unsigned_abs();
+    (diff(0) * 299 + diff(1) * 587 + diff(2) * 114) / 1000
+}
+
+fn blend(a: Pixel, b: Pixel) -> Pixel {
+    let mut mixed = [0; 4];
+    for (channel, value) in mixed.iter_mut().enumerate() {
+        *value = ((a[channel] as u16 + b[channel] as u16) / 2) as u8;
+    }
+    mixed
+}
+
+#[cfg(test)]
+mod tests {
+
+    use upscale::*;
+
+    const W: Pixel = [255, 255, 255, 255];
+    const K: Pixel = [0, 0, 0, 255];
+
+    fn frame(pixels: &[Pixel]) -> Vec<u8> {
+        pixels.iter().flat_map(|px| px.iter().cloned()).collect()
+    }
+
+    fn upscale(upscaler: Upscaler, pixels: &[Pixel], width: usize) -> Vec<Pixel> {
+        let mut output = Vec::new();
+        upscaler.upscale(&frame(pixels), width, pixels.len() / width, &mut output);
+        output.chunks(4).map(|px| [px[0], px[1], px[2], px[3]]).collect()
+    }
+
+    /// Block of the source pixel (x, y) in the output
+    fn block(output: &[Pixel], width: usize, factor: usize, x: usize, y: usize) -> Vec<Pixel> {
+        let output_width = width * factor;
+        (0..factor)
+            .flat_map(|by| {
+                let start = (y * factor + by) * output_width + x * factor;
+                output[start..(start + factor)].to_vec()
+            })
+            .collect()
+    }
+
+    // a diagonal, from the top right to the bottom left
+    const DIAGONAL: [Pixel; 9] = [
+        K, K, W,
+        K, W, K,
+        W, K, K,
+    ];
+
+    #[test]
+    fn nearest_repeats_the_pixels() {
+        assert_eq!(
+            vec![W, W, W, K, K, K,
+                 W, W, W, K, K, K,
+                 W, W, W, K, K, K],
+            upscale(Upscaler::Nearest(3), &[W, K], 2));
+        assert_eq!(vec![W, K], upscale(Upscaler::Nearest(1), &[W, K], 2));
+    }
+
+    #[test]
+    fn scale2x_fills_the_diagonal() {
+        let output = upscale(Upscaler::Scale2x, &DIAGONAL, 3);
+        assert_eq!(36, output.len());
+        assert_eq!(vec![W, W, W, W], block(&output, 3, 2, 1, 1));
+        assert_eq!(vec![K, K, K, W], block(&output, 3, 2, 1, 0));
+        assert_eq!(vec![W, K, K, K], block(&output, 3, 2, 2, 1));
+        assert_eq!(vec![K, K, K, K], block(&output, 3, 2, 0, 0));
+    }
+
+    #[test]
+    fn scale3x_fills_the_diagonal() {
+        let output = upscale(Upscaler::Scale3x, &DIAGONAL, 3);
+        assert_eq!(81, output.len());
+        assert_eq!(vec![W; 9], block(&output, 3, 3, 1, 1));
+        assert_eq!(
+            vec![K, K, K,
+                 K, K, W,
+                 K, K, W],
+            block(&output, 3, 3, 1, 0));
+    }
+
+    #[test]
+    fn lines_and_isolated_pixels_are_kept() {
+        let line = [K, K, K, W, W, W, K, K, K];
+        let dot = [K, K, K, K, W, K, K, K, K];
+        for pattern in &[line, dot] {
+            assert_eq!(upscale(Upscaler::Nearest(2), pattern, 3), upscale(Upscaler::Scale2x, pattern, 3));
+            assert_eq!(upscale(Upscaler::Nearest(3), pattern, 3), upscale(Upscaler::Scale3x, pattern, 3));
+        }
+    }
+
+    #[test]
+    fn epx_matches_scale2x() {
+        let g = [128, 128, 128, 255];
+        let patterns = [
+            DIAGONAL,
+            [K, W, K, W, g, W, K, W, K],
+            [W, W, K, W, K, g, K, g, g],
+            [g, K, W, K, K, W, W, W, g],
+        ];
+        for pattern in &patterns {
+            assert_eq!(upscale(Upscaler::Scale2x, pattern, 3), upscale(Upscaler::Epx, pattern, 3));
+        }
+    }
+
+    #[test]
+    fn xbr_blends_the_diagonal() {
+        let output = upscale(Upscaler::Xbr, &DIAGONAL, 3);
+        let gray = [127, 127, 127, 255];
+        // block of the center pixel: the corners facing the other diagonal pixels stay lit, the
+        // others are blended
+        assert_eq!(gray, output[2 * 6 + 2]);
+        assert_eq!(W, output[2 * 6 + 3]);
+        assert_eq!(W, output[3 * 6 + 2]);
+        assert_eq!(gray, output[3 * 6 + 3]);
+        // uniform areas are not changed
+        assert_eq!(vec![K; 16], upscale(Upscaler::Xbr, &[K; 4], 2));
+    }
+
+    #[test]
+    fn parse_names() {
+        for upscaler in &[Upscaler::Nearest(4), Upscaler::Scale2x, Upscaler::Scale3x, Upscaler::Epx, Upscaler::Xbr] {
+            assert_eq!(Ok(*upscaler), upscaler.to_string().parse());
+        }
+        assert_eq!(Ok(Upscaler::Nearest(2)), "nearest".parse());
+        assert!("nearest9".parse::<Upscaler>().is_err());
+        assert!("hq2x".parse::<Upscaler>().is_err());
+    }
+}
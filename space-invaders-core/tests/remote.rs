@@ -0,0 +1,134 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![cfg(all(unix, feature = "remote"))]
+
+extern crate serde_json;
+extern crate space_invaders_core;
+
+use std::env;
+use std::fs;
+use std::io::{ BufRead, BufReader, Write };
+use std::os::unix::net::UnixStream;
+use std::path::{ Path, PathBuf };
+use std::process;
+
+use serde_json::{ json, Value };
+use space_invaders_core::{ EmulatorThread, FrontendOptions };
+
+/// Client of the remote control, one request at a time
+struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl Client {
+    fn connect(path: &Path) -> Client {
+        let writer = UnixStream::connect(path).unwrap();
+        Client { reader: BufReader::new(writer.try_clone().unwrap()), writer, next_id: 1 }
+    }
+
+    /// Sends a line, and reads the response.
+    fn send(&mut self, line: &str) -> Value {
+        writeln!(self.writer, "{}", line).unwrap();
+        let mut response = String::new();
+        self.reader.read_line(&mut response).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    /// Result of the call, or the code of its error.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, i64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let response = self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }).to_string());
+        assert_eq!(json!(id), response["id"]);
+        match response.get("error") {
+            Some(error) => Err(error["code"].as_i64().unwrap()),
+            None => Ok(response["result"].clone()),
+        }
+    }
+}
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("space-invaders-{}-{}", test, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn remote_control_drives_the_emulation() {
+    let dir = temp_dir("remote");
+    let socket = dir.join("remote.sock");
+    let args = vec![
+        "--no-audio".to_string(),
+        "--remote".to_string(), socket.display().to_string(),
+        "--state".to_string(), dir.join("remote.state").display().to_string(),
+        "--screenshot-dir".to_string(), dir.display().to_string(),
+        "--achievement-log".to_string(), dir.join("achievements.log").display().to_string(),
+    ];
+    let options = FrontendOptions::from_args(args.into_iter()).unwrap();
+    let (driver, rom) = options.load_game().unwrap();
+    let mut emulator = EmulatorThread::spawn(&options, driver, &rom);
+    let mut client = Client::connect(&socket);
+
+    // paused, the emulation only runs the steps
+    assert_eq!(Ok(Value::Null), client.call("pause", json!({})));
+    let frames = client.call("game_state", json!({})).unwrap()["frames"].as_u64().unwrap();
+    assert_eq!(Ok(json!({ "frames": frames + 10 })), client.call("step", json!({ "frames": 10 })));
+    let state = client.call("game_state", json!({})).unwrap();
+    assert_eq!("invaders", state["game"]);
+    assert_eq!(json!(true), state["paused"]);
+    assert_eq!(json!(frames + 10), state["frames"]);
+    assert_eq!(json!(0), state["high_score"]);
+    assert_eq!(json!([]), state["achievements"]);
+
+    assert_eq!(Ok(Value::Null), client.call("press", json!({ "input": "coin" })));
+    assert_eq!(1, client.call("game_state", json!({})).unwrap()["ports"][1].as_u64().unwrap() & 1);
+    assert_eq!(Ok(Value::Null), client.call("release", json!({ "input": "coin" })));
+    assert_eq!(0, client.call("game_state", json!({})).unwrap()["ports"][1].as_u64().unwrap() & 1);
+
+    // a state saved, then overwritten, then loaded back
+    assert_eq!(Ok(Value::Null), client.call("write_memory", json!({ "address": 0x2400, "bytes": [1, 2, 3] })));
+    assert_eq!(Ok(json!([1, 2, 3])), client.call("read_memory", json!({ "address": 0x2400, "length": 3 })));
+    let saved = client.call("save_state", json!({})).unwrap();
+    assert_eq!(json!(dir.join("remote.state").display().to_string()), saved["path"]);
+    assert_eq!(Ok(Value::Null), client.call("write_memory", json!({ "address": 0x2400, "bytes": [0, 0, 0] })));
+    assert!(client.call("load_state", json!({})).is_ok());
+    assert_eq!(Ok(json!([1, 2, 3])), client.call("read_memory", json!({ "address": 0x2400, "length": 3 })));
+
+    let screenshot = client.call("screenshot", json!({ "scale": 2 })).unwrap();
+    assert!(Path::new(screenshot["path"].as_str().unwrap()).is_file());
+
+    // a notification has no response, the next line is the one of the next request
+    writeln!(client.writer, "{}", json!({ "jsonrpc": "2.0", "method": "resume" })).unwrap();
+    assert_eq!(json!(false), client.call("game_state", json!({})).unwrap()["paused"]);
+
+    emulator.stop();
+    assert!(!socket.exists());
+    assert_eq!(Err(-32000), client.call("game_state", json!({})));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_requests_are_rejected() {
+    let dir = temp_dir("invalid-requests");
+    let socket = dir.join("invalid.sock");
+    let args = vec!["--no-audio".to_string(), "--remote".to_string(), socket.display().to_string()];
+    let options = FrontendOptions::from_args(args.into_iter()).unwrap();
+    let (driver, rom) = options.load_game().unwrap();
+    let mut emulator = EmulatorThread::spawn(&options, driver, &rom);
+    let mut client = Client::connect(&socket);
+
+    assert_eq!(json!(-32700), client.send("{ not json")["error"]["code"]);
+    assert_eq!(json!(-32600), client.send(r#"{ "id": 1, "method": "pause" }"#)["error"]["code"]);
+    assert_eq!(Err(-32601), client.call("reset", json!({})));
+    assert_eq!(Err(-32602), client.call("press", json!({ "input": "p3-fire" })));
+    assert_eq!(Err(-32602), client.call("read_memory", json!({ "address": 0xffff, "length": 2 })));
+    assert_eq!(Err(-32602), client.call("write_memory", json!({ "address": 0x2400, "bytes": [256] })));
+    assert_eq!(Err(-32000), client.call("load_state", json!({ "path": dir.join("missing.state") })));
+
+    emulator.stop();
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,197 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::time::{ Duration, Instant };
+
+use space_invaders_core::Control;
+
+/// Terminals only send the key presses, and repeat them while the key is held, after a delay (500
+/// ms by default on most systems). A pressed control is released if its key is not repeated in time.
+const FIRST_REPEAT_DELAY: Duration = Duration::from_millis(550);
+const REPEAT_DELAY: Duration = Duration::from_millis(120);
+
+const ESCAPE: u8 = 0x1b;
+const CTRL_C: u8 = 0x03;
+
+/// Key read from the terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Function key, from 1 to 12
+    F(u8),
+    CtrlC,
+    /// Escape sequence of another key, or invalid UTF-8
+    Other,
+}
+
+/// Splits the bytes read from the terminal in keys.
+pub fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = bytes;
+    while let Some(&byte) = rest.first() {
+        let (key, len) = match byte {
+            ESCAPE => parse_escape(rest),
+            b'\r' | b'\n' => (Key::Enter, 1),
+            CTRL_C => (Key::CtrlC, 1),
+            _ => {
+                // the length of the UTF-8 sequence, from its first byte
+                let len = match byte {
+                    0xf0..=0xff => 4,
+                    0xe0..=0xef => 3,
+                    0xc0..=0xdf => 2,
+                    _ => 1,
+                }.min(rest.len());
+                let key = std::str::from_utf8(&rest[..len]).ok()
+                    .and_then(|text| text.chars().next())
+                    .map_or(Key::Other, Key::Char);
+                (key, len)
+            },
+        };
+        keys.push(key);
+        rest = &rest[len..];
+    }
+    keys
+}
+
+/// Key of the escape sequence at the start of `bytes`, and its length
+fn parse_escape(bytes: &[u8]) -> (Key, usize) {
+    match bytes.get(1) {
+        // CSI: parameters, then a final byte between '@' and '~'
+        Some(b'[') => {
+            let end = bytes[2..].iter().position(|byte| (0x40..=0x7e).contains(byte));
+            let end = match end {
+                Some(end) => end + 2,
+                None => return (Key::Other, bytes.len()),
+            };
+            let parameters = std::str::from_utf8(&bytes[2..end]).unwrap_or("");
+            let key = match (bytes[end], parameters) {
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'~', "11") => Key::F(1),
+                (b'~', "12") => Key::F(2),
+                (b'~', "13") => Key::F(3),
+                (b'~', "14") => Key::F(4),
+                (b'~', "15") => Key::F(5),
+                (b'~', "17") => Key::F(6),
+                (b'~', "18") => Key::F(7),
+                (b'~', "19") => Key::F(8),
+                (b'~', "20") => Key::F(9),
+                (b'~', "21") => Key::F(10),
+                (b'~', "23") => Key::F(11),
+                (b'~', "24") => Key::F(12),
+                _ => Key::Other,
+            };
+            (key, end + 1)
+        },
+        // SS3: F1 to F4, or the arrows in application mode
+        Some(b'O') if bytes.len() > 2 => {
+            let key = match bytes[2] {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'P'..=b'S' => Key::F(bytes[2] - b'P' + 1),
+                _ => Key::Other,
+            };
+            (key, 3)
+        },
+        _ => (Key::Other, 1),
+    }
+}
+
+/// Controls pressed by the keys, until they stop being repeated.
+#[derive(Default)]
+pub struct HeldControls {
+    /// Control, time of the last press, and whether its key was repeated
+    held: Vec<(Control, Instant, bool)>,
+}
+
+impl HeldControls {
+    pub fn new() -> HeldControls {
+        HeldControls::default()
+    }
+
+    /// The key of `control` was pressed or repeated at `now`. Returns true if `control` was not
+    /// held: only then are its inputs pressed and its command sent.
+    pub fn press(&mut self, control: Control, now: Instant) -> bool {
+        match self.held.iter_mut().find(|(held, _, _)| *held == control) {
+            Some(held) => {
+                *held = (control, now, true);
+                false
+            },
+            None => {
+                self.held.push((control, now, false));
+                true
+            },
+        }
+    }
+
+    /// Removes and returns the controls whose key was not repeated in time.
+    pub fn release_expired(&mut self, now: Instant) -> Vec<Control> {
+        let mut released = Vec::new();
+        self.held.retain(|&(control, pressed, repeated)| {
+            let delay = if repeated { REPEAT_DELAY } else { FIRST_REPEAT_DELAY };
+            let expired = now.duration_since(pressed) > delay;
+            if expired {
+                released.push(control);
+            }
+            !expired
+        });
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{ Duration, Instant };
+    use space_invaders_core::Control;
+    use keys::*;
+
+    #[test]
+    fn parse_key_sequences() {
+        assert_eq!(
+            vec![Key::Char('s'), Key::Left, Key::Right, Key::F(5), Key::F(1), Key::Enter, Key::Char('é'), Key::CtrlC],
+            parse_keys(b"s\x1b[D\x1b[C\x1b[15~\x1bOP\r\xc3\xa9\x03"));
+        assert_eq!(vec![Key::Other, Key::Char(' ')], parse_keys(b"\x1b[1;5H "));
+        assert_eq!(vec![Key::Other], parse_keys(b"\x1b"));
+    }
+
+    #[test]
+    fn controls_are_released_when_not_repeated() {
+        let start = Instant::now();
+        let mut held = HeldControls::new();
+        assert!(held.press(Control::Fire, start));
+        assert!(held.release_expired(start + Duration::from_millis(500)).is_empty());
+        assert!(!held.press(Control::Fire, start + Duration::from_millis(500)));
+        assert!(held.release_expired(start + Duration::from_millis(600)).is_empty());
+        assert_eq!(vec![Control::Fire], held.release_expired(start + Duration::from_millis(700)));
+        assert!(held.press(Control::Fire, start + Duration::from_millis(800)));
+    }
+
+    #[test]
+    fn repeated_commands_are_pressed_once() {
+        let start = Instant::now();
+        let mut held = HeldControls::new();
+        assert!(held.press(Control::Screenshot, start));
+        assert!(held.press(Control::ToggleCheat(0), start));
+        // auto-repeat of the terminal while the keys are held
+        for repeat in 0..10 {
+            let now = start + Duration::from_millis(500 + repeat * 100);
+            assert!(!held.press(Control::Screenshot, now));
+            assert!(!held.press(Control::ToggleCheat(0), now));
+            assert!(held.release_expired(now).is_empty());
+        }
+        assert!(held.press(Control::ToggleCheat(1), start + Duration::from_millis(1400)));
+        let released = held.release_expired(start + Duration::from_millis(1600));
+        assert_eq!(vec![Control::Screenshot, Control::ToggleCheat(0)], released);
+        assert!(held.press(Control::Screenshot, start + Duration::from_millis(1700)));
+    }
+}
@@ -0,0 +1,182 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+extern crate libc;
+extern crate space_invaders_core;
+
+mod keys;
+mod render;
+mod terminal;
+
+use std::io::{ self, Read };
+use std::sync::mpsc::{ self, Receiver };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use space_invaders_core::{ run_headless, BoardDriver, Control, EmulatorThread, FrontendOptions, StatusMessages, USAGE };
+
+use keys::{ parse_keys, HeldControls, Key };
+use render::{ RenderMode, TerminalRenderer };
+use terminal::{ CapturedStderr, RawTerminal };
+
+const TERMINAL_USAGE: &str = "\
+Options of the terminal:
+    --render <mode>           characters of the screen: braille (default, 2x4 pixels per
+                              character) or half-blocks (1x2 pixels, each in its own colour)
+    --no-overlay              white screen, without the colours of the cabinet overlay";
+
+/// The terminal is redrawn at most this often
+const REDRAW_PERIOD: Duration = Duration::from_millis(16);
+/// Time a message stays on the last row
+const STATUS_DURATION: Duration = Duration::from_secs(5);
+
+/// Options of this frontend, taken from the arguments before the others are given to
+/// `FrontendOptions`.
+struct TerminalOptions {
+    render_mode: RenderMode,
+    overlay: bool,
+}
+
+fn main() {
+    let (terminal_options, options) = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}\n{}", e, USAGE, TERMINAL_USAGE);
+        std::process::exit(1);
+    });
+    if options.headless {
+        if let Err(e) = run_headless(&options) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Err(e) = run_in_terminal(&terminal_options, &options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<(TerminalOptions, FrontendOptions), String> {
+    let mut terminal_options = TerminalOptions { render_mode: RenderMode::Braille, overlay: true };
+    let mut others = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--render" => {
+                let mode = args.next().ok_or_else(|| "missing value for --render".to_string())?;
+                terminal_options.render_mode = mode.parse()?;
+            },
+            "--no-overlay" => terminal_options.overlay = false,
+            _ => others.push(arg),
+        }
+    }
+    let mut options = FrontendOptions::from_args(others.into_iter())?;
+    // the overlay is what gives colours to the screen in the terminal
+    options.overlay = terminal_options.overlay;
+    Ok((terminal_options, options))
+}
+
+fn run_in_terminal(terminal_options: &TerminalOptions, options: &FrontendOptions) -> io::Result<()> {
+    let (driver, rom) = options.load_game().map_err(io::Error::other)?;
+    let stderr = CapturedStderr::start()?;
+    let mut status = StatusMessages::new(STATUS_DURATION);
+    let result = run_game(terminal_options, options, driver, &rom, &stderr, &mut status);
+    // the terminal is restored, the messages not shown yet are printed in order
+    let mut messages = status.take_all();
+    messages.extend(stderr.finish());
+    for message in messages {
+        eprintln!("{}", message);
+    }
+    result
+}
+
+/// Runs the game in the terminal, the messages written on the standard error are shown in
+/// `status`.
+fn run_game(
+    terminal_options: &TerminalOptions, options: &FrontendOptions, driver: &'static dyn BoardDriver, rom: &[u8],
+    stderr: &CapturedStderr, status: &mut StatusMessages,
+) -> io::Result<()> {
+    let terminal = RawTerminal::enter()?;
+    let mut emulator = EmulatorThread::spawn(options, driver, rom);
+    let keys = read_keys();
+    let mut held = HeldControls::new();
+    let mut size = None;
+
+    'running: loop {
+        let now = Instant::now();
+        while let Ok(bytes) = keys.try_recv() {
+            for key in parse_keys(&bytes) {
+                if !handle_key(&emulator, &mut held, key, now) {
+                    break 'running;
+                }
+            }
+        }
+        for control in held.release_expired(now) {
+            emulator.control(control, false);
+        }
+
+        let resized = terminal.size() != size;
+        if resized {
+            terminal.write("\x1b[0m\x1b[2J")?;
+            size = terminal.size();
+        }
+        let (columns, rows) = size.unwrap_or((80, 24));
+        let renderer = TerminalRenderer::new(terminal_options.render_mode, columns, rows);
+        // the achievements unlocked are among the messages of the emulator
+        for line in stderr.lines() {
+            status.push(line);
+        }
+        let status_changed = status.update(now);
+        if status_changed && status.shown().is_none() {
+            terminal.write(&renderer.status_line(""))?;
+        }
+        if emulator.update_frame() || resized || status_changed {
+            terminal.write(&renderer.render(emulator.pixels(), emulator.width(), emulator.height()))?;
+            if let Some(message) = status.shown() {
+                terminal.write(&renderer.status_line(message))?;
+            }
+        }
+        thread::sleep(REDRAW_PERIOD.saturating_sub(now.elapsed()));
+    }
+    emulator.stop();
+    Ok(())
+}
+
+/// Reads the standard input on a thread of its own, and sends what it reads.
+fn read_keys() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 64];
+        let mut stdin = io::stdin();
+        while let Ok(len) = stdin.read(&mut buffer) {
+            if len == 0 || sender.send(buffer[..len].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Returns false to quit.
+fn handle_key(emulator: &EmulatorThread, held: &mut HeldControls, key: Key, now: Instant) -> bool {
+    let control = match key {
+        Key::Enter => Control::Coin,
+        Key::Char('s') => Control::P1Start,
+        Key::Char('t') => Control::P2Start,
+        Key::Char(' ') => Control::Fire,
+        Key::Left => Control::Left,
+        Key::Right => Control::Right,
+        Key::Char('m') => Control::ToggleMute,
+        Key::F(5) => Control::SaveState,
+        Key::F(9) => Control::LoadState,
+        Key::F(10) => Control::ToggleRecording,
+        Key::F(12) => Control::Screenshot,
+        Key::Char(digit @ '1'..='9') => Control::ToggleCheat(digit as usize - '1' as usize),
+        Key::Char('q') | Key::CtrlC => return false,
+        _ => return true,
+    };
+    // the inputs stay pressed while the key is repeated, and the command is only sent once
+    if held.press(control, now) {
+        emulator.control(control, true);
+    }
+    true
+}
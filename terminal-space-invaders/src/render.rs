@@ -0,0 +1,204 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Sum of the components of the darkest pixel drawn as a braille dot
+const BRAILLE_THRESHOLD: u32 = 96;
+const BRAILLE_BLANK: u32 = 0x2800;
+/// Bit of the braille pattern of each dot, by row then column
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+const UPPER_HALF_BLOCK: char = '\u{2580}';
+
+type Rgb = (u8, u8, u8);
+const BLACK: Rgb = (0, 0, 0);
+
+/// How the pixels are drawn with characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Upper half blocks, 1×2 pixels per character, each pixel in its own colour (the foreground
+    /// for the upper one, the background for the lower one)
+    HalfBlocks,
+    /// Braille patterns, 2×4 pixels per character, lit or not, in the colour of the brightest one
+    Braille,
+}
+
+impl RenderMode {
+    /// Pixels per character, horizontally and vertically
+    fn cell_size(self) -> (usize, usize) {
+        match self {
+            RenderMode::HalfBlocks => (1, 2),
+            RenderMode::Braille => (2, 4),
+        }
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<RenderMode, String> {
+        match name {
+            "half-blocks" => Ok(RenderMode::HalfBlocks),
+            "braille" => Ok(RenderMode::Braille),
+            _ => Err(format!("unknown render mode {}", name)),
+        }
+    }
+}
+
+/// Draws RGBA frames in a terminal of a given size, with ANSI escape codes and 24 bits colours.
+///
+/// The frame is shrunk by the smallest integer factor which makes it fit, each dot of the terminal
+/// taking the brightest of the pixels it covers (so that the shots, one pixel wide, stay visible),
+/// and is centered horizontally.
+pub struct TerminalRenderer {
+    mode: RenderMode,
+    columns: usize,
+    rows: usize,
+}
+
+impl TerminalRenderer {
+    pub fn new(mode: RenderMode, columns: usize, rows: usize) -> TerminalRenderer {
+        TerminalRenderer { mode, columns, rows }
+    }
+
+    /// Pixels of the frame per dot of the terminal
+    pub fn scale(&self, width: usize, height: usize) -> usize {
+        let (cell_width, cell_height) = self.mode.cell_size();
+        let columns = self.columns.max(1) * cell_width;
+        let rows = self.rows.max(1) * cell_height;
+        (1..).find(|scale| width.div_ceil(*scale) <= columns && height.div_ceil(*scale) <= rows).unwrap()
+    }
+
+    /// Escape codes and characters drawing the RGBA `pixels` of a `width` × `height` frame from the
+    /// top left corner of the terminal.
+    pub fn render(&self, pixels: &[u8], width: usize, height: usize) -> String {
+        let scale = self.scale(width, height);
+        let dot = |x: usize, y: usize| brightest(pixels, width, height, x * scale, y * scale, scale);
+        let (cell_width, cell_height) = self.mode.cell_size();
+        let (dot_columns, dot_rows) = (width.div_ceil(scale), height.div_ceil(scale));
+        let (columns, rows) = (dot_columns.div_ceil(cell_width), dot_rows.div_ceil(cell_height));
+        let left = (self.columns.saturating_sub(columns)) / 2;
+
+        let mut out = String::new();
+        let mut colours = None;
+        for row in 0..rows {
+            let _ = write!(out, "\x1b[{};{}H", row + 1, left + 1);
+            for column in 0..columns {
+                let (character, foreground, background) = match self.mode {
+                    RenderMode::HalfBlocks => {
+                        let lower = if row * 2 + 1 < dot_rows { dot(column, row * 2 + 1) } else { BLACK };
+                        (UPPER_HALF_BLOCK, dot(column, row * 2), lower)
+                    },
+                    RenderMode::Braille => {
+                        let mut pattern = 0;
+                        let mut foreground = BLACK;
+                        for (dy, bits) in BRAILLE_DOTS.iter().enumerate() {
+                            for (dx, bit) in bits.iter().enumerate() {
+                                let (x, y) = (column * 2 + dx, row * 4 + dy);
+                                if x >= dot_columns || y >= dot_rows {
+                                    continue;
+                                }
+                                let colour = dot(x, y);
+                                if brightness(colour) >= BRAILLE_THRESHOLD {
+                                    pattern |= bit;
+                                    if brightness(colour) > brightness(foreground) {
+                                        foreground = colour;
+                                    }
+                                }
+                            }
+                        }
+                        (std::char::from_u32(BRAILLE_BLANK + pattern).unwrap(), foreground, BLACK)
+                    },
+                };
+                if colours != Some((foreground, background)) {
+                    let ((fr, fg, fb), (br, bg, bb)) = (foreground, background);
+                    let _ = write!(out, "\x1b[38;2;{};{};{};48;2;{};{};{}m", fr, fg, fb, br, bg, bb);
+                    colours = Some((foreground, background));
+                }
+                out.push(character);
+            }
+        }
+        out.push_str("\x1b[0m");
+        out
+    }
+
+    /// Escape codes and characters writing `text` on the last row of the terminal, over the
+    /// frame, cut to its width. An empty text clears the row.
+    pub fn status_line(&self, text: &str) -> String {
+        let text: String = text.chars().take(self.columns).collect();
+        format!("\x1b[{};1H\x1b[0m\x1b[2K{}", self.rows.max(1), text)
+    }
+}
+
+/// Brightest pixel of the `size` × `size` square at (`x`, `y`), black out of the frame
+fn brightest(pixels: &[u8], width: usize, height: usize, x: usize, y: usize, size: usize) -> Rgb {
+    let mut brightest = BLACK;
+    for py in y..(y + size).min(height) {
+        for px in x..(x + size).min(width) {
+            let index = (py * width + px) * 4;
+            let colour = (pixels[index], pixels[index + 1], pixels[index + 2]);
+            if brightness(colour) > brightness(brightest) {
+                brightest = colour;
+            }
+        }
+    }
+    brightest
+}
+
+fn brightness((r, g, b): Rgb) -> u32 {
+    r as u32 + g as u32 + b as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use render::*;
+
+    /// RGBA frame of `width` × `height` pixels with the `lit` ones white
+    fn frame(width: usize, height: usize, lit: &[(usize, usize)]) -> Vec<u8> {
+        let mut pixels = vec![0; width * height * 4];
+        for &(x, y) in lit {
+            let index = (y * width + x) * 4;
+            pixels[index..(index + 4)].copy_from_slice(&[255, 255, 255, 255]);
+        }
+        pixels
+    }
+
+    #[test]
+    fn frame_is_shrunk_to_fit() {
+        let renderer = TerminalRenderer::new(RenderMode::Braille, 112, 64);
+        assert_eq!(1, renderer.scale(224, 256));
+        let renderer = TerminalRenderer::new(RenderMode::Braille, 80, 24);
+        assert_eq!(3, renderer.scale(224, 256));
+        let renderer = TerminalRenderer::new(RenderMode::HalfBlocks, 80, 24);
+        assert_eq!(6, renderer.scale(224, 256));
+    }
+
+    #[test]
+    fn braille_dots() {
+        let renderer = TerminalRenderer::new(RenderMode::Braille, 4, 1);
+        let pixels = frame(4, 4, &[(0, 0), (1, 3), (3, 1)]);
+        // centered in 4 columns, the first character has the dots 1 and 8, the second one dot 5
+        assert_eq!(
+            "\x1b[1;2H\x1b[38;2;255;255;255;48;2;0;0;0m\u{2881}\u{2810}\x1b[0m",
+            renderer.render(&pixels, 4, 4));
+    }
+
+    #[test]
+    fn half_blocks_in_two_colours() {
+        let renderer = TerminalRenderer::new(RenderMode::HalfBlocks, 2, 1);
+        // shrunk by 2, the lit pixel is kept
+        let pixels = frame(4, 4, &[(3, 1)]);
+        assert_eq!(
+            "\x1b[1;1H\x1b[38;2;0;0;0;48;2;0;0;0m\u{2580}\x1b[38;2;255;255;255;48;2;0;0;0m\u{2580}\x1b[0m",
+            renderer.render(&pixels, 4, 4));
+    }
+
+    #[test]
+    fn status_line_is_cut_to_the_width() {
+        let renderer = TerminalRenderer::new(RenderMode::Braille, 8, 24);
+        assert_eq!("\x1b[24;1H\x1b[0m\x1b[2Kunlocked", renderer.status_line("unlocked: UFO hunter"));
+        assert_eq!("\x1b[24;1H\x1b[0m\x1b[2K", renderer.status_line(""));
+    }
+}
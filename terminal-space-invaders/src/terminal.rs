@@ -0,0 +1,148 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fs::File;
+use std::io::{ self, BufRead, BufReader, Write };
+use std::mem;
+use std::os::unix::io::FromRawFd;
+use std::sync::mpsc::{ self, Receiver };
+use std::thread::{ self, JoinHandle };
+
+/// Alternate screen (the content of the terminal is back on exit), hidden cursor, cleared screen
+const ENTER: &str = "\x1b[?1049h\x1b[?25l\x1b[2J";
+const LEAVE: &str = "\x1b[0m\x1b[?25h\x1b[?1049l";
+
+/// Terminal in raw mode: the keys are read as they are typed, without echo nor line editing, and
+/// Ctrl-C is an ordinary key. The terminal is restored when dropped, even after a panic.
+pub struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    /// Switches the terminal of the standard input to raw mode, and the output to the alternate
+    /// screen.
+    pub fn enter() -> io::Result<RawTerminal> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 || libc::isatty(libc::STDOUT_FILENO) != 1 {
+                return Err(io::Error::other("the standard input and output must be a terminal"));
+            }
+            let mut original: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let terminal = RawTerminal { original };
+            terminal.write(ENTER)?;
+            Ok(terminal)
+        }
+    }
+
+    /// Writes `text` on the standard output right away.
+    pub fn write(&self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Number of columns and rows of the terminal
+    pub fn size(&self) -> Option<(usize, usize)> {
+        unsafe {
+            let mut size: libc::winsize = mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 || size.ws_col == 0 {
+                return None;
+            }
+            Some((size.ws_col as usize, size.ws_row as usize))
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = self.write(LEAVE);
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Standard error sent to a pipe, whose lines are read on a thread of their own: printed on the
+/// terminal in raw mode, they would be drawn over the frame. The lines not taken are printed on
+/// the standard error once it is restored, when dropped (e.g. after a panic).
+pub struct CapturedStderr {
+    original: libc::c_int,
+    lines: Receiver<String>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl CapturedStderr {
+    pub fn start() -> io::Result<CapturedStderr> {
+        unsafe {
+            let mut fds = [0; 2];
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = libc::dup(libc::STDERR_FILENO);
+            if original < 0 || libc::dup2(fds[1], libc::STDERR_FILENO) < 0 {
+                let error = io::Error::last_os_error();
+                libc::close(fds[0]);
+                libc::close(fds[1]);
+                if original >= 0 {
+                    libc::close(original);
+                }
+                return Err(error);
+            }
+            libc::close(fds[1]);
+            let pipe = File::from_raw_fd(fds[0]);
+            let (sender, lines) = mpsc::channel();
+            let reader = thread::spawn(move || {
+                // the pipe is read until it is closed, a writer must never be blocked on it
+                let mut pipe = BufReader::new(pipe);
+                let mut line = Vec::new();
+                while let Ok(len) = pipe.read_until(b'\n', &mut line) {
+                    if len == 0 {
+                        break;
+                    }
+                    let _ = sender.send(String::from_utf8_lossy(&line).trim_end().to_string());
+                    line.clear();
+                }
+            });
+            Ok(CapturedStderr { original, lines, reader: Some(reader) })
+        }
+    }
+
+    /// Lines written since the last call
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.try_iter().collect()
+    }
+
+    /// Restores the standard error, and returns the lines written since the last call.
+    pub fn finish(mut self) -> Vec<String> {
+        self.restore();
+        self.lines()
+    }
+
+    fn restore(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            unsafe {
+                // the pipe has no writer left, its reader stops at its end
+                libc::dup2(self.original, libc::STDERR_FILENO);
+                libc::close(self.original);
+            }
+            let _ = reader.join();
+        }
+    }
+}
+
+impl Drop for CapturedStderr {
+    fn drop(&mut self) {
+        self.restore();
+        for line in self.lines.try_iter() {
+            eprintln!("{}", line);
+        }
+    }
+}
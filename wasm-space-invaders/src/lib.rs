@@ -3,24 +3,22 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
  
 extern crate cfg_if;
-extern crate intel_8080_emu;
 extern crate space_invaders_core;
 extern crate wasm_bindgen;
-extern crate web_sys;
 
 mod utils;
 
-use std::cell::RefCell;
-use std::ops::Sub;
-use std::rc::Rc;
+use std::collections::VecDeque;
+use std::sync::mpsc::{ self, Receiver };
 use std::time::Duration;
 
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::Clamped;
-use web_sys::{CanvasRenderingContext2d, ImageData};
 
-use intel_8080_emu::proc_state::Proc8080;
-use space_invaders_core::{SpaceInvaderDataBus, SpaceInvaderMachine, INVADERS_ROM};
+use space_invaders_core::{
+  Achievement, AchievementEngine, AudioSink, BoardDriver, CheatEngine, Clock, Control, CrtSettings, DipSwitches, Emulator, HostEvent, HostLoop,
+  InputSource, Screen, SoundLatches, Synth, VideoSink, INVADERS,
+  color_palette, CPU_FREQUENCY, INVADERS_ACHIEVEMENTS, INVADERS_CHEATS, INVADERS_ROM,
+};
 
 #[wasm_bindgen]
 #[no_mangle]
@@ -35,124 +33,612 @@ macro_rules! console_log {
 
 #[wasm_bindgen]
 pub struct App {
-  cpu: Proc8080<SpaceInvaderDataBus>,
-  machine: Rc<RefCell<SpaceInvaderMachine>>,
-  time_elapsed: Duration,
-  last_vbl_interrupt: Duration,
-  half_interrupt_done: bool,
+  /// The emulator, run by `run_until` at the time of the browser
+  host: HostLoop<FrameSink, SampleBuffer, ActionQueue, BrowserClock>,
+  /// High score set before the game booted (it initializes its RAM during the first frame)
+  pending_high_score: Option<u32>,
+  /// Number of faults already returned by `take_faults`
+  reported_faults: u64,
+  /// Achievements unlocked, until `take_unlocked_achievements`
+  unlocks: Receiver<Achievement>,
 }
 
-const FRAME_DURATION: Duration = Duration::from_nanos(16666667);
-const HALF_FRAME_DURATION: Duration = Duration::from_nanos(16666667 / 2);
-const PIXEL_WIDTH: u32 = 224;
-const PIXEL_HEIGHT: u32 = 256;
+/// Abstract inputs of the cabinet, independent of the device (keyboard, gamepad, touch screen...)
+/// which triggered them.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+  Coin,
+  P1Start,
+  P2Start,
+  Fire,
+  Left,
+  Right,
+}
+
+const FRAME_CYCLES: u64 = CPU_FREQUENCY / 60;
+// If the page is hidden, requestAnimationFrame stops. The emulation does not try to catch up more
+// than this when it comes back.
+const MAX_CATCH_UP_FRAMES: u32 = 10;
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// Samples are dropped if js does not consume them (e.g. the audio context is not started yet)
+const MAX_BUFFERED_AUDIO_SECONDS: usize = 1;
 
 #[wasm_bindgen]
 impl App {
+  /// Builds the app with the ROM embedded at compile time (see `has_bundled_rom`).
   pub fn new() -> App {
-    let machine = Rc::new(RefCell::new(SpaceInvaderMachine::new()));
-    let data_bus = SpaceInvaderDataBus::new(machine.clone());
-    let mut memory = Box::new([0x00; 0xffff]);
-    memory[0..INVADERS_ROM.len()].copy_from_slice(INVADERS_ROM);
-    let cpu = Proc8080::new(memory, data_bus);
+    App::with_rom(&INVADERS, INVADERS_ROM)
+  }
 
-    App {
-      cpu,
-      machine,
-      time_elapsed: Duration::from_millis(0),
-      last_vbl_interrupt: Duration::from_millis(0),
-      half_interrupt_done: false,
-    }
+  /// Builds the app with a space invaders ROM image given by the user, which must be a known dump
+  /// (see `RomSet` for the sets of several files).
+  pub fn new_with_rom(rom: &[u8]) -> Result<App, JsError> {
+    space_invaders_core::assemble_rom(&INVADERS, &[("rom", rom)])?;
+    Ok(App::with_rom(&INVADERS, rom))
   }
 
+  /// Builds the app running the game `game` (short name, see `RomSet::game`) with the ROM image
+  /// built by `RomSet::build`, which must be a known dump of the game.
+  pub fn new_with_game(game: &str, rom: &[u8]) -> Result<App, JsError> {
+    let driver = space_invaders_core::find_driver(game)
+      .ok_or_else(|| JsError::new(&format!("unknown game {}", game)))?;
+    space_invaders_core::assemble_rom(driver, &[("rom", rom)])?;
+    Ok(App::with_rom(driver, rom))
+  }
+
+  /// Full name of the game
+  pub fn title(&self) -> String {
+    self.driver().title().to_string()
+  }
+
+  /// Emulates `duration_millis` ms of the machine.
   pub fn run(&mut self, duration_millis: u32) {
-    let duration = Duration::from_millis(duration_millis as u64);
-    let new_duration = self.time_elapsed + duration;
-
-    if new_duration.sub(self.last_vbl_interrupt) > FRAME_DURATION {
-      self.cpu.interrupt(1);
-      self.last_vbl_interrupt = new_duration;
-      self.half_interrupt_done = false;
-    } else if new_duration.sub(self.last_vbl_interrupt) > HALF_FRAME_DURATION
-      && !self.half_interrupt_done
-    {
-      self.cpu.interrupt(2);
-      self.half_interrupt_done = true;
+    self.host.run_for(Duration::from_millis(duration_millis as u64));
+    self.set_pending_high_score();
+  }
+
+  /// Emulates up to the end of the frame (the next vertical blank).
+  pub fn run_frame(&mut self) {
+    self.host.run_frame();
+    self.set_pending_high_score();
+  }
+
+  /// Emulates the machine up to `timestamp` (in ms, e.g. the one given by `requestAnimationFrame`).
+  /// The first call only sets the time origin. The emulation catches up over several frames if the
+  /// previous call was long ago, up to `MAX_CATCH_UP_FRAMES`.
+  pub fn run_until(&mut self, timestamp: f64) {
+    self.host.clock_mut().now = Duration::from_secs_f64(timestamp.max(0.0) / 1000.0);
+    self.host.step();
+    self.set_pending_high_score();
+  }
+
+  /// Serializes the emulation, in the same format as the native frontends.
+  pub fn save_state(&self) -> Vec<u8> {
+    space_invaders_core::save_state(self.host.emulator().cpu())
+  }
+
+  /// Restores a state built by `save_state` (or by a native frontend).
+  pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsError> {
+    // the state does not say where the beam is, the next frame starts now
+    self.host.emulator_mut().load_state(state)?;
+    self.pending_high_score = None;
+    Ok(())
+  }
+
+  /// High score of the game, 0 for the games where it is not known.
+  pub fn high_score(&self) -> u32 {
+    self
+      .pending_high_score
+      .or_else(|| space_invaders_core::high_score(self.driver(), self.host.emulator().cpu().memory()))
+      .unwrap_or(0)
+  }
+
+  /// Sets the high score displayed by the game (4 digits at most).
+  pub fn set_high_score(&mut self, score: u32) {
+    if self.driver().high_score_address().is_none() {
+      return;
     }
+    self.pending_high_score = Some(score);
+    self.set_pending_high_score();
+  }
+
+  pub fn lives(&self) -> u8 {
+    self.dip_switches().lives
+  }
+
+  pub fn extra_life_at_1000(&self) -> bool {
+    self.dip_switches().extra_life_at_1000
+  }
+
+  pub fn coin_info(&self) -> bool {
+    self.dip_switches().coin_info
+  }
+
+  /// Sets the DIP switches of the cabinet: ships per game (3 to 6), extra ship at 1000 points
+  /// instead of 1500 and coin information in the demo screen.
+  pub fn set_dip_switches(&mut self, lives: u8, extra_life_at_1000: bool, coin_info: bool) {
+    let dip_switches = DipSwitches { lives, extra_life_at_1000, coin_info };
+    self.host.emulator().input_latch().set_dip_switches(dip_switches);
+  }
+
+  /// Sets the colours of the screen, as `0xRRGGBB`.
+  pub fn set_palette(&mut self, foreground: u32, background: u32) {
+    self.screen_mut().set_palette(rgba(foreground), rgba(background));
+  }
 
-    let cycles = ((duration.as_secs() * 1000000) + (duration.subsec_nanos() as u64)) / 500;
-    let current_cycles = self.cpu.cycles();
-    while self.cpu.cycles() < (current_cycles + cycles) {
-      self.cpu.emulate();
+  /// Sets the colours of the colour RAM from the colour PROM of the game (see `RomSet::color_prom`).
+  /// Without it, the colours are approximated. Ignored by the black and white games.
+  pub fn set_color_prom(&mut self, prom: &[u8]) -> Result<(), JsError> {
+    if !self.driver().has_color_ram() {
+      return Ok(());
     }
+    if prom.len() < 8 {
+      return Err(JsError::new("the colour PROM is too short"));
+    }
+    self.screen_mut().set_color_palette(Some(color_palette(Some(prom))));
+    Ok(())
+  }
 
-    self.time_elapsed = new_duration;
+  /// Colours the screen like the cellophane strips of the cabinet: red at the top, green at the
+  /// bottom.
+  pub fn set_overlay(&mut self, overlay: bool) {
+    self.screen_mut().set_overlay(overlay);
   }
 
-  pub fn draw(&self, ctx: &CanvasRenderingContext2d) -> Result<(), JsValue> {
-    let pixels: &[u8] = &self.cpu.memory()[0x2400..0x4000];
-    let mut buffer = vec![200; (PIXEL_WIDTH * PIXEL_HEIGHT * 4) as usize];
+  /// Takes each half of the screen when the beam of the monitor scans it (the first one at the
+  /// mid-screen interrupt, the other at the vertical blank) rather than all the video RAM in
+  /// `update_frame`, so that the objects the game moves do not tear.
+  pub fn set_beam_sync(&mut self, beam_sync: bool) {
+    self.screen_mut().set_beam_sync(beam_sync);
+  }
+
+  /// PNG image of the screen, with the palette and the overlay but without the crt filter. Each
+  /// pixel is repeated `scale` times (1 to 8) in both directions.
+  pub fn screenshot_png(&self, scale: usize) -> Vec<u8> {
+    self.host.emulator().screen().frame().to_png(scale)
+  }
 
-    for (i, px_byte) in pixels.iter().enumerate() {
-      let (x_source, y_source) = memory_buffer_index_to_coordinates(i);
+  /// Sample rate of the samples returned by `take_audio_samples` (the one of the `AudioContext`).
+  pub fn set_sample_rate(&mut self, sample_rate: u32) {
+    let audio = self.host.audio_mut();
+    audio.synth.set_sample_rate(sample_rate);
+    audio.samples.clear();
+    audio.cycles_remainder = 0;
+  }
 
-      let x_target = y_source;
+  /// Returns the mono samples produced since the last call.
+  pub fn take_audio_samples(&mut self) -> Vec<f32> {
+    std::mem::take(&mut self.host.audio_mut().samples)
+  }
 
-      for bit in 0..8 {
-        let y_target = PIXEL_HEIGHT - 1 - x_source - bit;
+  pub fn volume(&self) -> f32 {
+    self.host.audio().synth.volume()
+  }
 
-        let px = px_byte & (1 << bit) == 0;
+  pub fn set_volume(&mut self, volume: f32) {
+    self.host.audio_mut().synth.set_volume(volume)
+  }
 
-        const WHITE: [u8; 4] = [255, 255, 255, 255];
-        const BLACK: [u8; 4] = [0, 0, 0, 255];
-        let index = (x_target * 4 + (4 * PIXEL_WIDTH * y_target)) as usize;
+  pub fn is_muted(&self) -> bool {
+    self.host.audio().is_muted()
+  }
+
+  pub fn set_muted(&mut self, muted: bool) {
+    self.host.audio_mut().set_muted(muted)
+  }
+
+  /// Sets the CRT post-processing, every value is between 0 and 1 (0 disables the effect).
+  pub fn set_crt(&mut self, phosphor_decay: f32, scanlines: f32, glow: f32, curvature: f32) {
+    self.screen_mut().set_crt(CrtSettings { phosphor_decay, scanlines, glow, curvature });
+  }
+
+  /// Sets what the machine does when the game uses a port the board does not have: `ignore`, `log`
+  /// (the default) or `halt`.
+  pub fn set_fault_policy(&mut self, name: &str) -> Result<(), JsError> {
+    let policy = name.parse().map_err(|e: String| JsError::new(&e))?;
+    self.host.emulator_mut().cpu_mut().data_bus_mut().machine_mut().set_fault_policy(policy);
+    Ok(())
+  }
+
+  /// Faults logged since the last call, one per line.
+  pub fn take_faults(&mut self) -> String {
+    let faults = self.host.emulator().cpu().data_bus().machine().faults();
+    let new: Vec<String> = faults.since(self.reported_faults).map(|fault| fault.to_string()).collect();
+    self.reported_faults = faults.total();
+    new.join("\n")
+  }
+
+  /// Whether a fault stopped the machine, with the `halt` policy. The state can still be saved.
+  pub fn is_halted(&self) -> bool {
+    self.host.emulator().cpu().data_bus().machine().is_halted()
+  }
+
+  /// Restarts the machine stopped by a fault.
+  pub fn resume(&mut self) {
+    self.host.emulator_mut().cpu_mut().data_bus_mut().machine_mut().resume();
+  }
+
+  /// Replaces the cheats (space invaders starts with its own ones, all disabled) by the ones of a
+  /// cheat file, or of a MAME cheat file if it starts with `<`. Returns the names of the MAME
+  /// cheats which could not be imported, one per line.
+  pub fn load_cheats(&mut self, text: &str) -> Result<String, JsError> {
+    let (cheats, skipped) = if text.trim_start().starts_with('<') {
+      let imported = space_invaders_core::import_mame_cheats(text)?;
+      (imported.cheats, imported.skipped)
+    } else {
+      (space_invaders_core::parse_cheats(text)?, Vec::new())
+    };
+    self.host.emulator_mut().set_cheats(CheatEngine::new(cheats));
+    Ok(skipped.join("\n"))
+  }
+
+  /// Names of the cheats, one per line. The keys 1 to 9 toggle the first nine.
+  pub fn cheat_names(&self) -> String {
+    let names: Vec<&str> = self.host.emulator().cheats().cheats().iter().map(|cheat| cheat.name.as_str()).collect();
+    names.join("\n")
+  }
+
+  pub fn is_cheat_enabled(&self, index: usize) -> bool {
+    self.host.emulator().cheats().is_enabled(index)
+  }
+
+  /// Enables the cheat `index` if it is disabled, and the other way round. Returns whether it is
+  /// now enabled.
+  pub fn toggle_cheat(&mut self, index: usize) -> Result<bool, JsError> {
+    self.host.emulator_mut().toggle_cheat(index)
+      .ok_or_else(|| JsError::new(&format!("there is no cheat {}", index + 1)))
+  }
+
+  /// Replaces the achievements (space invaders starts with its own ones) by the ones of an
+  /// achievement file, all locked.
+  pub fn load_achievements(&mut self, text: &str) -> Result<(), JsError> {
+    let achievements = space_invaders_core::parse_achievements(text)?;
+    self.host.emulator_mut().set_achievements(AchievementEngine::new(achievements));
+    Ok(())
+  }
+
+  /// Names of the achievements, one per line.
+  pub fn achievement_names(&self) -> String {
+    let achievements = self.host.emulator().achievements().achievements();
+    let names: Vec<&str> = achievements.iter().map(|achievement| achievement.name.as_str()).collect();
+    names.join("\n")
+  }
+
+  pub fn achievement_description(&self, index: usize) -> String {
+    let achievements = self.host.emulator().achievements().achievements();
+    achievements.get(index).map(|achievement| achievement.description.clone()).unwrap_or_default()
+  }
+
+  pub fn is_achievement_unlocked(&self, index: usize) -> bool {
+    self.host.emulator().achievements().is_unlocked(index)
+  }
+
+  /// Unlocks an achievement unlocked in an earlier session. Returns false if there is no such
+  /// achievement.
+  pub fn unlock_achievement(&mut self, name: &str) -> bool {
+    self.host.emulator_mut().achievements_mut().mark_unlocked(name)
+  }
+
+  /// Names of the achievements unlocked since the last call, one per line. They are not checked
+  /// while a cheat is enabled.
+  pub fn take_unlocked_achievements(&mut self) -> String {
+    let names: Vec<String> = self.unlocks.try_iter().map(|achievement| achievement.name).collect();
+    names.join("\n")
+  }
+
+  /// Sets the pixel-art upscaler by name (`nearest<1-8>`, `scale2x`, `scale3x`, `epx` or `xbr`), or
+  /// disables it with an empty name. The size of the frame changes accordingly.
+  pub fn set_upscaler(&mut self, name: &str) -> Result<(), JsError> {
+    let upscaler = if name.is_empty() {
+      None
+    } else {
+      Some(name.parse().map_err(|e: String| JsError::new(&e))?)
+    };
+    self.screen_mut().set_upscaler(upscaler);
+    Ok(())
+  }
+
+  /// Returns `false` if the screen did not change since the last call (nothing has to be drawn).
+  /// The video RAM (and the colour RAM on the colour boards) is decoded at each vertical blank.
+  pub fn update_frame(&mut self) -> bool {
+    let presented = std::mem::replace(&mut self.host.video_mut().presented, false);
+    // changed by the settings since the last run
+    let changed = self.screen_mut().take_changed();
+    presented || changed
+  }
+
+  /// Pointer to the RGBA frame buffer in the wasm memory. The buffer does not move until the crt
+  /// filter or the upscaler change, so js can keep an `ImageData` on it until `frame_ptr` changes.
+  pub fn frame_ptr(&self) -> *const u8 {
+    self.host.emulator().screen().pixels().as_ptr()
+  }
+
+  /// Length in bytes of the frame buffer
+  pub fn frame_len(&self) -> usize {
+    self.host.emulator().screen().pixels().len()
+  }
+
+  pub fn frame_width(&self) -> usize {
+    self.host.emulator().screen().width()
+  }
 
-      
-        buffer[index..(index+4)].copy_from_slice(if px { &BLACK } else { &WHITE });
-        // console_log!("{:?}", &buffer[index..(index+4)]);
-      }
+  pub fn frame_height(&self) -> usize {
+    self.host.emulator().screen().height()
+  }
+
+  pub fn press(&mut self, action: Action) {
+    self.set_action(action, true)
+  }
+
+  pub fn release(&mut self, action: Action) {
+    self.set_action(action, false)
+  }
+
+  /// Presses or releases the inputs of `action`, the game sees them on the next run.
+  pub fn set_action(&mut self, action: Action, pressed: bool) {
+    let control = match action {
+      Action::Coin => Control::Coin,
+      Action::P1Start => Control::P1Start,
+      Action::P2Start => Control::P2Start,
+      Action::Fire => Control::Fire,
+      Action::Left => Control::Left,
+      Action::Right => Control::Right,
+    };
+    let events = &mut self.host.input_mut().events;
+    events.extend(control.inputs().iter().map(|&input| HostEvent::Input(input, pressed)));
+  }
+
+}
+
+impl App {
+  fn with_rom(driver: &'static dyn BoardDriver, rom: &[u8]) -> App {
+    utils::set_panic_hook();
+    let mut screen = Screen::new(CrtSettings::disabled(), None);
+    if driver.has_color_ram() {
+      screen.set_color_palette(Some(color_palette(None)));
+    }
+    let mut emulator = Emulator::new(driver, rom, screen);
+    if driver.name() == INVADERS.name() {
+      let cheats = space_invaders_core::parse_cheats(INVADERS_CHEATS).unwrap_or_else(|e| {
+        console_log!("invalid cheats of space invaders, they are disabled: {}", e);
+        Vec::new()
+      });
+      emulator.set_cheats(CheatEngine::new(cheats));
+      let achievements = space_invaders_core::parse_achievements(INVADERS_ACHIEVEMENTS).unwrap_or_else(|e| {
+        console_log!("invalid achievements of space invaders, they are disabled: {}", e);
+        Vec::new()
+      });
+      emulator.set_achievements(AchievementEngine::new(achievements));
+    }
+    let (sender, unlocks) = mpsc::channel();
+    emulator.send_unlocks(sender);
+    let audio = SampleBuffer {
+      synth: Synth::new(DEFAULT_SAMPLE_RATE),
+      samples: Vec::new(),
+      cycles_remainder: 0,
+    };
+    let mut host = HostLoop::new(emulator, FrameSink::default(), audio, ActionQueue::default(), BrowserClock::default());
+    host.set_max_catch_up_frames(MAX_CATCH_UP_FRAMES);
+
+    App {
+      host,
+      pending_high_score: None,
+      reported_faults: 0,
+      unlocks,
+    }
+  }
+
+  fn driver(&self) -> &'static dyn BoardDriver {
+    self.host.emulator().cpu().data_bus().machine().driver()
+  }
+
+  fn screen_mut(&mut self) -> &mut Screen {
+    self.host.emulator_mut().screen_mut()
+  }
+
+  fn dip_switches(&self) -> DipSwitches {
+    self.host.emulator().input_latch().dip_switches()
+  }
+
+  /// Writes the high score set before the game booted, once it ran its first frame.
+  fn set_pending_high_score(&mut self) {
+    let driver = self.driver();
+    let cpu = self.host.emulator_mut().cpu_mut();
+    if cpu.cycles() < FRAME_CYCLES {
+      return;
     }
+    if let Some(score) = self.pending_high_score.take() {
+      space_invaders_core::set_high_score(driver, cpu.memory_mut(), score);
+    }
+  }
+}
+
+/// The frames are drawn by js from `App::frame_ptr`, the sink only notes that there is a new one.
+#[derive(Default)]
+struct FrameSink {
+  presented: bool,
+}
+
+impl VideoSink for FrameSink {
+  fn present(&mut self, _screen: &Screen) {
+    self.presented = true;
+  }
+}
 
-    // console_log!("{}", buffer[110*120*4]);
-    ctx.set_image_smoothing_enabled(false);
-    let image_data = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&mut buffer), PIXEL_WIDTH, PIXEL_HEIGHT)?;
-    
-    ctx.put_image_data(&image_data, 0.0, 0.0)
-  }
-
-  pub fn handle_key_down(&self, keycode: &str) {
-    self.handle_key(keycode, true)
-  } 
-
-  pub fn handle_key_up(&self, keycode: &str) {
-    self.handle_key(keycode, false)
-  } 
-
-  fn handle_key(&self, keycode: &str, down: bool) {
-    match keycode {
-            "Enter" => self.machine.borrow_mut().insert_coin(down),
-            "ControlLeft" => self.machine.borrow_mut().p1_start_button(down),
-            "ControlRight" => self.machine.borrow_mut().p2_start_button(down),
-            "Space" => {
-                self.machine.borrow_mut().p1_fire_button(down);
-                self.machine.borrow_mut().p2_fire_button(down);
-            },
-            "ArrowLeft" => {
-                self.machine.borrow_mut().p1_left_button(down);
-                self.machine.borrow_mut().p2_left_button(down);
-            },
-            "ArrowRight" => {
-                self.machine.borrow_mut().p1_right_button(down);
-                self.machine.borrow_mut().p2_right_button(down);
-            },
-            _ => (),
+/// Samples kept until js takes them with `App::take_audio_samples`
+struct SampleBuffer {
+  synth: Synth,
+  samples: Vec<f32>,
+  cycles_remainder: u64,
+}
+
+impl AudioSink for SampleBuffer {
+  fn update(&mut self, latches: SoundLatches, cycles: u64) {
+    let total = cycles * self.synth.sample_rate() as u64 + self.cycles_remainder;
+    let sample_count = (total / CPU_FREQUENCY) as usize;
+    self.cycles_remainder = total % CPU_FREQUENCY;
+
+    self.synth.set_latches(latches);
+    let start = self.samples.len();
+    self.samples.resize(start + sample_count, 0.0);
+    self.synth.render(&mut self.samples[start..]);
+
+    let max_samples = self.synth.sample_rate() as usize * MAX_BUFFERED_AUDIO_SECONDS;
+    if self.samples.len() > max_samples {
+      let overflow = self.samples.len() - max_samples;
+      self.samples.drain(0..overflow);
     }
   }
 
+  fn is_muted(&self) -> bool {
+    self.synth.is_muted()
+  }
+
+  fn set_muted(&mut self, muted: bool) {
+    self.synth.set_muted(muted)
+  }
+}
+
+/// Inputs pressed and released by js since the last run
+#[derive(Default)]
+struct ActionQueue {
+  events: VecDeque<HostEvent>,
+}
+
+impl InputSource for ActionQueue {
+  fn poll(&mut self) -> Option<HostEvent> {
+    self.events.pop_front()
+  }
+}
+
+/// Time given to `App::run_until`. The browser calls it on each of its frames, so there is no
+/// waiting.
+#[derive(Default)]
+struct BrowserClock {
+  now: Duration,
+}
+
+impl Clock for BrowserClock {
+  fn now(&self) -> Duration {
+    self.now
+  }
+
+  fn wait_until(&mut self, _time: Duration) {}
+}
+
+/// Whether a known ROM was embedded at compile time. Otherwise the user has to provide one.
+#[wasm_bindgen]
+pub fn has_bundled_rom() -> bool {
+  space_invaders_core::is_known_rom(INVADERS_ROM)
+}
+
+/// Files of a ROM dump chosen by the user: a single space invaders image, or the files of the MAME
+/// set of one of the supported games in any order.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct RomSet {
+  files: Vec<(String, Vec<u8>)>,
+}
+
+#[wasm_bindgen]
+impl RomSet {
+  pub fn new() -> RomSet {
+    RomSet::default()
+  }
+
+  pub fn add(&mut self, name: String, data: Vec<u8>) {
+    self.files.push((name, data));
+  }
+
+  /// Checks the files and returns the ROM image, to give to `App::new_with_game`.
+  pub fn build(&self) -> Result<Vec<u8>, JsError> {
+    Ok(space_invaders_core::identify_rom(&self.file_refs())?.1)
+  }
+
+  /// Short name of the game of the files.
+  pub fn game(&self) -> Result<String, JsError> {
+    Ok(space_invaders_core::identify_rom(&self.file_refs())?.0.name().to_string())
+  }
+
+  /// Colour PROM of the game of the files, if it has one and it was given (see
+  /// `App::set_color_prom`).
+  pub fn color_prom(&self) -> Option<Vec<u8>> {
+    let files = self.file_refs();
+    let (driver, _) = space_invaders_core::identify_rom(&files).ok()?;
+    space_invaders_core::find_color_prom(driver, &files).map(|prom| prom.to_vec())
+  }
 }
 
-fn memory_buffer_index_to_coordinates(index: usize) -> (u32, u32) {
-    let x = (index as u32 * 8) % PIXEL_HEIGHT;
-    let y = (index as u32 * 8) / PIXEL_HEIGHT;
-    (x, y)
-}
\ No newline at end of file
+impl RomSet {
+  fn file_refs(&self) -> Vec<(&str, &[u8])> {
+    self.files.iter().map(|(name, data)| (name.as_str(), data.as_slice())).collect()
+  }
+}
+
+fn rgba(rgb: u32) -> [u8; 4] {
+  [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255]
+}
+
+impl Default for App {
+  fn default() -> App {
+    App::new()
+  }
+}
+
+/// Tests of the handmade programs, which are not known dumps (see `App::new_with_game`)
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+  use wasm_bindgen_test::*;
+  use super::*;
+
+  #[wasm_bindgen_test]
+  fn faults_halt_the_machine() {
+    // IN 7, a port the board does not have, then loops
+    let mut rom = vec![0; 0x2000];
+    rom[0..5].copy_from_slice(&[0xdb, 0x07, 0xc3, 0x00, 0x00]);
+    let mut app = App::with_rom(&INVADERS, &rom);
+    assert!(app.set_fault_policy("panic").is_err());
+    app.set_fault_policy("halt").unwrap();
+    app.run(10);
+    assert!(app.is_halted());
+    assert_eq!("IN from unknown port 7 at 0x0000", app.take_faults());
+    assert_eq!("", app.take_faults());
+
+    app.set_fault_policy("log").unwrap();
+    app.resume();
+    app.run(10);
+    assert!(!app.is_halted());
+    assert!(app.take_faults().lines().count() > 1);
+  }
+
+  #[wasm_bindgen_test]
+  fn beam_sync_takes_each_half_at_its_interrupt() {
+    // enables the interrupts and loops, RST 1 lights the first and the last line of the video RAM
+    let mut rom = vec![0; 0x2000];
+    rom[0..4].copy_from_slice(&[0xfb, 0xc3, 0x01, 0x00]);
+    rom[0x08..0x10].copy_from_slice(&[0x3e, 0xff, 0x32, 0x00, 0x24, 0xc3, 0x18, 0x00]);
+    rom[0x10..0x12].copy_from_slice(&[0xfb, 0xc9]);
+    rom[0x18..0x1d].copy_from_slice(&[0x32, 0xe0, 0x3f, 0xfb, 0xc9]);
+    let lit = |app: &App, x: usize| {
+      let pixels = unsafe { std::slice::from_raw_parts(app.frame_ptr(), app.frame_len()) };
+      pixels[(255 * 224 + x) * 4] != 0
+    };
+
+    let mut app = App::with_rom(&INVADERS, &rom);
+    app.set_beam_sync(true);
+    app.run_frame();
+    assert!(app.update_frame());
+    // the beam had already scanned the first line when it was lit
+    assert!(!lit(&app, 0));
+    assert!(lit(&app, 223));
+    app.run_frame();
+    app.update_frame();
+    assert!(lit(&app, 0));
+
+    let mut app = App::with_rom(&INVADERS, &rom);
+    app.run_frame();
+    app.update_frame();
+    assert!(lit(&app, 0));
+  }
+}
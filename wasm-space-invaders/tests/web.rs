@@ -0,0 +1,217 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Headless tests, run under node with `wasm-pack test --node`.
+
+#![cfg(target_arch = "wasm32")]
+
+extern crate wasm_bindgen_test;
+extern crate wasm_space_invaders;
+
+use wasm_bindgen_test::*;
+use space_invaders_core::INVADERS_ROM;
+use wasm_space_invaders::{App, RomSet, has_bundled_rom};
+
+#[wasm_bindgen_test]
+fn produces_samples_for_the_emulated_duration() {
+  let mut app = App::new();
+  app.set_sample_rate(48000);
+  for _ in 0..100 {
+    app.run(10);
+  }
+  let samples = app.take_audio_samples();
+  // 1 second of emulation, give or take the last instruction of every run
+  assert!(samples.len() >= 48000 && samples.len() < 48100);
+  assert!(samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+  assert!(app.take_audio_samples().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn muted_app_is_silent() {
+  let mut app = App::new();
+  app.set_muted(true);
+  app.set_volume(2.0);
+  assert_eq!(app.volume(), 1.0);
+  for _ in 0..100 {
+    app.run(10);
+  }
+  assert!(app.take_audio_samples().iter().all(|s| *s == 0.0));
+}
+
+#[wasm_bindgen_test]
+fn frame_buffer_is_only_updated_when_vram_changes() {
+  let mut app = App::new();
+  assert_eq!(224 * 256 * 4, app.frame_len());
+  let ptr = app.frame_ptr();
+
+  for _ in 0..100 {
+    app.run(10);
+  }
+  assert!(app.update_frame());
+  assert!(!app.update_frame());
+  assert_eq!(ptr, app.frame_ptr());
+}
+
+#[wasm_bindgen_test]
+fn crt_filter_is_applied_once_per_frame() {
+  let mut app = App::new();
+  let ptr = app.frame_ptr();
+  app.set_crt(0.5, 0.3, 0.0, 0.0);
+  assert_ne!(ptr, app.frame_ptr());
+  assert_eq!(224 * 256 * 4, app.frame_len());
+  assert!(app.update_frame());
+  assert!(!app.update_frame());
+
+  app.run_frame();
+  assert!(app.update_frame());
+
+  app.set_crt(0.0, 0.0, 0.0, 0.0);
+  assert_eq!(ptr, app.frame_ptr());
+  assert!(app.update_frame());
+}
+
+#[wasm_bindgen_test]
+fn upscaler_changes_the_frame_size() {
+  let mut app = App::new();
+  app.set_upscaler("scale3x").unwrap();
+  assert!(app.update_frame());
+  assert_eq!((672, 768), (app.frame_width(), app.frame_height()));
+  assert_eq!(672 * 768 * 4, app.frame_len());
+
+  assert!(app.set_upscaler("hq4x").is_err());
+  app.set_upscaler("").unwrap();
+  assert_eq!(224 * 256 * 4, app.frame_len());
+}
+
+#[wasm_bindgen_test]
+fn screenshot_is_a_scaled_png() {
+  let mut app = App::new();
+  app.set_upscaler("scale3x").unwrap();
+  let png = app.screenshot_png(2);
+  assert_eq!(&[0x89, b'P', b'N', b'G'], &png[0..4]);
+  // the size in the header does not depend on the upscaler of the display
+  assert_eq!(&[0, 0, 1, 192, 0, 0, 2, 0], &png[16..24]);
+}
+
+#[wasm_bindgen_test]
+fn run_until_catches_up_a_limited_number_of_frames() {
+  let mut app = App::new();
+  app.set_sample_rate(48000);
+
+  // the first call only sets the origin
+  app.run_until(1000.0);
+  assert!(app.take_audio_samples().is_empty());
+
+  app.run_until(1050.0);
+  let samples = app.take_audio_samples().len();
+  assert!((2400..2410).contains(&samples), "{} samples", samples);
+
+  // after a long pause (e.g. hidden page), at most 10 frames are emulated
+  app.run_until(60_000.0);
+  let samples = app.take_audio_samples().len();
+  assert!((7990..8010).contains(&samples), "{} samples", samples);
+}
+
+#[wasm_bindgen_test]
+fn state_round_trip() {
+  let mut app = App::new();
+  app.set_high_score(1230);
+  for _ in 0..60 {
+    app.run_frame();
+  }
+  let state = app.save_state();
+  for _ in 0..60 {
+    app.run_frame();
+  }
+
+  let mut restored = App::new();
+  restored.load_state(&state).unwrap();
+  assert_eq!(1230, restored.high_score());
+  assert_eq!(state, restored.save_state());
+}
+
+#[wasm_bindgen_test]
+fn rom_from_split_files() {
+  if !has_bundled_rom() {
+    return;
+  }
+  assert!(App::new_with_rom(&[0; 0x2000]).is_err());
+  assert!(App::new_with_rom(INVADERS_ROM).is_ok());
+
+  let mut rom_set = RomSet::new();
+  for (name, offset) in [("invaders.f", 0x1000), ("invaders.h", 0), ("invaders.e", 0x1800)] {
+    rom_set.add(name.to_string(), INVADERS_ROM[offset..(offset + 0x800)].to_vec());
+  }
+  assert!(rom_set.build().is_err());
+  rom_set.add("invaders.g".to_string(), INVADERS_ROM[0x800..0x1000].to_vec());
+  assert_eq!(INVADERS_ROM, &rom_set.build().unwrap()[..]);
+  assert_eq!("invaders", rom_set.game().unwrap());
+  assert!(App::new_with_game("invaders", &rom_set.build().unwrap()).is_ok());
+  // the right size is not enough
+  assert!(App::new_with_game("invaders", &[0; 0x2000]).is_err());
+}
+
+#[wasm_bindgen_test]
+fn other_games_of_the_board_are_not_supported_yet() {
+  // named as the files of lunar rescue, which have no CRC32 to check them
+  let mut rom_set = RomSet::new();
+  for name in ["lrescue.1", "lrescue.2", "lrescue.3", "lrescue.4", "lrescue.5", "lrescue.6"] {
+    rom_set.add(name.to_string(), vec![0; 0x800]);
+  }
+  assert!(rom_set.game().is_err());
+  assert!(rom_set.build().is_err());
+  assert!(App::new_with_game("lrescue", &[0; 0x4800]).is_err());
+  assert!(App::new_with_game("pacman", INVADERS_ROM).is_err());
+}
+
+#[wasm_bindgen_test]
+fn cheats_are_applied_at_every_frame() {
+  let mut app = App::new();
+  assert_eq!("Infinite lives\nInvincibility\nSlow fleet", app.cheat_names());
+
+  // the high score, written by the game at boot
+  app.load_cheats("cheat high score\nwrite 0x20f4 0x50\nwrite 0x20f5 0x12").unwrap();
+  assert_eq!("high score", app.cheat_names());
+  assert!(app.toggle_cheat(0).unwrap());
+  assert!(app.is_cheat_enabled(0));
+  assert!(app.toggle_cheat(1).is_err());
+  for _ in 0..60 {
+    app.run_frame();
+  }
+  assert_eq!(1250, app.high_score());
+
+  let skipped = app.load_cheats(r#"<mamecheat><cheat desc="Level"><parameter min="1" max="9"/></cheat></mamecheat>"#).unwrap();
+  assert_eq!("Level", skipped);
+  assert_eq!("", app.cheat_names());
+  assert!(app.load_cheats("patch 0x0000 0x00").is_err());
+}
+
+#[wasm_bindgen_test]
+fn achievements_are_unlocked_once() {
+  let mut app = App::new();
+  assert_eq!("UFO hunter\nShield keeper\n5000 points", app.achievement_names());
+  assert_eq!("Reach 5000 points in a game of player 1", app.achievement_description(2));
+  assert!(app.unlock_achievement("5000 points"));
+  assert!(app.is_achievement_unlocked(2));
+  assert!(!app.unlock_achievement("Missing"));
+
+  // the high score, written by the game at boot, goes from 0 to 1250
+  app.load_achievements("achievement High score\nrequire bcd 0x20f4 > 1000").unwrap();
+  assert_eq!("High score", app.achievement_names());
+  app.load_cheats("cheat high score\nwrite 0x20f4 0x50\nwrite 0x20f5 0x12").unwrap();
+  app.toggle_cheat(0).unwrap();
+  for _ in 0..60 {
+    app.run_frame();
+  }
+  assert_eq!("", app.take_unlocked_achievements());
+  // checked again without cheats, from a score which does not hold
+  app.toggle_cheat(0).unwrap();
+  app.set_high_score(0);
+  app.run_frame();
+  app.set_high_score(1250);
+  app.run_frame();
+  assert_eq!("High score", app.take_unlocked_achievements());
+  assert_eq!("", app.take_unlocked_achievements());
+  assert!(app.load_achievements("require byte 0x2000 == 1").is_err());
+}
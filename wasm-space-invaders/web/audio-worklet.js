@@ -0,0 +1,22 @@
+import { RingBuffer } from "./ring-buffer.js";
+
+class SpaceInvadersProcessor extends AudioWorkletProcessor {
+  constructor(options) {
+    super()
+    const { buffers, capacity } = options.processorOptions
+    this.ring = buffers ? new RingBuffer(buffers) : RingBuffer.allocate(capacity, false)
+    // only used when the ring buffer cannot be shared with the main thread
+    this.port.onmessage = (e) => this.ring.push(e.data)
+  }
+
+  process(inputs, outputs) {
+    const channels = outputs[0]
+    this.ring.pull(channels[0])
+    for (let i = 1; i < channels.length; i++) {
+      channels[i].set(channels[0])
+    }
+    return true
+  }
+}
+
+registerProcessor("space-invaders-audio", SpaceInvadersProcessor)
@@ -0,0 +1,49 @@
+import { RingBuffer } from "./ring-buffer.js";
+
+// A bit more than what the emulation produces between two ticks, the rest is dropped to keep the
+// latency low.
+const BUFFERED_SECONDS = 0.15
+
+// Browsers only allow to start an `AudioContext` after a user interaction, so `push` just drops
+// the samples until `start` is done.
+export class Audio {
+  constructor(app) {
+    this.app = app
+    this.ring = null
+    this.port = null
+  }
+
+  async start() {
+    if (this.context) {
+      return
+    }
+    this.context = new AudioContext()
+    await this.context.audioWorklet.addModule("./audio-worklet.js")
+
+    const capacity = Math.ceil(this.context.sampleRate * BUFFERED_SECONDS)
+    const shared = self.crossOriginIsolated === true
+    const processorOptions = { capacity }
+    if (shared) {
+      this.ring = RingBuffer.allocate(capacity, true)
+      processorOptions.buffers = this.ring.buffers
+    }
+    const node = new AudioWorkletNode(this.context, "space-invaders-audio", {
+      numberOfInputs: 0,
+      outputChannelCount: [1],
+      processorOptions,
+    })
+    if (!shared) {
+      this.port = node.port
+    }
+    node.connect(this.context.destination)
+    this.app.set_sample_rate(this.context.sampleRate)
+  }
+
+  push(samples) {
+    if (this.ring) {
+      this.ring.push(samples)
+    } else if (this.port) {
+      this.port.postMessage(samples, [samples.buffer])
+    }
+  }
+}
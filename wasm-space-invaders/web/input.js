@@ -0,0 +1,113 @@
+import { Action } from "./pkg/wasm_space_invaders.js";
+
+const GAMEPAD_DEAD_ZONE = 0.4
+
+// Buttons of the "standard" gamepad mapping
+// (https://w3c.github.io/gamepad/#remapping)
+const GAMEPAD_BUTTONS = [
+  [0, Action.Fire],   // bottom face button
+  [1, Action.Fire],   // right face button
+  [8, Action.Coin],   // select / back
+  [9, Action.P1Start], // start
+  [14, Action.Left],  // d-pad left
+  [15, Action.Right], // d-pad right
+]
+
+// An action can be held by several devices at the same time (e.g. the arrow key and the d-pad).
+// The action is only released on the emulator when no device holds it anymore.
+export class InputState {
+  constructor(app) {
+    this.app = app
+    this.sources = new Map()
+  }
+
+  set(source, action, pressed) {
+    const key = `${source}/${action}`
+    if (pressed === this.sources.has(key)) {
+      return
+    }
+    if (pressed) {
+      this.sources.set(key, action)
+    } else {
+      this.sources.delete(key)
+    }
+    const held = [...this.sources.values()].includes(action)
+    this.app.set_action(action, held)
+  }
+
+  setAll(source, actions) {
+    Object.values(Action)
+      .filter((action) => typeof action === "number")
+      .forEach((action) => this.set(source, action, actions.has(action)))
+  }
+}
+
+// `bindings` maps `KeyboardEvent.code` to the name of an `Action`. It is read on every event, so it
+// can be changed afterwards.
+export const bindKeyboard = (input, bindings) => {
+  const handleKey = (pressed) => (e) => {
+    const action = Action[bindings[e.code]]
+    if (action === undefined) {
+      return
+    }
+    e.preventDefault()
+    if (!e.repeat) {
+      input.set("keyboard", action, pressed)
+    }
+  }
+  document.addEventListener('keydown', handleKey(true))
+  document.addEventListener('keyup', handleKey(false))
+}
+
+// The Gamepad API has no events for buttons, so it has to be polled (once per frame is enough).
+export const pollGamepads = (input) => {
+  const gamepads = navigator.getGamepads ? navigator.getGamepads() : []
+  for (const gamepad of gamepads) {
+    if (!gamepad) {
+      continue
+    }
+    const actions = new Set()
+    GAMEPAD_BUTTONS.forEach(([index, action]) => {
+      if (gamepad.buttons[index] && gamepad.buttons[index].pressed) {
+        actions.add(action)
+      }
+    })
+    const xAxis = gamepad.axes[0] || 0
+    if (xAxis < -GAMEPAD_DEAD_ZONE) {
+      actions.add(Action.Left)
+    } else if (xAxis > GAMEPAD_DEAD_ZONE) {
+      actions.add(Action.Right)
+    }
+    input.setAll(`gamepad-${gamepad.index}`, actions)
+  }
+}
+
+// Every finger is tracked independently so that the player can move and fire at the same time,
+// and slide from one button to the other.
+export const bindTouchOverlay = (input, overlay) => {
+  const fingers = new Map()
+
+  const update = (e) => {
+    e.preventDefault()
+    for (const touch of e.changedTouches) {
+      const element = document.elementFromPoint(touch.clientX, touch.clientY)
+      const button = element && element.closest("[data-action]")
+      if (e.type === "touchend" || e.type === "touchcancel" || !button) {
+        fingers.delete(touch.identifier)
+      } else {
+        fingers.set(touch.identifier, button)
+      }
+    }
+    const pressedButtons = new Set(fingers.values())
+    overlay.querySelectorAll("[data-action]").forEach((button) => {
+      button.classList.toggle("pressed", pressedButtons.has(button))
+    })
+    input.setAll("touch", new Set([...pressedButtons].map((button) => Action[button.dataset.action])))
+  }
+
+  window.addEventListener("touchstart", () => overlay.classList.add("visible"), { once: true })
+  overlay.addEventListener("touchstart", update, { passive: false })
+  overlay.addEventListener("touchmove", update, { passive: false })
+  overlay.addEventListener("touchend", update, { passive: false })
+  overlay.addEventListener("touchcancel", update, { passive: false })
+}
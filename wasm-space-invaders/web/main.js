@@ -1,41 +1,139 @@
-import init, { App } from "./pkg/wasm_space_invaders.js";
-init().then(() => {
-  const app = App.new()
+import init, { App, has_bundled_rom } from "./pkg/wasm_space_invaders.js";
+import { InputState, bindKeyboard, bindTouchOverlay, pollGamepads } from "./input.js";
+import { Audio } from "./audio.js";
+import { applySettings, bindAchievementsUi, bindCheatsUi, bindSettingsUi, bindStatesUi } from "./settings.js";
+import { bindRomFiles, forgetRom, loadStoredRom, storeRom } from "./rom.js";
+import { loadHighScore, loadSettings, saveHighScore } from "./storage.js";
+
+const romPicker = document.getElementById("rom-picker")
+
+// Without a ROM, the picker stays open until the user gives valid files. Afterwards it can be
+// opened again to change the ROM, which reloads the page.
+let onRom = async (rom) => {
+  await storeRom(rom)
+  location.reload()
+}
+
+const newGame = ({ game, rom, colorProm }) => {
+  const app = App.new_with_game(game, rom)
+  if (colorProm) {
+    app.set_color_prom(colorProm)
+  }
+  return app
+}
+
+const newApp = async () => {
+  const stored = await loadStoredRom()
+  if (stored) {
+    try {
+      return newGame(stored)
+    } catch (e) {
+      await forgetRom()
+    }
+  }
+  if (has_bundled_rom()) {
+    return App.new()
+  }
+  romPicker.classList.add("visible", "required")
+  const changeRom = onRom
+  const rom = await new Promise((resolve) => onRom = resolve)
+  onRom = changeRom
+  await storeRom(rom)
+  romPicker.classList.remove("visible", "required")
+  return newGame(rom)
+}
+
+init().then(async (wasm) => {
+  bindRomFiles(document.body, document.getElementById("rom-input"), document.getElementById("rom-error"),
+    (rom) => onRom(rom))
+  document.getElementById("change-rom").addEventListener("click", () => romPicker.classList.add("visible"))
+  document.getElementById("close-rom-picker").addEventListener("click", () => romPicker.classList.remove("visible"))
+
+  const app = await newApp()
+  document.title = app.title()
   const canvas = document.getElementById("space-invaders-canvas")
   const ctx = canvas.getContext('2d')
 
-  const renderLoop = () => {
-    app.draw(ctx)
-    requestAnimationFrame(renderLoop)
-    ctx.stroke();
+  // The image data is a view on the frame buffer of the app, in the wasm memory. It only has to be
+  // created again if the memory grows (which detaches the previous `ArrayBuffer`) or if the buffer
+  // changes (the crt filter and the upscaler have their own, the upscaler also changes the size).
+  let imageData = null
+  const frameImageData = () => {
+    if (!imageData || imageData.data.buffer !== wasm.memory.buffer
+      || imageData.data.byteOffset !== app.frame_ptr() || imageData.data.length !== app.frame_len()) {
+      const pixels = new Uint8ClampedArray(wasm.memory.buffer, app.frame_ptr(), app.frame_len())
+      imageData = new ImageData(pixels, app.frame_width(), app.frame_height())
+      canvas.width = imageData.width
+      canvas.height = imageData.height
+    }
+    return imageData
   }
 
-  let lastRun = performance.now()
-  const tick = () => {
-    const currentTime = performance.now()
-    let duration = currentTime - lastRun
-    while(duration > 0) {
-      // Run the simulation for only a bit less than half a frame
-      // Otherwise we might miss some interrupts
-      const simulationTime = duration % 7 || 7;
-      app.run(simulationTime)
-      duration -= simulationTime;
+  const settings = loadSettings()
+  applySettings(app, settings)
+  let highScore = loadHighScore()
+  app.set_high_score(highScore)
+
+  const input = new InputState(app)
+  const audio = new Audio(app)
+
+  // The faults (accesses to ports the board does not have) are logged in the console, the last one
+  // is shown under the settings
+  const faultStatus = document.getElementById("fault-status")
+  const showFaults = () => {
+    const faults = app.take_faults()
+    if (faults) {
+      console.warn(faults)
+      faultStatus.textContent = faults.substring(faults.lastIndexOf("\n") + 1)
     }
-    lastRun = currentTime
+    faultStatus.classList.toggle("halted", app.is_halted())
   }
+  const showAchievements = bindAchievementsUi(app)
 
-  const handleKeyDown = (e) => {
-    if(!e.repeat) {
-      app.handle_key_down(e.code)
+  const renderLoop = (timestamp) => {
+    pollGamepads(input)
+    app.run_until(timestamp)
+    audio.push(app.take_audio_samples())
+    if (app.update_frame()) {
+      ctx.putImageData(frameImageData(), 0, 0)
     }
+    showFaults()
+    showAchievements()
+    if (app.high_score() > highScore) {
+      highScore = app.high_score()
+      saveHighScore(highScore)
+    }
+    requestAnimationFrame(renderLoop)
   }
 
-  const handleKeyUp = (e) => {
-    app.handle_key_up(e.code)
+  const volume = document.getElementById("volume")
+  const mute = document.getElementById("mute")
+  const updateMuteButton = () => {
+    mute.textContent = app.is_muted() ? "unmute" : "mute"
   }
+  volume.addEventListener("input", () => app.set_volume(volume.valueAsNumber))
+  mute.addEventListener("click", () => {
+    app.set_muted(!app.is_muted())
+    updateMuteButton()
+  })
+  document.addEventListener("keydown", (e) => {
+    if (e.code === "KeyM" && !e.repeat) {
+      mute.click()
+    }
+  })
+  updateMuteButton()
+
+  // Audio can only start after a user gesture
+  const startAudio = () => audio.start()
+  document.addEventListener("keydown", startAudio, { once: true })
+  document.addEventListener("pointerdown", startAudio, { once: true })
+  document.addEventListener("touchstart", startAudio, { once: true })
 
+  ctx.putImageData(frameImageData(), 0, 0)
   requestAnimationFrame(renderLoop)
-  setInterval(tick, 1)
-  document.addEventListener('keydown', handleKeyDown)
-  document.addEventListener('keyup', handleKeyUp)
+  bindKeyboard(input, settings.keyBindings)
+  bindSettingsUi(app, settings)
+  bindStatesUi(app)
+  bindCheatsUi(app)
+  bindTouchOverlay(input, document.getElementById("touch-controls"))
 });
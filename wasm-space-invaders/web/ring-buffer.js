@@ -0,0 +1,55 @@
+// Single producer / single consumer ring buffer of audio samples.
+//
+// When the page is cross origin isolated, the buffers are `SharedArrayBuffer`s shared between the
+// main thread (producer) and the audio worklet (consumer). Otherwise each side has its own copy and
+// samples are sent through the worklet message port.
+export class RingBuffer {
+  static allocate(capacity, shared) {
+    const BufferType = shared ? SharedArrayBuffer : ArrayBuffer
+    return new RingBuffer({
+      indexes: new BufferType(2 * Int32Array.BYTES_PER_ELEMENT),
+      samples: new BufferType(capacity * Float32Array.BYTES_PER_ELEMENT),
+    })
+  }
+
+  constructor(buffers) {
+    this.buffers = buffers
+    this.indexes = new Int32Array(buffers.indexes)
+    this.samples = new Float32Array(buffers.samples)
+    this.capacity = this.samples.length
+  }
+
+  available() {
+    const read = Atomics.load(this.indexes, 0)
+    const write = Atomics.load(this.indexes, 1)
+    return (write - read + this.capacity) % this.capacity
+  }
+
+  // Returns the number of samples written, samples which do not fit are dropped.
+  push(samples) {
+    const read = Atomics.load(this.indexes, 0)
+    let write = Atomics.load(this.indexes, 1)
+    const free = (read - write - 1 + this.capacity) % this.capacity
+    const count = Math.min(free, samples.length)
+    for (let i = 0; i < count; i++) {
+      this.samples[write] = samples[i]
+      write = (write + 1) % this.capacity
+    }
+    Atomics.store(this.indexes, 1, write)
+    return count
+  }
+
+  // Fills `output` and returns the number of samples read, the rest is filled with silence.
+  pull(output) {
+    let read = Atomics.load(this.indexes, 0)
+    const write = Atomics.load(this.indexes, 1)
+    const count = Math.min(output.length, (write - read + this.capacity) % this.capacity)
+    for (let i = 0; i < count; i++) {
+      output[i] = this.samples[read]
+      read = (read + 1) % this.capacity
+    }
+    output.fill(0, count)
+    Atomics.store(this.indexes, 0, read)
+    return count
+  }
+}
@@ -0,0 +1,77 @@
+import { RomSet } from "./pkg/wasm_space_invaders.js";
+
+// The ROM chosen by the user is kept in IndexedDB (it is binary and does not belong with the
+// settings in the localStorage), as `{ game, rom, colorProm }` with the short name of the game and
+// its colour PROM (undefined for the black and white games, or if it was not given).
+const DB_NAME = "space-invaders"
+const STORE = "roms"
+const KEY = "invaders"
+
+const openDb = () => new Promise((resolve, reject) => {
+  const request = indexedDB.open(DB_NAME, 1)
+  request.onupgradeneeded = () => request.result.createObjectStore(STORE)
+  request.onsuccess = () => resolve(request.result)
+  request.onerror = () => reject(request.error)
+})
+
+const withStore = async (mode, operation) => {
+  const db = await openDb()
+  return new Promise((resolve, reject) => {
+    const transaction = db.transaction(STORE, mode)
+    const request = operation(transaction.objectStore(STORE))
+    transaction.oncomplete = () => resolve(request.result)
+    transaction.onerror = () => reject(transaction.error)
+  })
+}
+
+// Before the other games were supported, only the space invaders image was stored
+export const loadStoredRom = () => withStore("readonly", (store) => store.get(KEY))
+  .then((stored) => stored instanceof Uint8Array ? { game: "invaders", rom: stored } : stored)
+  .catch(() => undefined)
+
+export const storeRom = (rom) => withStore("readwrite", (store) => store.put(rom, KEY))
+
+export const forgetRom = () => withStore("readwrite", (store) => store.delete(KEY))
+
+// Returns `{ game, rom, colorProm }`, throws an error with a message for the user if the files are not a
+// known dump
+export const buildRom = async (files) => {
+  const romSet = RomSet.new()
+  try {
+    for (const file of files) {
+      romSet.add(file.name, new Uint8Array(await file.arrayBuffer()))
+    }
+    return { game: romSet.game(), rom: romSet.build(), colorProm: romSet.color_prom() }
+  } finally {
+    romSet.free()
+  }
+}
+
+// Calls `onRom` with the game, its image and its colour PROM when valid files are dropped on `target` or chosen with `input`,
+// and shows the errors in `errorElement`.
+export const bindRomFiles = (target, input, errorElement, onRom) => {
+  const tryFiles = async (files) => {
+    try {
+      const rom = await buildRom(files)
+      errorElement.textContent = ""
+      onRom(rom)
+    } catch (e) {
+      errorElement.textContent = e.message
+    }
+  }
+
+  input.addEventListener("change", () => {
+    tryFiles([...input.files])
+    input.value = ""
+  })
+  target.addEventListener("dragover", (e) => {
+    e.preventDefault()
+    document.body.classList.add("dragging")
+  })
+  target.addEventListener("dragleave", () => document.body.classList.remove("dragging"))
+  target.addEventListener("drop", (e) => {
+    e.preventDefault()
+    document.body.classList.remove("dragging")
+    tryFiles([...e.dataTransfer.files])
+  })
+}
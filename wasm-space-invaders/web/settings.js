@@ -0,0 +1,300 @@
+import {
+  AUTOSAVE_SLOT, deleteSlot, download, listSlots, loadAchievements, loadSlot, readFile, saveAchievements,
+  saveSettings, saveSlot,
+} from "./storage.js";
+
+const ACTIONS = ["Coin", "P1Start", "P2Start", "Fire", "Left", "Right"]
+
+const hexToRgb = (hex) => parseInt(hex.substring(1), 16)
+
+export const applySettings = (app, settings) => {
+  const { lives, extraLifeAt1000, coinInfo } = settings.dipSwitches
+  app.set_dip_switches(lives, extraLifeAt1000, coinInfo)
+  app.set_palette(hexToRgb(settings.palette.foreground), hexToRgb(settings.palette.background))
+  const { phosphorDecay, scanlines, glow, curvature } = settings.crt
+  app.set_crt(phosphorDecay, scanlines, glow, curvature)
+  app.set_upscaler(settings.upscaler)
+  app.set_overlay(settings.overlay)
+  app.set_beam_sync(settings.beamSync)
+  app.set_fault_policy(settings.faultPolicy)
+}
+
+// "YYYYMMDD-HHMMSS" in UTC, like the screenshots of the native versions
+const timestamp = () => new Date().toISOString().replace(/[-:]/g, "").replace("T", "-").substring(0, 15)
+
+export const bindSettingsUi = (app, settings) => {
+  const changed = () => {
+    applySettings(app, settings)
+    saveSettings(settings)
+  }
+
+  const lives = document.getElementById("lives")
+  const extraLife = document.getElementById("extra-life")
+  const coinInfo = document.getElementById("coin-info")
+  lives.value = settings.dipSwitches.lives
+  extraLife.value = settings.dipSwitches.extraLifeAt1000 ? "1000" : "1500"
+  coinInfo.checked = settings.dipSwitches.coinInfo
+  lives.addEventListener("change", () => {
+    settings.dipSwitches.lives = parseInt(lives.value)
+    changed()
+  })
+  extraLife.addEventListener("change", () => {
+    settings.dipSwitches.extraLifeAt1000 = extraLife.value === "1000"
+    changed()
+  })
+  coinInfo.addEventListener("change", () => {
+    settings.dipSwitches.coinInfo = coinInfo.checked
+    changed()
+  })
+
+  for (const name of ["foreground", "background"]) {
+    const input = document.getElementById(name)
+    input.value = settings.palette[name]
+    input.addEventListener("input", () => {
+      settings.palette[name] = input.value
+      changed()
+    })
+  }
+
+  for (const name of ["phosphorDecay", "scanlines", "glow", "curvature"]) {
+    const input = document.getElementById(`crt-${name}`)
+    input.value = settings.crt[name]
+    input.addEventListener("input", () => {
+      settings.crt[name] = input.valueAsNumber
+      changed()
+    })
+  }
+
+  const upscaler = document.getElementById("upscaler")
+  upscaler.value = settings.upscaler
+  upscaler.addEventListener("change", () => {
+    settings.upscaler = upscaler.value
+    changed()
+  })
+
+  const overlay = document.getElementById("overlay")
+  overlay.checked = settings.overlay
+  overlay.addEventListener("change", () => {
+    settings.overlay = overlay.checked
+    changed()
+  })
+
+  const beamSync = document.getElementById("beam-sync")
+  beamSync.checked = settings.beamSync
+  beamSync.addEventListener("change", () => {
+    settings.beamSync = beamSync.checked
+    changed()
+  })
+
+  const screenshotScale = document.getElementById("screenshot-scale")
+  screenshotScale.value = settings.screenshotScale
+  screenshotScale.addEventListener("change", () => {
+    settings.screenshotScale = parseInt(screenshotScale.value)
+    saveSettings(settings)
+  })
+  document.getElementById("screenshot").addEventListener("click", () => {
+    download(app.screenshot_png(settings.screenshotScale), `space-invaders-${timestamp()}.png`, "image/png")
+  })
+
+  const faultPolicy = document.getElementById("fault-policy")
+  faultPolicy.value = settings.faultPolicy
+  faultPolicy.addEventListener("change", () => {
+    settings.faultPolicy = faultPolicy.value
+    changed()
+  })
+  document.getElementById("resume").addEventListener("click", () => app.resume())
+
+  bindKeyBindingsUi(settings.keyBindings, () => saveSettings(settings))
+}
+
+// One button per action, showing its key. Clicking it waits for the next key, which replaces the
+// previous one.
+const bindKeyBindingsUi = (keyBindings, changed) => {
+  const container = document.getElementById("key-bindings")
+  let waitingFor = null
+
+  const render = () => {
+    container.replaceChildren(...ACTIONS.map((action) => {
+      const button = document.createElement("button")
+      const key = Object.keys(keyBindings).find((code) => keyBindings[code] === action)
+      button.textContent = `${action}: ${action === waitingFor ? "press a key" : key || "none"}`
+      button.addEventListener("click", () => {
+        waitingFor = action
+        render()
+      })
+      return button
+    }))
+  }
+
+  // Registered on window in the capture phase, to run before (and stop) the game inputs
+  window.addEventListener("keydown", (e) => {
+    if (waitingFor === null) {
+      return
+    }
+    e.preventDefault()
+    e.stopPropagation()
+    if (e.code !== "Escape") {
+      for (const code of Object.keys(keyBindings)) {
+        if (keyBindings[code] === waitingFor) {
+          delete keyBindings[code]
+        }
+      }
+      keyBindings[e.code] = waitingFor
+      changed()
+    }
+    waitingFor = null
+    render()
+  }, { capture: true })
+
+  render()
+}
+
+// One checkbox per cheat. A cheat file (or a MAME cheat.xml) replaces the cheats of the game, the
+// keys 1 to 9 toggle the first nine.
+export const bindCheatsUi = (app) => {
+  const container = document.getElementById("cheats")
+  const status = document.getElementById("cheat-status")
+  const fileInput = document.getElementById("cheats-file")
+
+  const render = () => {
+    const names = app.cheat_names()
+    container.replaceChildren(...(names ? names.split("\n") : []).map((name, index) => {
+      const label = document.createElement("label")
+      const checkbox = document.createElement("input")
+      checkbox.type = "checkbox"
+      checkbox.checked = app.is_cheat_enabled(index)
+      checkbox.addEventListener("change", () => {
+        app.toggle_cheat(index)
+        render()
+      })
+      label.append(checkbox, `${index + 1} ${name} `)
+      return label
+    }))
+  }
+
+  document.addEventListener("keydown", (e) => {
+    const index = e.code.startsWith("Digit") ? parseInt(e.code.substring(5)) - 1 : -1
+    if (index >= 0 && index < 9 && !e.repeat) {
+      try {
+        app.toggle_cheat(index)
+        render()
+      } catch {
+        // there are fewer cheats
+      }
+    }
+  })
+
+  document.getElementById("load-cheats").addEventListener("click", () => fileInput.click())
+  fileInput.addEventListener("change", async () => {
+    const file = fileInput.files[0]
+    if (file) {
+      try {
+        const skipped = app.load_cheats(await file.text())
+        status.textContent = skipped ? `could not import ${skipped.split("\n").join(", ")}` : `loaded ${file.name}`
+      } catch (e) {
+        status.textContent = `could not load ${file.name}: ${e.message}`
+      }
+      render()
+    }
+    fileInput.value = ""
+  })
+
+  render()
+}
+
+// The achievements of the game, the unlocked ones ticked. Those unlocked in earlier sessions come
+// from the localStorage, and the new ones are added to it. Returns the function to call at every
+// frame, which shows the new ones.
+export const bindAchievementsUi = (app) => {
+  const container = document.getElementById("achievements")
+  const status = document.getElementById("achievement-status")
+  const unlocked = loadAchievements(app.title())
+  unlocked.forEach((name) => app.unlock_achievement(name))
+
+  const render = () => {
+    const names = app.achievement_names()
+    container.replaceChildren(...(names ? names.split("\n") : []).map((name, index) => {
+      const span = document.createElement("span")
+      span.title = app.achievement_description(index)
+      span.textContent = `${app.is_achievement_unlocked(index) ? "\u2611" : "\u2610"} ${name} `
+      return span
+    }))
+  }
+  render()
+
+  return () => {
+    const names = app.take_unlocked_achievements()
+    if (names) {
+      for (const name of names.split("\n")) {
+        console.log(`achievement unlocked: ${name}`)
+        unlocked.push(name)
+      }
+      saveAchievements(app.title(), unlocked)
+      status.textContent = `unlocked ${names.split("\n").join(", ")}`
+      render()
+    }
+  }
+}
+
+export const bindStatesUi = (app) => {
+  const slotName = document.getElementById("slot-name")
+  const slots = document.getElementById("slots")
+  const status = document.getElementById("state-status")
+  const fileInput = document.getElementById("state-file")
+
+  const refreshSlots = () => {
+    slots.replaceChildren(...listSlots().map((name) => {
+      const option = document.createElement("option")
+      option.value = name
+      return option
+    }))
+  }
+
+  const load = (state, origin) => {
+    try {
+      app.load_state(state)
+      status.textContent = `loaded ${origin}`
+    } catch (e) {
+      status.textContent = `could not load ${origin}: ${e.message}`
+    }
+  }
+
+  document.getElementById("save-slot").addEventListener("click", () => {
+    saveSlot(slotName.value, app.save_state())
+    status.textContent = `saved ${slotName.value}`
+    refreshSlots()
+  })
+  document.getElementById("load-slot").addEventListener("click", () => {
+    const state = loadSlot(slotName.value)
+    if (state === null) {
+      status.textContent = `no state named ${slotName.value}`
+    } else {
+      load(state, slotName.value)
+    }
+  })
+  document.getElementById("delete-slot").addEventListener("click", () => {
+    deleteSlot(slotName.value)
+    status.textContent = `deleted ${slotName.value}`
+    refreshSlots()
+  })
+  document.getElementById("download-state").addEventListener("click", () => {
+    download(app.save_state(), `${slotName.value || "space-invaders"}.state`)
+  })
+  document.getElementById("upload-state").addEventListener("click", () => fileInput.click())
+  fileInput.addEventListener("change", async () => {
+    const file = fileInput.files[0]
+    if (file) {
+      load(await readFile(file), file.name)
+    }
+    fileInput.value = ""
+  })
+
+  // The page can be closed at any time, the emulation goes on from there on the next visit
+  window.addEventListener("pagehide", () => saveSlot(AUTOSAVE_SLOT, app.save_state()))
+  const autosave = loadSlot(AUTOSAVE_SLOT)
+  if (autosave !== null) {
+    load(autosave, AUTOSAVE_SLOT)
+  }
+
+  refreshSlots()
+}
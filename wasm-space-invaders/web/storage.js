@@ -0,0 +1,108 @@
+// Everything is kept in the localStorage: the states are small (~8 KiB) and it is synchronous,
+// which matters when saving from `pagehide`.
+
+const PREFIX = "space-invaders/"
+const SLOT_PREFIX = `${PREFIX}slot/`
+
+export const AUTOSAVE_SLOT = "autosave"
+
+export const DEFAULT_SETTINGS = {
+  // KeyboardEvent.code -> name of the `Action`
+  keyBindings: {
+    "Enter": "Coin",
+    "ControlLeft": "P1Start",
+    "ControlRight": "P2Start",
+    "Space": "Fire",
+    "ArrowLeft": "Left",
+    "ArrowRight": "Right",
+  },
+  dipSwitches: { lives: 3, extraLifeAt1000: false, coinInfo: true },
+  palette: { foreground: "#ffffff", background: "#000000" },
+  // CRT post-processing, every effect between 0 and 1 (0 disables it)
+  crt: { phosphorDecay: 0, scanlines: 0, glow: 0, curvature: 0 },
+  // name of the pixel-art upscaler, "" for none
+  upscaler: "",
+  // colours of the cellophane strips of the cabinet
+  overlay: false,
+  // each half of the screen is taken when the beam scans it, against the tearing
+  beamSync: false,
+  // size of the pixels of the screenshots
+  screenshotScale: 1,
+  // access to a port the board does not have: "ignore", "log" or "halt"
+  faultPolicy: "log",
+}
+
+const read = (key) => {
+  try {
+    return JSON.parse(localStorage.getItem(PREFIX + key))
+  } catch (e) {
+    return null
+  }
+}
+
+const write = (key, value) => localStorage.setItem(PREFIX + key, JSON.stringify(value))
+
+export const loadSettings = () => {
+  const stored = read("settings") || {}
+  return {
+    keyBindings: { ...(stored.keyBindings || DEFAULT_SETTINGS.keyBindings) },
+    dipSwitches: { ...DEFAULT_SETTINGS.dipSwitches, ...stored.dipSwitches },
+    palette: { ...DEFAULT_SETTINGS.palette, ...stored.palette },
+    crt: { ...DEFAULT_SETTINGS.crt, ...stored.crt },
+    upscaler: stored.upscaler || DEFAULT_SETTINGS.upscaler,
+    overlay: stored.overlay || DEFAULT_SETTINGS.overlay,
+    beamSync: stored.beamSync || DEFAULT_SETTINGS.beamSync,
+    screenshotScale: stored.screenshotScale || DEFAULT_SETTINGS.screenshotScale,
+    faultPolicy: stored.faultPolicy || DEFAULT_SETTINGS.faultPolicy,
+  }
+}
+
+export const saveSettings = (settings) => write("settings", settings)
+
+export const loadHighScore = () => read("high-score") || 0
+
+export const saveHighScore = (score) => write("high-score", score)
+
+// names of the achievements unlocked, by title of the game
+export const loadAchievements = (game) => (read("achievements") || {})[game] || []
+
+export const saveAchievements = (game, names) => write("achievements", { ...read("achievements"), [game]: names })
+
+const toBase64 = (bytes) => {
+  let binary = ""
+  bytes.forEach((byte) => binary += String.fromCharCode(byte))
+  return btoa(binary)
+}
+
+const fromBase64 = (text) => Uint8Array.from(atob(text), (c) => c.charCodeAt(0))
+
+export const listSlots = () => {
+  const slots = []
+  for (let i = 0; i < localStorage.length; i++) {
+    const key = localStorage.key(i)
+    if (key.startsWith(SLOT_PREFIX)) {
+      slots.push(key.substring(SLOT_PREFIX.length))
+    }
+  }
+  return slots.sort()
+}
+
+export const saveSlot = (name, state) => localStorage.setItem(SLOT_PREFIX + name, toBase64(state))
+
+export const loadSlot = (name) => {
+  const stored = localStorage.getItem(SLOT_PREFIX + name)
+  return stored === null ? null : fromBase64(stored)
+}
+
+export const deleteSlot = (name) => localStorage.removeItem(SLOT_PREFIX + name)
+
+export const download = (bytes, fileName, type = "application/octet-stream") => {
+  const url = URL.createObjectURL(new Blob([bytes], { type }))
+  const link = document.createElement("a")
+  link.href = url
+  link.download = fileName
+  link.click()
+  URL.revokeObjectURL(url)
+}
+
+export const readFile = async (file) => new Uint8Array(await file.arrayBuffer())